@@ -0,0 +1,34 @@
+//! Meshes on the CPU with `marching_cubes::mesh`, which runs on any machine.
+//! `cargo run --release -- bench` times the compute shaders on the GPU instead.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nuage::marching_cubes;
+
+/// Blobs of roughly the size the clouds have, so most voxels are empty and some hold a few
+/// triangles, without needing the noise from the shaders.
+fn density(voxels_per_dim: u32) -> impl Fn([u32; 3]) -> f32 {
+    let scale = std::f32::consts::TAU * 2.0 / voxels_per_dim as f32;
+    move |corner| {
+        let [x, y, z] = corner.map(|c| c as f32 * scale);
+        0.5 + 0.25 * (x.sin() * y.cos() + y.sin() * z.cos() + z.sin() * x.cos())
+    }
+}
+
+fn mesh(c: &mut Criterion) {
+    let mut group = c.benchmark_group("marching_cubes");
+    for voxels_per_dim in [16_u32, 32, 50] {
+        group.throughput(Throughput::Elements(voxels_per_dim.pow(3) as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(voxels_per_dim),
+            &voxels_per_dim,
+            |b, &voxels_per_dim| {
+                let density = density(voxels_per_dim);
+                b.iter(|| marching_cubes::mesh(voxels_per_dim, 0.5, &density))
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, mesh);
+criterion_main!(benches);
@@ -0,0 +1,414 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, ensure, Context as _};
+use log::info;
+
+use crate::{
+    chunks::{Chunks, ChunksConfig, Mesher, CHUNK_COUNT, INDIRECT_DRAW_STRIDE, VERTICES_PER_VOXEL},
+    graphics::Graphics,
+    noise::Noise,
+    profiler::GpuPass,
+};
+
+// Frames run before timing each combination, while the driver settles
+const WARMUP_FRAMES: u32 = 3;
+// Two timestamps around each of the density and meshing passes
+const QUERIES_PER_CHUNK: u32 = 4;
+const QUERY_SIZE: u64 = std::mem::size_of::<u64>() as u64;
+// Timestamps are split into sets of this many, as Metal can't sample more than 4096 into one
+// counter sample buffer. Each chunk's queries stay within a set.
+const QUERIES_PER_SET: u32 = 4096;
+const _: () = assert!(QUERIES_PER_SET.is_multiple_of(QUERIES_PER_CHUNK));
+
+/// Which resolutions and chunk counts to time density and marching cubes at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchOptions {
+    /// Voxels along each side of a chunk.
+    pub voxels_per_chunk_dim: Vec<u32>,
+    /// Chunks meshed each frame.
+    pub chunks: Vec<u32>,
+    /// Frames timed for each combination.
+    pub frames: u32,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            voxels_per_chunk_dim: vec![32, 50, 64],
+            chunks: vec![CHUNK_COUNT],
+            frames: 60,
+        }
+    }
+}
+
+pub const USAGE: &str = "\
+Usage: nuage bench [options]
+  --dims <list>     Voxels along each side of a chunk, comma separated [default: 32,50,64]
+  --chunks <list>   Chunks meshed per frame, comma separated [default: 8]
+  --frames <count>  Frames timed for each combination [default: 60]";
+
+impl BenchOptions {
+    /// Reads the options following the `bench` subcommand.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .with_context(|| format!("{} needs a value\n{}", flag, USAGE))?;
+            match flag.as_str() {
+                "--dims" => options.voxels_per_chunk_dim = parse_list(&flag, &value)?,
+                "--chunks" => options.chunks = parse_list(&flag, &value)?,
+                "--frames" => options.frames = value.parse().context("--frames")?,
+                _ => bail!("Unknown option {}\n{}", flag, USAGE),
+            }
+        }
+        ensure!(options.frames > 0, "--frames has to be above 0");
+        Ok(options)
+    }
+}
+
+fn parse_list(flag: &str, value: &str) -> anyhow::Result<Vec<u32>> {
+    value
+        .split(',')
+        .map(|item| {
+            item.trim()
+                .parse()
+                .ok()
+                .filter(|&item| item > 0)
+                .with_context(|| format!("Expected a list like 32,50 for {}, got {}", flag, value))
+        })
+        .collect()
+}
+
+/// What one combination of resolution and chunk count took, summed over the timed frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Timings {
+    frames: u32,
+    voxels: u64,
+    vertices: u64,
+    // Only there with TIMESTAMP_QUERY
+    density: Option<Duration>,
+    meshing: Option<Duration>,
+    // From submitting each frame until the GPU was done with it
+    wall: Duration,
+}
+
+impl Timings {
+    /// Time spent meshing on the GPU, or waiting for each frame without timestamp queries.
+    fn gpu_seconds(&self) -> f64 {
+        match (self.density, self.meshing) {
+            (Some(density), Some(meshing)) => (density + meshing).as_secs_f64(),
+            _ => self.wall.as_secs_f64(),
+        }
+    }
+
+    fn per_second(&self, count: u64) -> f64 {
+        count as f64 / self.gpu_seconds().max(f64::EPSILON)
+    }
+
+    fn millis_per_frame(&self, duration: Option<Duration>) -> String {
+        duration.map_or("-".to_string(), |duration| {
+            format!(
+                "{:.3}",
+                duration.as_secs_f64() * 1000.0 / self.frames as f64
+            )
+        })
+    }
+}
+
+/// Times the density and marching cubes passes at every resolution and chunk count,
+/// printing a row of throughput for each.
+pub async fn run(options: BenchOptions) -> anyhow::Result<()> {
+    let gfx = Graphics::new_headless(1, 1).await?;
+    if !gfx
+        .device()
+        .features()
+        .contains(wgpu::Features::TIMESTAMP_QUERY)
+    {
+        info!("Timestamp queries aren't supported, timing whole frames from the CPU instead");
+    }
+
+    // Check every combination fits before spending minutes on the ones that do
+    let limits = gfx.device().limits();
+    for &voxels_per_chunk_dim in &options.voxels_per_chunk_dim {
+        for &chunks in &options.chunks {
+            // The readback and timestamp buffers are no bigger than the indirect draw buffer
+            chunks_config(voxels_per_chunk_dim, chunks)
+                .check(&limits)
+                .with_context(|| {
+                    format!(
+                        "Can't bench {} voxels per chunk with {} chunks on this GPU",
+                        voxels_per_chunk_dim, chunks
+                    )
+                })?;
+        }
+    }
+
+    println!(
+        "{:>5} {:>6} {:>11} {:>11} {:>11} {:>13} {:>15}",
+        "dim", "chunks", "density ms", "meshing ms", "frame ms", "Mvoxels/s", "Mvertices/s"
+    );
+    for &voxels_per_chunk_dim in &options.voxels_per_chunk_dim {
+        for &chunks in &options.chunks {
+            let bench = MeshingBench::new(&gfx, chunks_config(voxels_per_chunk_dim, chunks));
+            let timings = bench.run(&gfx, options.frames);
+            println!(
+                "{:>5} {:>6} {:>11} {:>11} {:>11.3} {:>13.2} {:>15.2}",
+                voxels_per_chunk_dim,
+                chunks,
+                timings.millis_per_frame(timings.density),
+                timings.millis_per_frame(timings.meshing),
+                timings.wall.as_secs_f64() * 1000.0 / timings.frames as f64,
+                timings.per_second(timings.voxels) / 1e6,
+                timings.per_second(timings.vertices) / 1e6,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Only the vertex counts are read back, so every chunk writes over the same vertices.
+fn chunks_config(voxels_per_chunk_dim: u32, chunk_count: u32) -> ChunksConfig {
+    ChunksConfig {
+        voxels_per_chunk_dim,
+        chunk_count,
+        vertices_per_voxel: VERTICES_PER_VOXEL,
+        share_vertices: true,
+    }
+}
+
+/// `CloudWorld`'s chunks at another resolution and chunk count, meshed with marching cubes.
+struct MeshingBench {
+    config: ChunksConfig,
+    chunks: Chunks,
+    draw_commands_readback: wgpu::Buffer,
+    timestamps: Option<Timestamps>,
+}
+
+impl MeshingBench {
+    /// The config has to pass `ChunksConfig::check` for the device.
+    fn new(gfx: &Graphics, config: ChunksConfig) -> Self {
+        let device = gfx.device();
+        Self {
+            config,
+            chunks: Chunks::new(device, &Noise::default(), config),
+            draw_commands_readback: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("bench_draw_commands_readback_buffer"),
+                size: config.indirect_draw_buffer_size(),
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            timestamps: Timestamps::new(gfx, config.chunk_count * QUERIES_PER_CHUNK),
+        }
+    }
+
+    /// Meshes every chunk each frame, waiting for each frame to be read back before the next.
+    fn run(&self, gfx: &Graphics, frames: u32) -> Timings {
+        let device = gfx.device();
+        let timestamp_writes = |pass, chunk_id| {
+            let queries = chunk_id * QUERIES_PER_CHUNK;
+            self.timestamps.as_ref().map(|timestamps| match pass {
+                GpuPass::Meshing => timestamps.writes(queries + 2),
+                _ => timestamps.writes(queries),
+            })
+        };
+        let mut timings = Timings::default();
+        for frame in 0..WARMUP_FRAMES + frames {
+            self.chunks.clear_draws(gfx.queue());
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("bench_command_encoder"),
+            });
+            self.chunks.build(
+                &mut encoder,
+                // Moves the noise along a little every frame, like when running
+                frame as f32 / 60.0,
+                Mesher::MarchingCubes,
+                |_| true,
+                timestamp_writes,
+            );
+            encoder.copy_buffer_to_buffer(
+                self.chunks.indirect_draw_buffer(),
+                0,
+                &self.draw_commands_readback,
+                0,
+                self.draw_commands_readback.size(),
+            );
+            if let Some(timestamps) = &self.timestamps {
+                timestamps.resolve(&mut encoder);
+            }
+
+            let submit_start = Instant::now();
+            gfx.queue().submit(std::iter::once(encoder.finish()));
+            let draw_commands = read_back(device, &self.draw_commands_readback);
+            let ticks = self.timestamps.as_ref().map(|t| t.read_back(device));
+            if frame < WARMUP_FRAMES {
+                continue;
+            }
+            timings.wall += submit_start.elapsed();
+            timings.frames += 1;
+            timings.voxels +=
+                self.config.chunk_count as u64 * (self.config.voxels_per_chunk_dim as u64).pow(3);
+            timings.vertices += draw_commands
+                .chunks_exact(INDIRECT_DRAW_STRIDE as usize)
+                .map(|command| bytemuck::pod_read_unaligned::<u32>(&command[..4]) as u64)
+                .sum::<u64>();
+            if let Some((density, meshing)) = ticks {
+                *timings.density.get_or_insert_with(Duration::default) += density;
+                *timings.meshing.get_or_insert_with(Duration::default) += meshing;
+            }
+        }
+        timings
+    }
+}
+
+/// A pair of timestamps around every pass, read back once the frame is done.
+struct Timestamps {
+    // Each holds QUERIES_PER_SET, except the last
+    query_sets: Vec<wgpu::QuerySet>,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    count: u32,
+    // Nanoseconds per tick
+    period: f32,
+}
+
+impl Timestamps {
+    /// Only with TIMESTAMP_QUERY.
+    fn new(gfx: &Graphics, count: u32) -> Option<Self> {
+        let device = gfx.device();
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let size = QUERY_SIZE * count as u64;
+        Some(Self {
+            query_sets: (0..count)
+                .step_by(QUERIES_PER_SET as usize)
+                .map(|start| {
+                    device.create_query_set(&wgpu::QuerySetDescriptor {
+                        label: Some("bench_query_set"),
+                        ty: wgpu::QueryType::Timestamp,
+                        count: (count - start).min(QUERIES_PER_SET),
+                    })
+                })
+                .collect(),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("bench_resolve_buffer"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("bench_timestamp_readback_buffer"),
+                size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            count,
+            period: gfx.queue().get_timestamp_period(),
+        })
+    }
+
+    fn writes(&self, index: u32) -> wgpu::ComputePassTimestampWrites<'_> {
+        let index_in_set = index % QUERIES_PER_SET;
+        wgpu::ComputePassTimestampWrites {
+            query_set: &self.query_sets[(index / QUERIES_PER_SET) as usize],
+            beginning_of_pass_write_index: Some(index_in_set),
+            end_of_pass_write_index: Some(index_in_set + 1),
+        }
+    }
+
+    fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        for (set, query_set) in self.query_sets.iter().enumerate() {
+            let start = set as u32 * QUERIES_PER_SET;
+            encoder.resolve_query_set(
+                query_set,
+                0..(self.count - start).min(QUERIES_PER_SET),
+                &self.resolve_buffer,
+                start as u64 * QUERY_SIZE,
+            );
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            self.readback_buffer.size(),
+        );
+    }
+
+    /// The time spent in the density and meshing passes of the frame.
+    fn read_back(&self, device: &wgpu::Device) -> (Duration, Duration) {
+        let ticks = read_back(device, &self.readback_buffer);
+        pass_durations(bytemuck::cast_slice(&ticks), self.period)
+    }
+}
+
+/// Sums the passes timed by each chunk's four timestamps into the density and meshing time.
+fn pass_durations(ticks: &[u64], period: f32) -> (Duration, Duration) {
+    let elapsed = |begin: u64, end: u64| {
+        // Some drivers reorder timestamps across passes, never count those as negative
+        Duration::from_nanos((end.saturating_sub(begin) as f64 * period as f64) as u64)
+    };
+    ticks.chunks_exact(QUERIES_PER_CHUNK as usize).fold(
+        (Duration::ZERO, Duration::ZERO),
+        |(density, meshing), chunk| {
+            (
+                density + elapsed(chunk[0], chunk[1]),
+                meshing + elapsed(chunk[2], chunk[3]),
+            )
+        },
+    )
+}
+
+/// Waits for the GPU to finish with the buffer and copies it out.
+fn read_back(device: &wgpu::Device, buffer: &wgpu::Buffer) -> Vec<u8> {
+    let (sender, receiver) = flume::bounded(1);
+    buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).unwrap()
+        });
+    device.poll(wgpu::Maintain::Wait);
+    receiver
+        .recv()
+        .unwrap()
+        .expect("Couldn't read back the bench");
+    let bytes = buffer.slice(..).get_mapped_range().to_vec();
+    buffer.unmap();
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> anyhow::Result<BenchOptions> {
+        BenchOptions::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn lists_of_resolutions_and_chunk_counts_are_read() {
+        assert_eq!(parse(&[]).unwrap(), BenchOptions::default());
+        assert_eq!(
+            parse(&["--dims", "16, 100", "--chunks", "1,8,64", "--frames", "5"]).unwrap(),
+            BenchOptions {
+                voxels_per_chunk_dim: vec![16, 100],
+                chunks: vec![1, 8, 64],
+                frames: 5,
+            }
+        );
+        assert!(parse(&["--dims", "16,"]).is_err());
+        assert!(parse(&["--chunks", "0"]).is_err());
+        assert!(parse(&["--frames", "0"]).is_err());
+    }
+
+    #[test]
+    fn chunk_timestamps_are_split_into_density_and_meshing() {
+        let ticks = [0, 10, 10, 40, 100, 105, 110, 100];
+        assert_eq!(
+            pass_durations(&ticks, 2.0),
+            (Duration::from_nanos(30), Duration::from_nanos(60))
+        );
+    }
+}
@@ -4,7 +4,7 @@ use glm::{Mat4, Vec3};
 use nalgebra::Point3;
 use wgpu::{util::DeviceExt as _, BindingResource};
 
-use crate::{graphics::Graphics, texture};
+use crate::{graphics::Graphics, shader, texture};
 
 pub struct Camera {
     eye: Point3<f32>,
@@ -14,6 +14,7 @@ pub struct Camera {
     fovy: f32,
     znear: f32,
     zfar: f32,
+    orbit: Orbit,
 
     buffer: wgpu::Buffer,
 
@@ -39,10 +40,11 @@ impl Camera {
             target: Point3::<f32>::new(0.0, 0.0, 0.0),
             // which way is "up"
             up: Vec3::new(0.0, 1.0, 0.0),
-            aspect: 1.0,
+            aspect: aspect(gfx.config()),
             fovy: 45.0,
             znear: 0.1,
             zfar: 100.0,
+            orbit: Orbit::default(),
 
             buffer,
 
@@ -54,7 +56,14 @@ impl Camera {
         }
     }
 
-    pub fn buffer_binding_resource(&self) -> BindingResource {
+    /// Fits the projection and the depth texture to the resized surface.
+    pub fn resize(&mut self, gfx: &Graphics) {
+        self.aspect = aspect(gfx.config());
+        self.depth_texture =
+            texture::Texture::create_depth_texture(gfx.device(), gfx.config(), "Depth texture");
+    }
+
+    pub fn buffer_binding_resource(&self) -> BindingResource<'_> {
         self.buffer.as_entire_binding()
     }
 
@@ -62,42 +71,101 @@ impl Camera {
         &self.depth_texture
     }
 
+    /// Distances to the near and far planes.
+    pub fn depth_range(&self) -> (f32, f32) {
+        (self.znear, self.zfar)
+    }
+
+    pub fn inverse_view_projection_matrix(&self) -> Mat4 {
+        self.build_view_projection_matrix()
+            .try_inverse()
+            .unwrap_or_default()
+    }
+
     fn build_view_projection_matrix(&self) -> Mat4 {
         let view = Mat4::look_at_rh(&self.eye, &self.target, &self.up);
         let proj = Mat4::new_perspective(self.aspect, self.fovy, self.znear, self.zfar);
-        return proj * view;
+        proj * view
+    }
+
+    pub fn orbit_mut(&mut self) -> &mut Orbit {
+        &mut self.orbit
     }
 
     pub fn update(&mut self, world_time: f32) {
-        let time = (world_time * 0.15) % (PI * 2.0) as f32;
-        self.eye.x = time.cos() * 14.0;
-        self.eye.y = time.sin() * 8.0;
-        self.eye.z = time.sin() * 14.0;
+        let time = (world_time * self.orbit.speed) % (PI * 2.0) as f32;
+        self.eye.x = time.cos() * self.orbit.radius;
+        self.eye.y = time.sin() * self.orbit.height;
+        self.eye.z = time.sin() * self.orbit.radius;
     }
 
     pub fn write_data_buffer(&self, queue: &wgpu::Queue) {
-        let view_proj = self.build_view_projection_matrix();
-        let data = CameraUniform {
-            view_proj: view_proj.into(),
-        };
+        let data = CameraUniform::from_view_projection(
+            &self.build_view_projection_matrix(),
+            &self.eye.coords,
+        );
         queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[data]));
     }
 }
 
+/// How the camera circles the clouds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Orbit {
+    /// Radians per second of world time. The camera's place on the orbit follows
+    /// the world time, so changing this jumps it around.
+    pub speed: f32,
+    pub radius: f32,
+    /// How far above and below the clouds the camera swings.
+    pub height: f32,
+}
+
+impl Default for Orbit {
+    fn default() -> Self {
+        Self {
+            speed: 0.15,
+            radius: 14.0,
+            height: 8.0,
+        }
+    }
+}
+
 // We need this for Rust to store our data correctly for the shaders
 #[repr(C)]
 // This is so we can store this in a buffer
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct CameraUniform {
+pub(crate) struct CameraUniform {
     // We can't use cgmath with bytemuck directly, so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
-    view_proj: [[f32; 4]; 4],
+    pub view_proj: [[f32; 4]; 4],
+    // For turning screen positions back into rays
+    pub inverse_view_proj: [[f32; 4]; 4],
+    pub eye: [f32; 4],
 }
 
+shader::assert_wgsl_layout!(CameraUniform, 144, {
+    view_proj: 0,
+    inverse_view_proj: 64,
+    eye: 128,
+});
+
 impl CameraUniform {
     fn new() -> Self {
         Self {
             view_proj: Mat4::identity().into(),
+            inverse_view_proj: Mat4::identity().into(),
+            eye: [0.0, 0.0, 0.0, 1.0],
         }
     }
+
+    pub(crate) fn from_view_projection(view_proj: &Mat4, eye: &Vec3) -> Self {
+        Self {
+            view_proj: (*view_proj).into(),
+            inverse_view_proj: view_proj.try_inverse().unwrap_or_default().into(),
+            eye: [eye.x, eye.y, eye.z, 1.0],
+        }
+    }
+}
+
+fn aspect(config: &wgpu::SurfaceConfiguration) -> f32 {
+    config.width as f32 / config.height as f32
 }
@@ -0,0 +1,263 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context as _;
+use log::{error, info};
+
+use crate::graphics::Graphics;
+
+const BYTES_PER_PIXEL: u32 = 4;
+const SCREENSHOT_DIR: &str = "screenshots";
+
+/// Copies a texture into a staging buffer and waits for it to be read back.
+/// Returns tightly packed rows of RGBA8 pixels.
+pub fn read_texture(gfx: &Graphics, texture: &wgpu::Texture) -> Vec<u8> {
+    let mut encoder = gfx
+        .device()
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("capture_command_encoder"),
+        });
+    let staged = StagedTexture::copy(gfx, &mut encoder, texture);
+    gfx.queue().submit(std::iter::once(encoder.finish()));
+
+    let (sender, receiver) = flume::bounded(1);
+    staged
+        .buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).unwrap()
+        });
+    gfx.device().poll(wgpu::Maintain::Wait);
+    receiver
+        .recv()
+        .unwrap()
+        .expect("Couldn't read back the frame");
+    staged.read_pixels()
+}
+
+/// Whether the texture's pixels can be saved as they are, give or take the channel order.
+fn is_capturable(texture: &wgpu::Texture) -> bool {
+    texture.usage().contains(wgpu::TextureUsages::COPY_SRC)
+        && matches!(
+            texture.format(),
+            wgpu::TextureFormat::Rgba8Unorm
+                | wgpu::TextureFormat::Rgba8UnormSrgb
+                | wgpu::TextureFormat::Bgra8Unorm
+                | wgpu::TextureFormat::Bgra8UnormSrgb
+        )
+}
+
+/// A texture copied into a staging buffer, to be read once the copy is done.
+struct StagedTexture {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl StagedTexture {
+    fn copy(gfx: &Graphics, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) -> Self {
+        assert!(
+            is_capturable(texture),
+            "{:?} can't be captured",
+            texture.format()
+        );
+        let (width, height) = (texture.width(), texture.height());
+        let padded_bytes_per_row = padded_bytes_per_row(width);
+        let buffer = gfx.device().create_buffer(&wgpu::BufferDescriptor {
+            label: Some("capture_staging_buffer"),
+            size: padded_bytes_per_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            texture.size(),
+        );
+        Self {
+            buffer,
+            width,
+            height,
+            format: texture.format(),
+        }
+    }
+
+    /// Takes the pixels out of the mapped buffer and unmaps it.
+    fn read_pixels(&self) -> Vec<u8> {
+        let mut pixels = unpad_rows(
+            &self.buffer.slice(..).get_mapped_range(),
+            self.width,
+            self.height,
+        );
+        self.buffer.unmap();
+        if matches!(
+            self.format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        ) {
+            bgra_to_rgba(&mut pixels);
+        }
+        pixels
+    }
+}
+
+/// Screenshots of the window. Each is copied out of its frame, read back once the GPU is done
+/// with it and saved on its own thread, so taking one never holds up the frames after it.
+#[derive(Default)]
+pub struct Screenshots {
+    requested: bool,
+    // Copied this frame, to be mapped once submitted
+    copied: Option<StagedTexture>,
+    // Waiting on their map callbacks, which send whether the read back worked
+    mapping: Vec<(
+        StagedTexture,
+        flume::Receiver<Result<(), wgpu::BufferAsyncError>>,
+    )>,
+}
+
+impl Screenshots {
+    /// Takes a screenshot of the next frame.
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    /// Copies the frame if a screenshot was requested, before it's submitted.
+    pub fn copy(
+        &mut self,
+        gfx: &Graphics,
+        encoder: &mut wgpu::CommandEncoder,
+        frame: &wgpu::Texture,
+    ) {
+        if !std::mem::take(&mut self.requested) {
+            return;
+        }
+        if !is_capturable(frame) {
+            error!(
+                "Frames drawn as {:?} can't be captured on this platform",
+                frame.format()
+            );
+            return;
+        }
+        self.copied = Some(StagedTexture::copy(gfx, encoder, frame));
+    }
+
+    /// Starts reading back this frame's screenshot, once it has been submitted.
+    pub fn end_frame(&mut self) {
+        let Some(staged) = self.copied.take() else {
+            return;
+        };
+        let (sender, receiver) = flume::bounded(1);
+        staged
+            .buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+        self.mapping.push((staged, receiver));
+    }
+
+    /// Saves every screenshot that has been read back, dropping those that couldn't be.
+    pub fn poll(&mut self, gfx: &Graphics) {
+        if self.mapping.is_empty() {
+            return;
+        }
+        gfx.device().poll(wgpu::Maintain::Poll);
+        self.mapping.retain(|(staged, mapped)| {
+            match mapped.try_recv() {
+                Err(flume::TryRecvError::Empty) => return true,
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    error!("Couldn't read back the screenshot: {}", e);
+                    return false;
+                }
+                // wgpu dropped the callback without calling it, like when the device is lost
+                Err(flume::TryRecvError::Disconnected) => {
+                    error!("Couldn't read back the screenshot, the GPU never finished mapping it");
+                    return false;
+                }
+            }
+            let (width, height, pixels) = (staged.width, staged.height, staged.read_pixels());
+            std::thread::spawn(move || match save_screenshot(width, height, &pixels) {
+                Ok(path) => info!("Saved a screenshot to {:?}", path),
+                Err(e) => error!("Couldn't save the screenshot: {:?}", e),
+            });
+            false
+        });
+    }
+}
+
+/// Named after the time it was taken, so none overwrite each other.
+fn save_screenshot(width: u32, height: u32, pixels: &[u8]) -> anyhow::Result<PathBuf> {
+    std::fs::create_dir_all(SCREENSHOT_DIR)?;
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let path = Path::new(SCREENSHOT_DIR).join(format!("screenshot_{}.png", millis));
+    save_png(&path, width, height, pixels)?;
+    Ok(path)
+}
+
+pub fn save_png(path: &Path, width: u32, height: u32, pixels: &[u8]) -> anyhow::Result<()> {
+    let file = File::create(path).with_context(|| format!("Couldn't create {:?}", path))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // The frames are drawn to sRGB targets
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    encoder.write_header()?.write_image_data(pixels)?;
+    Ok(())
+}
+
+/// Rows copied out of a texture must be aligned to 256 bytes.
+fn padded_bytes_per_row(width: u32) -> u32 {
+    (width * BYTES_PER_PIXEL).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+}
+
+fn unpad_rows(padded: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let row_bytes = (width * BYTES_PER_PIXEL) as usize;
+    padded
+        .chunks_exact(padded_bytes_per_row(width) as usize)
+        .take(height as usize)
+        .flat_map(|row| &row[..row_bytes])
+        .copied()
+        .collect()
+}
+
+fn bgra_to_rgba(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(BYTES_PER_PIXEL as usize) {
+        pixel.swap(0, 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padding_is_dropped_from_every_row() {
+        // 3 pixels take 12 bytes of each 256 byte row
+        let mut padded = vec![0_u8; 512];
+        padded[..12].fill(1);
+        padded[256..268].fill(2);
+        let pixels = unpad_rows(&padded, 3, 2);
+        assert_eq!(pixels.len(), 24);
+        assert!(pixels[..12].iter().all(|&byte| byte == 1));
+        assert!(pixels[12..].iter().all(|&byte| byte == 2));
+    }
+
+    #[test]
+    fn bgra_pixels_are_swizzled() {
+        let mut pixels = vec![3, 2, 1, 4, 7, 6, 5, 8];
+        bgra_to_rgba(&mut pixels);
+        assert_eq!(pixels, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+}
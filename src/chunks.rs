@@ -0,0 +1,745 @@
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt, DrawIndirect},
+    BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    BufferDescriptor, BufferUsages, ComputePipeline, ComputePipelineDescriptor, Extent3d,
+    PipelineLayout, PushConstantRange, ShaderModule, ShaderStages, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureUsages, TextureViewDimension, VertexAttribute,
+    VertexBufferLayout,
+};
+
+use crate::{
+    error::Error,
+    noise::Noise,
+    profiler::GpuPass,
+    shader::{self, create_shader_module_with_resolution, try_create_pipeline, Shader},
+};
+
+pub(crate) const VOXELS_PER_CHUNK_DIM: u32 = 50;
+// The world is a 2x2x2 grid of chunks
+pub(crate) const CHUNK_COUNT: u32 = 8;
+// Every chunk gets a region of the density texture, so the whole world can be raymarched.
+// One voxel of padding on the low side lets the meshers look into the neighbouring chunk.
+pub(crate) const DENSITY_REGION_DIM: u32 = VOXELS_PER_CHUNK_DIM + 2;
+pub(crate) const DENSITY_TEXTURE_DIM: u32 = DENSITY_REGION_DIM * 2;
+pub(crate) const VERTICES_PER_VOXEL: u64 = 3; // Assumes an average of 1 triangle per voxel
+pub(crate) const CLOUD_VERTEX_SIZE: u64 = std::mem::size_of::<Vertex>() as u64;
+// Every chunk keeps its mesh for the whole frame, so it can be drawn into the shadow map and then lit.
+// Storage buffer bindings need their offsets aligned to 256 bytes.
+pub(crate) const CHUNK_VERTEX_BUFFER_SIZE: u64 = (VOXELS_PER_CHUNK_DIM as u64
+    * VOXELS_PER_CHUNK_DIM as u64
+    * VOXELS_PER_CHUNK_DIM as u64
+    * CLOUD_VERTEX_SIZE
+    * VERTICES_PER_VOXEL)
+    .next_multiple_of(256);
+pub(crate) const INDIRECT_DRAW_STRIDE: u64 = 256;
+pub(crate) const PUSH_CONSTANTS_SIZE: u32 = std::mem::size_of::<PushConstants>() as u32;
+// The meshers' workgroups are this many voxels along each side, matching @workgroup_size.
+// Keep it a multiple of 8: Mesa's llvmpipe loses the writes from the second trip through a loop
+// for invocations in a partly filled group of 8 lanes, which left holes in the mesh.
+pub(crate) const MESHER_WORKGROUP_DIM: u32 = 8;
+
+// The layouts below mirror the structs in shaders/common.wgsl
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PushConstants {
+    pub time: f32,
+    pub chunk_id: u32,
+}
+
+shader::assert_wgsl_layout!(PushConstants, 8, { time: 0, chunk_id: 4 });
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex {
+    pub position: [f32; 4],
+    // The w component holds the height used for coloring
+    pub normal: [f32; 4],
+}
+
+shader::assert_wgsl_layout!(Vertex, 32, { position: 0, normal: 16 });
+// The meshers' IndirectDrawCommand
+shader::assert_wgsl_layout!(DrawIndirect, 16, {
+    vertex_count: 0,
+    instance_count: 4,
+    base_vertex: 8,
+    base_instance: 12,
+});
+
+pub const CLOUD_VERTEX_BUFFER_LAYOUT: VertexBufferLayout = VertexBufferLayout {
+    array_stride: CLOUD_VERTEX_SIZE,
+    step_mode: wgpu::VertexStepMode::Vertex,
+    attributes: &VERTEX_ATTRIBUTES,
+};
+
+pub const VERTEX_ATTRIBUTES: [VertexAttribute; 2] = [
+    VertexAttribute {
+        format: wgpu::VertexFormat::Float32x4,
+        offset: std::mem::offset_of!(Vertex, position) as u64,
+        shader_location: 0,
+    },
+    VertexAttribute {
+        format: wgpu::VertexFormat::Float32x4,
+        offset: std::mem::offset_of!(Vertex, normal) as u64,
+        shader_location: 1,
+    },
+];
+
+// Push constant ranges and vertex strides must be multiples of 4 bytes.
+const _: () = assert!(PUSH_CONSTANTS_SIZE.is_multiple_of(4));
+const _: () = assert!(CLOUD_VERTEX_SIZE.is_multiple_of(4));
+
+/// How the density texture is turned into triangles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mesher {
+    #[default]
+    MarchingCubes,
+    /// Slower and with more triangles than marching cubes, but the surface is always closed.
+    MarchingTetrahedra,
+    /// Naive surface nets. Smoother, with fewer triangles than marching cubes.
+    SurfaceNets,
+    /// Surface nets with vertices fitted to the density gradients, which keeps sharp features.
+    DualContouring,
+}
+
+impl Mesher {
+    pub fn next(self) -> Self {
+        match self {
+            Mesher::MarchingCubes => Mesher::MarchingTetrahedra,
+            Mesher::MarchingTetrahedra => Mesher::SurfaceNets,
+            Mesher::SurfaceNets => Mesher::DualContouring,
+            Mesher::DualContouring => Mesher::MarchingCubes,
+        }
+    }
+}
+
+/// How finely and how many chunks are meshed. `CloudWorld` uses the default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct ChunksConfig {
+    pub voxels_per_chunk_dim: u32,
+    /// Chunks past the 2x2x2 grid wrap around and redo the density and mesh of an earlier one.
+    pub chunk_count: u32,
+    /// Room in each chunk's vertex buffer, as an average per voxel. Vertices past it are dropped.
+    pub vertices_per_voxel: u64,
+    /// Every chunk writes over the same vertices, for when only the vertex counts are read.
+    pub share_vertices: bool,
+}
+
+impl Default for ChunksConfig {
+    fn default() -> Self {
+        Self {
+            voxels_per_chunk_dim: VOXELS_PER_CHUNK_DIM,
+            chunk_count: CHUNK_COUNT,
+            vertices_per_voxel: VERTICES_PER_VOXEL,
+            share_vertices: false,
+        }
+    }
+}
+
+impl ChunksConfig {
+    pub fn density_region_dim(&self) -> u32 {
+        self.voxels_per_chunk_dim + 2
+    }
+
+    pub fn density_texture_dim(&self) -> u32 {
+        self.density_region_dim() * 2
+    }
+
+    pub fn chunk_vertex_buffer_size(&self) -> u64 {
+        ((self.voxels_per_chunk_dim as u64).pow(3) * CLOUD_VERTEX_SIZE * self.vertices_per_voxel)
+            .next_multiple_of(256)
+    }
+
+    pub fn vertex_buffer_size(&self) -> u64 {
+        if self.share_vertices {
+            self.chunk_vertex_buffer_size()
+        } else {
+            self.chunk_vertex_buffer_size() * self.chunk_count as u64
+        }
+    }
+
+    pub fn indirect_draw_buffer_size(&self) -> u64 {
+        INDIRECT_DRAW_STRIDE * self.chunk_count as u64
+    }
+
+    pub fn chunk_vertex_offset(&self, chunk_id: u32) -> u64 {
+        if self.share_vertices {
+            0
+        } else {
+            chunk_id as u64 * self.chunk_vertex_buffer_size()
+        }
+    }
+
+    /// Fails if the density texture or the buffers don't fit within the device's limits.
+    pub fn check(&self, limits: &wgpu::Limits) -> Result<(), Error> {
+        if self.voxels_per_chunk_dim == 0 || self.chunk_count == 0 || self.vertices_per_voxel == 0 {
+            return Err(Error::Empty);
+        }
+        let too_large = |resource, size: u64, limit: u64| {
+            if size > limit {
+                Err(Error::TooLarge {
+                    resource,
+                    size,
+                    limit,
+                })
+            } else {
+                Ok(())
+            }
+        };
+        too_large(
+            "density texture's width",
+            self.density_texture_dim() as u64,
+            limits.max_texture_dimension_3d as u64,
+        )?;
+        too_large(
+            "vertex buffer binding of each chunk",
+            self.chunk_vertex_buffer_size(),
+            limits.max_storage_buffer_binding_size as u64,
+        )?;
+        too_large(
+            "vertex buffer",
+            self.vertex_buffer_size(),
+            limits.max_buffer_size,
+        )?;
+        too_large(
+            "indirect draw buffer",
+            self.indirect_draw_buffer_size(),
+            limits.max_buffer_size,
+        )
+    }
+}
+
+/// The density texture and every chunk's mesh, rebuilt on the GPU each frame.
+pub(crate) struct Chunks {
+    config: ChunksConfig,
+    // Only read back by the mesh parity test
+    #[cfg_attr(not(test), allow(dead_code))]
+    density_texture: wgpu::Texture,
+    density_texture_view: wgpu::TextureView,
+    noise_buffer: wgpu::Buffer,
+    density_pipeline_layout: PipelineLayout,
+    density_pipeline: ComputePipeline,
+    density_bind_group: BindGroup,
+    mesher_pipeline_layout: PipelineLayout,
+    marching_cubes_pipeline: ComputePipeline,
+    marching_tetrahedra_pipeline: ComputePipeline,
+    surface_nets_pipeline: ComputePipeline,
+    dual_contouring_pipeline: ComputePipeline,
+    // One per chunk, each writing to the chunk's own part of the vertex and indirect draw buffers
+    mesher_bind_groups: Vec<BindGroup>,
+    vertex_buffer: wgpu::Buffer,
+    indirect_draw_buffer: wgpu::Buffer,
+}
+
+impl Chunks {
+    /// The config has to pass `ChunksConfig::check` for the device.
+    pub fn new(device: &wgpu::Device, noise: &Noise, config: ChunksConfig) -> Self {
+        let create_shader_module = |shader| {
+            create_shader_module_with_resolution(device, shader, config.voxels_per_chunk_dim)
+        };
+        let density_texture_dim = config.density_texture_dim();
+        let density_texture = device.create_texture(&TextureDescriptor {
+            label: Some("density_texture"),
+            size: Extent3d {
+                width: density_texture_dim,
+                height: density_texture_dim,
+                depth_or_array_layers: density_texture_dim,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D3,
+            format: TextureFormat::Rgba16Float,
+            // Sampled by the volume renderer, and copied out by the tests
+            usage: TextureUsages::STORAGE_BINDING
+                | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC,
+            view_formats: &[TextureFormat::Rgba16Float],
+        });
+        let density_texture_view = density_texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("density_texture_view"),
+            ..Default::default()
+        });
+
+        // Density generation shader
+        let density_shader = create_shader_module(Shader::CloudDensity);
+        let density_bind_group_layout = create_density_bind_group_layout(device);
+        let density_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("density_pipeline_layout"),
+                bind_group_layouts: &[&density_bind_group_layout],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..PUSH_CONSTANTS_SIZE,
+                }],
+            });
+        let density_pipeline = create_compute_pipeline(
+            device,
+            "density_pipeline",
+            &density_pipeline_layout,
+            &density_shader,
+            "main",
+        );
+        let noise_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("noise_uniform_buffer"),
+            contents: bytemuck::bytes_of(&noise.uniform()),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        let density_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("density_bind_group"),
+            layout: &density_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&density_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: noise_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        // Mesher shaders
+        let marching_cubes_shader = create_shader_module(Shader::MarchingCubes);
+        let marching_tetrahedra_shader = create_shader_module(Shader::MarchingTetrahedra);
+        let surface_nets_shader = create_shader_module(Shader::SurfaceNets);
+        let mesher_bind_group_layout = create_mesher_bind_group_layout(device);
+        let mesher_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("mesher_pipeline_layout"),
+                bind_group_layouts: &[&mesher_bind_group_layout],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..PUSH_CONSTANTS_SIZE,
+                }],
+            });
+        let marching_cubes_pipeline = create_compute_pipeline(
+            device,
+            "marching_cubes_pipeline",
+            &mesher_pipeline_layout,
+            &marching_cubes_shader,
+            "main",
+        );
+        let marching_tetrahedra_pipeline = create_compute_pipeline(
+            device,
+            "marching_tetrahedra_pipeline",
+            &mesher_pipeline_layout,
+            &marching_tetrahedra_shader,
+            "main",
+        );
+        let (surface_nets_pipeline, dual_contouring_pipeline) =
+            create_surface_nets_pipelines(device, &mesher_pipeline_layout, &surface_nets_shader);
+
+        let vertex_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("cloud_vertex_buffer"),
+            size: config.vertex_buffer_size(),
+            usage: BufferUsages::STORAGE | BufferUsages::VERTEX | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let indirect_draw_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("render_indirect_draw_buffer"),
+            size: config.indirect_draw_buffer_size(),
+            usage: BufferUsages::STORAGE
+                | BufferUsages::INDIRECT
+                | BufferUsages::COPY_DST
+                | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let edge_table_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("marching_cubes_edge_table_buffer"),
+            contents: bytemuck::cast_slice(&crate::marching_cubes::EDGE_TABLE),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let tri_table_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("marching_cubes_tri_table_buffer"),
+            contents: bytemuck::cast_slice(&crate::marching_cubes::TRI_TABLE),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let mesher_bind_groups = (0..config.chunk_count)
+            .map(|chunk_id| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("mesher_bind_group"),
+                    layout: &mesher_bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&density_texture_view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                                buffer: &indirect_draw_buffer,
+                                offset: indirect_draw_offset(chunk_id),
+                                size: wgpu::BufferSize::new(
+                                    std::mem::size_of::<DrawIndirect>() as u64
+                                ),
+                            }),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                                buffer: &vertex_buffer,
+                                offset: config.chunk_vertex_offset(chunk_id),
+                                size: wgpu::BufferSize::new(config.chunk_vertex_buffer_size()),
+                            }),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: edge_table_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: tri_table_buffer.as_entire_binding(),
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        Self {
+            config,
+            density_texture,
+            density_texture_view,
+            noise_buffer,
+            density_pipeline_layout,
+            density_pipeline,
+            density_bind_group,
+            mesher_pipeline_layout,
+            marching_cubes_pipeline,
+            marching_tetrahedra_pipeline,
+            surface_nets_pipeline,
+            dual_contouring_pipeline,
+            mesher_bind_groups,
+            vertex_buffer,
+            indirect_draw_buffer,
+        }
+    }
+
+    /// Rebuilds the pipelines of a density or mesher shader.
+    pub fn reload(&mut self, device: &wgpu::Device, shader: Shader, source: &str) {
+        match shader {
+            Shader::CloudDensity => {
+                if let Some(pipeline) = try_create_pipeline(device, shader, source, |module| {
+                    create_compute_pipeline(
+                        device,
+                        "density_pipeline",
+                        &self.density_pipeline_layout,
+                        module,
+                        "main",
+                    )
+                }) {
+                    self.density_pipeline = pipeline;
+                }
+            }
+            Shader::MarchingCubes => {
+                if let Some(pipeline) = try_create_pipeline(device, shader, source, |module| {
+                    create_compute_pipeline(
+                        device,
+                        "marching_cubes_pipeline",
+                        &self.mesher_pipeline_layout,
+                        module,
+                        "main",
+                    )
+                }) {
+                    self.marching_cubes_pipeline = pipeline;
+                }
+            }
+            Shader::MarchingTetrahedra => {
+                if let Some(pipeline) = try_create_pipeline(device, shader, source, |module| {
+                    create_compute_pipeline(
+                        device,
+                        "marching_tetrahedra_pipeline",
+                        &self.mesher_pipeline_layout,
+                        module,
+                        "main",
+                    )
+                }) {
+                    self.marching_tetrahedra_pipeline = pipeline;
+                }
+            }
+            Shader::SurfaceNets => {
+                if let Some((surface_nets, dual_contouring)) =
+                    try_create_pipeline(device, shader, source, |module| {
+                        create_surface_nets_pipelines(device, &self.mesher_pipeline_layout, module)
+                    })
+                {
+                    self.surface_nets_pipeline = surface_nets;
+                    self.dual_contouring_pipeline = dual_contouring;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn write_noise(&self, queue: &wgpu::Queue, noise: &Noise) {
+        queue.write_buffer(&self.noise_buffer, 0, bytemuck::bytes_of(&noise.uniform()));
+    }
+
+    /// Empties every chunk's indirect draw, for the meshers to count their vertices into.
+    pub fn clear_draws(&self, queue: &wgpu::Queue) {
+        // See wgpu::DrawIndirect
+        let mut draw_commands = vec![0_u8; self.config.indirect_draw_buffer_size() as usize];
+        for chunk_id in 0..self.config.chunk_count {
+            let offset = indirect_draw_offset(chunk_id) as usize;
+            draw_commands[offset..offset + std::mem::size_of::<DrawIndirect>()]
+                .copy_from_slice(bytemuck::cast_slice(&[0_u32, 1_u32, 0_u32, 0_u32]));
+        }
+        queue.write_buffer(&self.indirect_draw_buffer, 0, &draw_commands);
+    }
+
+    /// Fills in every chunk's density, then meshes the chunks `meshed` picks with `mesher`.
+    /// `timestamp_writes` times each chunk's passes.
+    pub fn build<'a>(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        world_time: f32,
+        mesher: Mesher,
+        meshed: impl Fn(u32) -> bool,
+        timestamp_writes: impl Fn(GpuPass, u32) -> Option<wgpu::ComputePassTimestampWrites<'a>>,
+    ) {
+        let mesher_pipeline = match mesher {
+            Mesher::MarchingCubes => &self.marching_cubes_pipeline,
+            Mesher::MarchingTetrahedra => &self.marching_tetrahedra_pipeline,
+            Mesher::SurfaceNets => &self.surface_nets_pipeline,
+            Mesher::DualContouring => &self.dual_contouring_pipeline,
+        };
+        let density_region_dim = self.config.density_region_dim();
+        let mesher_workgroups = self
+            .config
+            .voxels_per_chunk_dim
+            .div_ceil(MESHER_WORKGROUP_DIM);
+
+        // Build a 2x2x2 grid of chunks, one chunk at a time.
+        // Each chunk saturates the GPU with work.
+        // The workgroup counts are conditioned on the workgroup sizes
+        // to cover every voxel in the chunk without going over GPU limits.
+        for chunk_id in 0..self.config.chunk_count {
+            let push_constants = PushConstants {
+                time: world_time,
+                chunk_id: chunk_id % CHUNK_COUNT,
+            };
+            let push_constants = bytemuck::bytes_of(&push_constants);
+
+            // Generate density data
+            // This step operates on the corners of the voxels
+            {
+                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some("cloud_density_pass"),
+                    timestamp_writes: timestamp_writes(GpuPass::Density, chunk_id),
+                });
+                compute_pass.set_pipeline(&self.density_pipeline);
+                compute_pass.set_push_constants(0, push_constants);
+                compute_pass.set_bind_group(0, &self.density_bind_group, &[]);
+                compute_pass.dispatch_workgroups(
+                    density_region_dim.div_ceil(10),
+                    density_region_dim.div_ceil(9),
+                    density_region_dim.div_ceil(8),
+                );
+            }
+
+            if !meshed(chunk_id) {
+                continue;
+            }
+
+            // Meshing
+            // This step operates on the centers of the voxels
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("mesher_compute_pass"),
+                timestamp_writes: timestamp_writes(GpuPass::Meshing, chunk_id),
+            });
+            compute_pass.set_pipeline(mesher_pipeline);
+            compute_pass.set_push_constants(0, push_constants);
+            compute_pass.set_bind_group(0, &self.mesher_bind_groups[chunk_id as usize], &[]);
+            compute_pass.dispatch_workgroups(
+                mesher_workgroups,
+                mesher_workgroups,
+                mesher_workgroups,
+            );
+        }
+    }
+
+    /// Draws every chunk's mesh with the pipeline and bind groups already set.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, world_time: f32) {
+        for chunk_id in 0..self.config.chunk_count {
+            let push_constants = PushConstants {
+                time: world_time,
+                chunk_id: chunk_id % CHUNK_COUNT,
+            };
+            render_pass.set_push_constants(
+                ShaderStages::VERTEX,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+            render_pass.set_vertex_buffer(0, self.chunk_vertices(chunk_id));
+            render_pass.draw_indirect(&self.indirect_draw_buffer, indirect_draw_offset(chunk_id));
+        }
+    }
+
+    /// The part of the vertex buffer holding a chunk's mesh.
+    pub fn chunk_vertices(&self, chunk_id: u32) -> wgpu::BufferSlice<'_> {
+        let start = self.config.chunk_vertex_offset(chunk_id);
+        self.vertex_buffer
+            .slice(start..start + self.config.chunk_vertex_buffer_size())
+    }
+
+    /// Every chunk's region of the density, for the passes sampling it.
+    pub fn density_view(&self) -> &wgpu::TextureView {
+        &self.density_texture_view
+    }
+
+    #[cfg(test)]
+    pub fn density_texture(&self) -> &wgpu::Texture {
+        &self.density_texture
+    }
+
+    #[cfg(test)]
+    pub fn vertex_buffer(&self) -> &wgpu::Buffer {
+        &self.vertex_buffer
+    }
+
+    pub fn indirect_draw_buffer(&self) -> &wgpu::Buffer {
+        &self.indirect_draw_buffer
+    }
+}
+
+pub(crate) fn indirect_draw_offset(chunk_id: u32) -> u64 {
+    chunk_id as u64 * INDIRECT_DRAW_STRIDE
+}
+
+/// The density pass writes every chunk's region of the density texture.
+fn create_density_bind_group_layout(device: &wgpu::Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("density_bind_group_layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: TextureFormat::Rgba16Float,
+                    view_dimension: TextureViewDimension::D3,
+                },
+                count: None,
+            },
+            // Noise parameters
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+/// All meshers share a bind group, the tables are only used by marching cubes.
+fn create_mesher_bind_group_layout(device: &wgpu::Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("mesher_bind_group_layout"),
+        entries: &[
+            // Density data
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::ReadOnly,
+                    format: TextureFormat::Rgba16Float,
+                    view_dimension: TextureViewDimension::D3,
+                },
+                count: None,
+            },
+            // Indirect draw buffer
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Vertex buffer
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Edge table
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Tri table
+            BindGroupLayoutEntry {
+                binding: 4,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+fn create_compute_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &PipelineLayout,
+    module: &ShaderModule,
+    entry_point: &str,
+) -> ComputePipeline {
+    device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        module,
+        entry_point,
+    })
+}
+
+/// Both entry points of the surface nets shader, the second one fitting the vertices with dual
+/// contouring.
+fn create_surface_nets_pipelines(
+    device: &wgpu::Device,
+    layout: &PipelineLayout,
+    module: &ShaderModule,
+) -> (ComputePipeline, ComputePipeline) {
+    (
+        create_compute_pipeline(
+            device,
+            "surface_nets_pipeline",
+            layout,
+            module,
+            "surface_nets",
+        ),
+        create_compute_pipeline(
+            device,
+            "dual_contouring_pipeline",
+            layout,
+            module,
+            "dual_contouring",
+        ),
+    )
+}
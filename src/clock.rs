@@ -0,0 +1,115 @@
+/// How far a single step moves a paused clock, unless it has a fixed timestep.
+pub const DEFAULT_STEP_SECONDS: f32 = 1.0 / 60.0;
+
+/// The world's time, which everything animated follows.
+/// Can be paused, stepped, sped up and moved to any time. With a fixed timestep every tick
+/// advances by the same amount, whatever the frame took, so runs can be reproduced.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clock {
+    seconds: f32,
+    pub speed: f32,
+    pub paused: bool,
+    /// Seconds per tick, in place of the time the frame took.
+    pub fixed_timestep: Option<f32>,
+    // Set by `step`, advances the next tick while paused
+    step_requested: bool,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self {
+            seconds: 0.0,
+            speed: 1.0,
+            paused: false,
+            fixed_timestep: None,
+            step_requested: false,
+        }
+    }
+}
+
+impl Clock {
+    /// Seconds of world time.
+    pub fn seconds(&self) -> f32 {
+        self.seconds
+    }
+
+    /// Moves the clock once a frame, given the seconds the frame took.
+    pub fn tick(&mut self, frame_seconds: f32) {
+        let seconds = if self.paused {
+            if !std::mem::take(&mut self.step_requested) {
+                return;
+            }
+            self.fixed_timestep.unwrap_or(DEFAULT_STEP_SECONDS)
+        } else {
+            self.fixed_timestep.unwrap_or(frame_seconds)
+        };
+        self.seconds += seconds * self.speed;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    /// Advances a paused clock by one timestep on the next tick.
+    pub fn step(&mut self) {
+        self.step_requested = self.paused;
+    }
+
+    /// Jumps to `seconds` of world time.
+    pub fn seek(&mut self, seconds: f32) {
+        self.seconds = seconds;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_follow_the_frame_time_scaled_by_the_speed() {
+        let mut clock = Clock {
+            speed: 2.0,
+            ..Default::default()
+        };
+        clock.tick(0.25);
+        clock.tick(0.5);
+        assert_eq!(clock.seconds(), 1.5);
+    }
+
+    #[test]
+    fn fixed_timesteps_ignore_the_frame_time() {
+        let mut clock = Clock {
+            fixed_timestep: Some(0.125),
+            ..Default::default()
+        };
+        clock.tick(1.0);
+        clock.tick(0.001);
+        assert_eq!(clock.seconds(), 0.25);
+    }
+
+    #[test]
+    fn paused_clocks_only_move_when_stepped() {
+        let mut clock = Clock::default();
+        clock.seek(3.0);
+        clock.toggle_pause();
+        clock.tick(1.0);
+        assert_eq!(clock.seconds(), 3.0);
+        clock.step();
+        clock.tick(1.0);
+        clock.tick(1.0);
+        assert_eq!(clock.seconds(), 3.0 + DEFAULT_STEP_SECONDS);
+    }
+
+    #[test]
+    fn steps_are_ignored_while_running() {
+        let mut clock = Clock {
+            fixed_timestep: Some(0.5),
+            ..Default::default()
+        };
+        clock.step();
+        clock.tick(0.0);
+        clock.toggle_pause();
+        clock.tick(0.0);
+        assert_eq!(clock.seconds(), 0.5);
+    }
+}
@@ -1,482 +1,676 @@
 use std::time::Instant;
 
-use log::info;
+use log::{error, info};
 use wgpu::{
-    util::{BufferInitDescriptor, DeviceExt, DrawIndirect},
-    vertex_attr_array, BindGroup, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
-    Buffer, BufferDescriptor, BufferUsages, Color, ComputePipeline, ComputePipelineDescriptor,
-    DepthStencilState, Extent3d, LoadOp, Operations, PushConstantRange, RenderPassColorAttachment,
-    RenderPassDescriptor, RenderPipeline, ShaderStages, StoreOp, SurfaceError, TextureDescriptor,
-    TextureDimension, TextureFormat, TextureUsages, TextureViewDimension, VertexAttribute,
-    VertexBufferLayout,
+    util::{BufferInitDescriptor, DeviceExt},
+    Buffer, BufferUsages, SurfaceError,
 };
 
-use crate::{camera::Camera, graphics::Graphics};
+use winit::keyboard::KeyCode;
+
+use crate::{
+    camera::Camera,
+    chunks::{Chunks, ChunksConfig, Mesher, CHUNK_COUNT},
+    clock::Clock,
+    debug_view::{DebugView, DebugViews},
+    frame_stats::FrameStats,
+    graphics::Graphics,
+    lighting::Lighting,
+    lit_mesh::LitMesh,
+    noise::Noise,
+    overlay,
+    post::{PostPasses, PostProcessing},
+    shader::{self, Shader, ShaderWatcher},
+    shadow::ShadowMap,
+    sky::Sky,
+    ssao::Ssao,
+    volume::Volume,
+};
 
 pub struct CloudWorld {
-    creation_instant: Instant,
+    clock: Clock,
     camera: Camera,
-    density_pipeline: ComputePipeline,
-    marching_cubes_pipeline: ComputePipeline,
-    render_pipeline: RenderPipeline,
-    indirect_draw_buffer: Buffer,
-    cloud_vertex_buffer: Buffer,
-    density_bind_group: BindGroup,
-    marching_cubes_bind_group: BindGroup,
-    main_bind_group: BindGroup,
-    last_fps_instant: Instant,
-    fps_frame_count: u32,
+    lighting: Lighting,
+    lighting_buffer: Buffer,
+    noise: Noise,
+    // Chunks left out are still in the density texture, but aren't meshed
+    chunks_enabled: [bool; CHUNK_COUNT as usize],
+    chunks: Chunks,
+    mesher: Mesher,
+    shadow_map: ShadowMap,
+    lit_mesh: LitMesh,
+    ssao: Ssao,
+    volume: Volume,
+    sky: Sky,
+    post_processing: PostProcessing,
+    post: PostPasses,
+    render_mode: RenderMode,
+    stats: FrameStats,
+    debug: DebugViews,
+    // Only set when hot reloading is enabled
+    shader_watcher: Option<ShaderWatcher>,
+}
+
+/// What the clouds are drawn as.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderMode {
+    /// The mesh built by the current mesher.
+    #[default]
+    Mesh,
+    /// Raymarching the density directly. Softer, but much more expensive.
+    Volume,
+    /// A short raymarch starting just in front of the mesh,
+    /// which gets most of the volume's look for not much more than the mesh's cost.
+    Hybrid,
 }
 
-const VOXELS_PER_CHUNK_DIM: u32 = 50;
-const VERTICES_PER_VOXEL: u64 = 1 * 3; // Assumes an average of 1 triangle per voxel
+impl RenderMode {
+    pub fn next(self) -> Self {
+        match self {
+            RenderMode::Mesh => RenderMode::Volume,
+            RenderMode::Volume => RenderMode::Hybrid,
+            RenderMode::Hybrid => RenderMode::Mesh,
+        }
+    }
+}
 
 impl CloudWorld {
-    pub fn new(gfx: &Graphics) -> Self {
-        let density_texture_desc = TextureDescriptor {
-            label: Some("density_texture"),
-            size: Extent3d {
-                width: VOXELS_PER_CHUNK_DIM + 1,
-                height: VOXELS_PER_CHUNK_DIM + 1,
-                depth_or_array_layers: VOXELS_PER_CHUNK_DIM + 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D3,
-            format: TextureFormat::Rgba16Float,
-            usage: TextureUsages::STORAGE_BINDING,
-            view_formats: &[TextureFormat::Rgba16Float],
-        };
-        let density_texture = gfx.device().create_texture(&density_texture_desc);
-        let density_texture_view = density_texture.create_view(&wgpu::TextureViewDescriptor {
-            label: Some("density_texture_view"),
-            ..Default::default()
+    pub fn new(gfx: &Graphics, lighting: Lighting, post_processing: PostProcessing) -> Self {
+        let noise = Noise::default();
+        let chunks = Chunks::new(gfx.device(), &noise, ChunksConfig::default());
+        let shadow_map = ShadowMap::new(gfx.device());
+        let camera = Camera::new(gfx);
+        let lighting_buffer = gfx.device().create_buffer_init(&BufferInitDescriptor {
+            label: Some("lighting_uniform_buffer"),
+            contents: bytemuck::bytes_of(&lighting.uniform()),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
+        let lit_mesh = LitMesh::new(
+            gfx,
+            &camera,
+            &lighting_buffer,
+            &shadow_map,
+            post_processing.msaa_samples,
+        );
+        let ssao = Ssao::new(gfx, &camera, lit_mesh.normal());
+        let volume = Volume::new(
+            gfx.device(),
+            &camera,
+            chunks.density_view(),
+            &lighting_buffer,
+        );
+        let sky = Sky::new(gfx.device(), lit_mesh.bind_group_layout());
+        let post = PostPasses::new(gfx, &post_processing);
 
-        // Density generation shader
-        let density_shader = gfx
-            .device()
-            .create_shader_module(wgpu::include_wgsl!("./shaders/cloud_density.wgsl"));
-        let density_bind_group_layout =
-            gfx.device()
-                .create_bind_group_layout(&BindGroupLayoutDescriptor {
-                    label: Some("density_bind_group_layout"),
-                    entries: &[BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::StorageTexture {
-                            access: wgpu::StorageTextureAccess::WriteOnly,
-                            format: TextureFormat::Rgba16Float,
-                            view_dimension: TextureViewDimension::D3,
-                        },
-                        count: None,
-                    }],
-                });
-        let density_pipeline_layout =
-            gfx.device()
-                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("density_pipeline_layout"),
-                    bind_group_layouts: &[&density_bind_group_layout],
-                    push_constant_ranges: &[PushConstantRange {
-                        stages: ShaderStages::COMPUTE,
-                        range: 0..8,
-                    }],
-                });
-        let density_pipeline = gfx
-            .device()
-            .create_compute_pipeline(&ComputePipelineDescriptor {
-                label: Some("density_pipeline"),
-                layout: Some(&density_pipeline_layout),
-                module: &density_shader,
-                entry_point: "main",
-            });
-        let density_bind_group = gfx.device().create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("density_bind_group"),
-            layout: &density_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&density_texture_view),
-            }],
-        });
+        let debug = DebugViews::new(gfx, &camera, chunks.density_view());
 
-        // Marching cubes shader
-        let marching_cubes_shader = gfx
-            .device()
-            .create_shader_module(wgpu::include_wgsl!("./shaders/marching_cubes.wgsl"));
-        let marching_cubes_bind_group_layout =
-            gfx.device()
-                .create_bind_group_layout(&BindGroupLayoutDescriptor {
-                    label: Some("marching_cubes_bind_group_layout"),
-                    entries: &[
-                        // Density data
-                        BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: ShaderStages::COMPUTE,
-                            ty: BindingType::StorageTexture {
-                                access: wgpu::StorageTextureAccess::ReadOnly,
-                                format: TextureFormat::Rgba16Float,
-                                view_dimension: TextureViewDimension::D3,
-                            },
-                            count: None,
-                        },
-                        // Indirect draw buffer
-                        BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: ShaderStages::COMPUTE,
-                            ty: BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: false },
-                                has_dynamic_offset: false,
-                                min_binding_size: None,
-                            },
-                            count: None,
-                        },
-                        // Vertex buffer
-                        BindGroupLayoutEntry {
-                            binding: 2,
-                            visibility: ShaderStages::COMPUTE,
-                            ty: BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: false },
-                                has_dynamic_offset: false,
-                                min_binding_size: None,
-                            },
-                            count: None,
-                        },
-                        // Edge table
-                        BindGroupLayoutEntry {
-                            binding: 3,
-                            visibility: ShaderStages::COMPUTE,
-                            ty: BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: true },
-                                has_dynamic_offset: false,
-                                min_binding_size: None,
-                            },
-                            count: None,
-                        },
-                        // Tri table
-                        BindGroupLayoutEntry {
-                            binding: 4,
-                            visibility: ShaderStages::COMPUTE,
-                            ty: BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: true },
-                                has_dynamic_offset: false,
-                                min_binding_size: None,
-                            },
-                            count: None,
-                        },
-                    ],
-                });
-        let marching_cubes_pipeline_layout =
-            gfx.device()
-                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("marching_cubes_pipeline_layout"),
-                    bind_group_layouts: &[&marching_cubes_bind_group_layout],
-                    push_constant_ranges: &[PushConstantRange {
-                        stages: ShaderStages::COMPUTE,
-                        range: 0..8,
-                    }],
-                });
-        let marching_cubes_pipeline =
-            gfx.device()
-                .create_compute_pipeline(&ComputePipelineDescriptor {
-                    label: Some("marching_cubes_pipeline"),
-                    layout: Some(&marching_cubes_pipeline_layout),
-                    module: &marching_cubes_shader,
-                    entry_point: "main",
-                });
-
-        // Render pipeline
-        let render_bind_group_layout =
-            gfx.device()
-                .create_bind_group_layout(&BindGroupLayoutDescriptor {
-                    label: Some("render_bind_group_layout"),
-                    entries: &[BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::VERTEX,
-                        ty: BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    }],
-                });
-        let render_pipeline_layout =
-            gfx.device()
-                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("render_pipeline_layout"),
-                    bind_group_layouts: &[&render_bind_group_layout],
-                    push_constant_ranges: &[PushConstantRange {
-                        stages: ShaderStages::VERTEX_FRAGMENT,
-                        range: 0..8,
-                    }],
-                });
-        let chunk_render_shader = gfx
-            .device()
-            .create_shader_module(wgpu::include_wgsl!("./shaders/chunk_render.wgsl"));
+        Self {
+            clock: Clock::default(),
+            camera,
+            lighting,
+            lighting_buffer,
+            noise,
+            chunks_enabled: [true; CHUNK_COUNT as usize],
+            chunks,
+            mesher: Mesher::default(),
+            shadow_map,
+            lit_mesh,
+            ssao,
+            volume,
+            sky,
+            post_processing,
+            post,
+            render_mode: RenderMode::default(),
+            stats: FrameStats::new(gfx.device(), gfx.queue()),
+            debug,
+            shader_watcher: shader::hot_reload_enabled().then(ShaderWatcher::new),
+        }
+    }
 
-        const ATTRIBS: [VertexAttribute; 2] = vertex_attr_array![0 => Float32x4, 1 => Float32x4];
-        const CLOUD_VERTEX_SIZE: u64 = 2 * 4 * 4;
+    /// Fits the camera and everything drawn at the surface's size to the resized surface.
+    pub fn resize(&mut self, gfx: &Graphics) {
+        self.camera.resize(gfx);
+        self.post.resize(gfx);
+        self.lit_mesh.resize(gfx);
+        self.ssao.resize(gfx, &self.camera, self.lit_mesh.normal());
+        self.volume.resize(
+            gfx.device(),
+            &self.camera,
+            self.chunks.density_view(),
+            &self.lighting_buffer,
+        );
+    }
 
-        let aligned_vertex_desc = VertexBufferLayout {
-            array_stride: CLOUD_VERTEX_SIZE,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &ATTRIBS,
+    /// Recompiles the pipelines of any shader that changed on disk.
+    /// Does nothing unless hot reloading is enabled.
+    /// If a shader fails to compile, the last good pipeline is kept.
+    pub fn reload_shaders(&mut self, gfx: &Graphics) {
+        let Some(watcher) = self.shader_watcher.as_mut() else {
+            return;
         };
 
-        let render_pipeline =
-            gfx.device()
-                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("chunk_render_pipeline"),
-                    layout: Some(&render_pipeline_layout),
-                    vertex: wgpu::VertexState {
-                        module: &chunk_render_shader,
-                        entry_point: "vs_main",
-                        buffers: &[aligned_vertex_desc],
-                    },
-                    fragment: Some(wgpu::FragmentState {
-                        module: &chunk_render_shader,
-                        entry_point: "fs_main",
-                        targets: &[Some(wgpu::ColorTargetState {
-                            format: gfx.config().format,
-                            blend: Some(wgpu::BlendState::REPLACE),
-                            write_mask: wgpu::ColorWrites::ALL,
-                        })],
-                    }),
-                    primitive: wgpu::PrimitiveState {
-                        topology: wgpu::PrimitiveTopology::TriangleList,
-                        front_face: wgpu::FrontFace::Ccw,
-                        cull_mode: None, //Some(wgpu::Face::Back),
-                        ..Default::default()
-                    },
-                    depth_stencil: Some(DepthStencilState {
-                        format: wgpu::TextureFormat::Depth32Float,
-                        depth_write_enabled: true,
-                        depth_compare: wgpu::CompareFunction::Less,
-                        stencil: Default::default(),
-                        bias: Default::default(),
-                    }),
-                    multisample: wgpu::MultisampleState::default(),
-                    multiview: None,
-                });
-
-        let camera = Camera::new(gfx);
-        let main_bind_group = gfx.device().create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("world_bind_group"),
-            layout: &render_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera.buffer_binding_resource(),
-            }],
-        });
-
-        let cloud_vertex_buffer = gfx.device().create_buffer(&BufferDescriptor {
-            label: Some("cloud_vertex_buffer"),
-            size: VOXELS_PER_CHUNK_DIM as u64
-                * VOXELS_PER_CHUNK_DIM as u64
-                * VOXELS_PER_CHUNK_DIM as u64
-                * CLOUD_VERTEX_SIZE
-                * VERTICES_PER_VOXEL,
-            usage: BufferUsages::STORAGE | BufferUsages::VERTEX,
-            mapped_at_creation: false,
-        });
+        for shader in watcher.poll() {
+            let source = match shader.compose(shader::SHADER_CONSTANTS, true) {
+                Ok(source) => source,
+                Err(e) => {
+                    error!("{:#}", e);
+                    continue;
+                }
+            };
 
-        let indirect_draw_buffer = gfx.device().create_buffer(&BufferDescriptor {
-            label: Some("render_indirect_draw_buffer"),
-            size: std::mem::size_of::<DrawIndirect>() as u64,
-            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT | BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+            let device = gfx.device();
+            match shader {
+                Shader::CloudDensity
+                | Shader::MarchingCubes
+                | Shader::MarchingTetrahedra
+                | Shader::SurfaceNets => self.chunks.reload(device, shader, &source),
+                Shader::ChunkRender => self.lit_mesh.reload(device, &source),
+                // Only drawn by clouds::Clouds, for other renderers
+                Shader::MeshRender => {}
+                Shader::ShadowMap => self.shadow_map.reload(device, &source),
+                Shader::Sky => self.sky.reload(device, &source),
+                Shader::CloudVolume => self.volume.reload(device, &source),
+                Shader::Ssao => self.ssao.reload(device, &source),
+                Shader::Post => self.post.reload(device, &source),
+                Shader::Debug => self.debug.reload(device, &source),
+            }
+        }
+    }
 
-        let edge_table_buffer = gfx.device().create_buffer_init(&BufferInitDescriptor {
-            label: Some("marching_cubes_edge_table_buffer"),
-            contents: bytemuck::cast_slice(&crate::marching_cubes::EDGE_TABLE),
-            usage: BufferUsages::STORAGE,
-        });
+    pub fn handle_key(&mut self, key: KeyCode) {
+        match key {
+            KeyCode::KeyM => {
+                self.mesher = self.mesher.next();
+                info!("Mesher: {:?}", self.mesher);
+            }
+            KeyCode::KeyV => {
+                self.render_mode = self.render_mode.next();
+                info!("Render mode: {:?}", self.render_mode);
+            }
+            KeyCode::KeyD | KeyCode::BracketLeft | KeyCode::BracketRight => {
+                self.debug.handle_key(key)
+            }
+            KeyCode::Space => {
+                self.clock.toggle_pause();
+                info!("Paused: {}", self.clock.paused);
+            }
+            KeyCode::Period => self.clock.step(),
+            KeyCode::Home => self.clock.seek(0.0),
+            KeyCode::KeyB => {
+                self.post_processing.bloom.enabled = !self.post_processing.bloom.enabled;
+                info!("Bloom: {}", self.post_processing.bloom.enabled);
+            }
+            // Half a stop at a time
+            KeyCode::Minus | KeyCode::Equal => {
+                let step = if key == KeyCode::Minus {
+                    std::f32::consts::FRAC_1_SQRT_2
+                } else {
+                    std::f32::consts::SQRT_2
+                };
+                self.post_processing.exposure *= step;
+                info!("Exposure: {:.2}", self.post_processing.exposure);
+            }
+            _ => {}
+        }
+    }
 
-        let tri_table_buffer = gfx.device().create_buffer_init(&BufferInitDescriptor {
-            label: Some("marching_cubes_tri_table_buffer"),
-            contents: bytemuck::cast_slice(&crate::marching_cubes::TRI_TABLE),
-            usage: BufferUsages::STORAGE,
-        });
+    pub fn clock_mut(&mut self) -> &mut Clock {
+        &mut self.clock
+    }
 
-        let marching_cubes_bind_group =
-            gfx.device().create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("marching_cubes_compute_bind_group"),
-                layout: &marching_cubes_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&density_texture_view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: indirect_draw_buffer.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: cloud_vertex_buffer.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 3,
-                        resource: edge_table_buffer.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 4,
-                        resource: tri_table_buffer.as_entire_binding(),
-                    },
-                ],
-            });
+    /// Lays out the overlay's controls for everything that can be changed while running.
+    pub fn tuning_window(&mut self, ctx: &egui::Context) {
+        let meshed = self.meshes_this_frame();
+        overlay::tuning_window(
+            ctx,
+            overlay::Tuning {
+                stats: &self.stats,
+                meshed,
+                mesher: &mut self.mesher,
+                render_mode: &mut self.render_mode,
+                debug_view: &mut self.debug.view,
+                density_slice: &mut self.debug.density_slice,
+                noise: &mut self.noise,
+                clock: &mut self.clock,
+                chunks_enabled: &mut self.chunks_enabled,
+                lighting: &mut self.lighting,
+                post_processing: &mut self.post_processing,
+                orbit: self.camera.orbit_mut(),
+            },
+        );
+    }
 
-        Self {
-            creation_instant: Instant::now(),
-            camera,
-            main_bind_group,
-            density_bind_group,
-            density_pipeline,
-            marching_cubes_pipeline,
-            marching_cubes_bind_group,
-            cloud_vertex_buffer,
-            indirect_draw_buffer,
-            render_pipeline,
-            last_fps_instant: Instant::now(),
-            fps_frame_count: 0,
-        }
+    /// The volume only needs the density, unless a debug view shows the mesh.
+    fn meshes_this_frame(&self) -> bool {
+        self.render_mode != RenderMode::Volume || self.debug.view.draws_mesh()
     }
 
     pub fn update(&mut self) {
-        let world_time = self.creation_instant.elapsed().as_secs_f32();
-
-        let time_since_last_fps = self.last_fps_instant.elapsed().as_secs_f32();
-        // Log FPS every 5 seconds. Set RUST_LOG=info to see the log.
-        if time_since_last_fps >= 5.0 {
-            info!(
-                "FPS: {}",
-                (1.0 / (time_since_last_fps / (self.fps_frame_count as f32))).round()
-            );
-            self.fps_frame_count = 0;
-            self.last_fps_instant = Instant::now();
+        let update_start = Instant::now();
+        let dt = self
+            .stats
+            .begin_update(update_start, self.meshes_this_frame());
+        self.clock.tick(dt);
+        self.camera.update(self.clock.seconds());
+        self.stats.end_update(update_start);
+    }
+
+    /// Draws the clouds in the current render mode and the sky into the HDR target,
+    /// then post processes it onto `output_view`.
+    fn draw_lit_frame(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        output_view: &wgpu::TextureView,
+        world_time: f32,
+    ) {
+        let profiler = self.stats.profiler();
+        let hdr = self.post.hdr();
+        match self.render_mode {
+            RenderMode::Mesh => {
+                // Shadows only fall on the lit mesh
+                self.shadow_map
+                    .render(encoder, profiler, &self.chunks, world_time);
+                self.lit_mesh.render(
+                    encoder,
+                    profiler,
+                    hdr,
+                    &self.camera,
+                    &self.chunks,
+                    world_time,
+                );
+                // Ambient occlusion, from the mesh's depth and normals
+                self.ssao.render(encoder, profiler, &hdr.view);
+            }
+            RenderMode::Volume => self.volume.render(encoder, profiler, &hdr.view, false),
+            RenderMode::Hybrid => {
+                // The hybrid mode only needs the mesh's depth
+                self.lit_mesh.render_depth(
+                    encoder,
+                    profiler,
+                    &self.camera,
+                    &self.chunks,
+                    world_time,
+                );
+                self.volume.render(encoder, profiler, &hdr.view, true);
+            }
         }
-        self.fps_frame_count += 1;
+        // Behind everything drawn so far
+        self.sky
+            .render(encoder, profiler, &hdr.view, self.lit_mesh.bind_group());
+        self.post.render(
+            encoder,
+            profiler,
+            output_view,
+            self.post_processing.bloom.enabled,
+        );
+    }
 
-        self.camera.update(world_time);
+    /// Copies everything changed on the CPU into its uniform buffer.
+    fn write_uniforms(&self, queue: &wgpu::Queue) {
+        self.camera.write_data_buffer(queue);
+        self.post.write_uniform(queue, &self.post_processing);
+        queue.write_buffer(
+            &self.lighting_buffer,
+            0,
+            bytemuck::bytes_of(&self.lighting.uniform()),
+        );
+        self.chunks.write_noise(queue, &self.noise);
+        let (znear, zfar) = self.camera.depth_range();
+        self.shadow_map.write_data_buffers(
+            queue,
+            &self.camera.inverse_view_projection_matrix(),
+            znear,
+            zfar,
+            &glm::Vec3::from(self.lighting.sun.direction),
+        );
+        self.debug.write_uniform(queue);
     }
 
-    pub fn render(&self, gfx: &Graphics) -> anyhow::Result<(), SurfaceError> {
-        let output = gfx.surface().get_current_texture()?;
+    /// Renders the frame, then hands it to `finish` to draw over or copy before it's presented.
+    pub fn render(
+        &self,
+        gfx: &Graphics,
+        finish: impl FnOnce(&mut wgpu::CommandEncoder, &wgpu::Texture, &wgpu::TextureView),
+    ) -> anyhow::Result<(), SurfaceError> {
+        let output = self.stats.begin_frame(gfx.device(), || gfx.frame())?;
+        let encode_start = Instant::now();
         let output_view = output
-            .texture
+            .texture()
             .create_view(&wgpu::TextureViewDescriptor::default());
 
-        self.camera.write_data_buffer(gfx.queue());
-        let world_time = self.creation_instant.elapsed().as_secs_f32();
-
-        // Render a 2x2x2 grid of chunks in 8 render passes.
-        // Each chunk saturates the GPU with work.
-        // The workgroup counts are conditioned on the workgroup sizes
-        // to cover every voxel in the chunk without going over GPU limits.
-        for chunk_id in 0..8 {
-            let push_constants_slice = &[world_time, bytemuck::cast::<u32, f32>(chunk_id)];
-            let push_constants = bytemuck::cast_slice(push_constants_slice);
-
-            // Clear the indirect draw buffer
-            // See wgpu::DrawIndirect
-            gfx.queue().write_buffer(
-                &self.indirect_draw_buffer,
-                0,
-                bytemuck::cast_slice(&[0_u32, 1_u32, 0_u32, 0_u32]),
+        self.write_uniforms(gfx.queue());
+        self.chunks.clear_draws(gfx.queue());
+        let world_time = self.clock.seconds();
+
+        let mut encoder = gfx
+            .device()
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("cloud_render_command_encoder"),
+            });
+        let profiler = self.stats.profiler();
+        self.chunks.build(
+            &mut encoder,
+            world_time,
+            self.mesher,
+            |chunk_id| self.meshes_this_frame() && self.chunks_enabled[chunk_id as usize],
+            |pass, _| profiler.compute_pass(pass),
+        );
+        if self.debug.view == DebugView::Off {
+            self.draw_lit_frame(&mut encoder, &output_view, world_time);
+        } else {
+            self.debug.render(
+                &mut encoder,
+                self.stats.profiler(),
+                &output_view,
+                &self.camera,
+                &self.chunks,
+                world_time,
             );
+        }
+        finish(&mut encoder, output.texture(), &output_view);
+        self.stats.submit(
+            gfx,
+            encoder,
+            encode_start,
+            self.meshes_this_frame()
+                .then(|| self.chunks.indirect_draw_buffer()),
+            || output.present(),
+        );
 
-            let mut encoder =
-                gfx.device()
-                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                        label: Some("cloud_render_command_encoder"),
-                    });
+        Ok(())
+    }
+}
 
-            // Generate density data
-            // This step operates on the corners of the voxels
-            {
-                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                    label: Some("cloud_density_pass"),
-                    timestamp_writes: None,
-                });
-                compute_pass.set_pipeline(&self.density_pipeline);
-                compute_pass.set_push_constants(0, push_constants);
-                compute_pass.set_bind_group(0, &self.density_bind_group, &[]);
-                compute_pass.dispatch_workgroups(7, 7, 7);
-            }
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use pollster::FutureExt as _;
+    use wgpu::BufferDescriptor;
+
+    use super::*;
+    use crate::chunks::{
+        indirect_draw_offset, Vertex, CHUNK_VERTEX_BUFFER_SIZE, CLOUD_VERTEX_SIZE,
+        DENSITY_REGION_DIM, DENSITY_TEXTURE_DIM, VOXELS_PER_CHUNK_DIM,
+    };
+
+    type Triangle = [[f32; 3]; 3];
+
+    // Both meshers interpolate the same half floats, so any difference is rounding
+    const POSITION_TOLERANCE: f32 = 0.001;
+    const DENSITY_TEXEL_SIZE: u32 = 8;
+
+    /// Copies out of the GPU with `copy` and waits for the `size` bytes it wrote.
+    fn read_back(
+        gfx: &Graphics,
+        size: u64,
+        copy: impl FnOnce(&mut wgpu::CommandEncoder, &Buffer),
+    ) -> Vec<u8> {
+        let staging_buffer = gfx.device().create_buffer(&BufferDescriptor {
+            label: Some("test_staging_buffer"),
+            size,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = gfx
+            .device()
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        copy(&mut encoder, &staging_buffer);
+        gfx.queue().submit(std::iter::once(encoder.finish()));
+
+        let (sender, receiver) = flume::bounded(1);
+        staging_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                sender.send(result).unwrap()
+            });
+        gfx.device().poll(wgpu::Maintain::Wait);
+        receiver.recv().unwrap().unwrap();
+        let bytes = staging_buffer.slice(..).get_mapped_range().to_vec();
+        staging_buffer.unmap();
+        bytes
+    }
+
+    /// The density and its gradient at every texel, indexed by `densityTexel`.
+    fn read_density(gfx: &Graphics, cloud_world: &CloudWorld) -> impl Fn([u32; 3]) -> [f32; 4] {
+        let bytes_per_row = (DENSITY_TEXTURE_DIM * DENSITY_TEXEL_SIZE)
+            .next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let size = bytes_per_row as u64 * DENSITY_TEXTURE_DIM as u64 * DENSITY_TEXTURE_DIM as u64;
+        let texels = read_back(gfx, size, |encoder, staging_buffer| {
+            encoder.copy_texture_to_buffer(
+                cloud_world.chunks.density_texture().as_image_copy(),
+                wgpu::ImageCopyBuffer {
+                    buffer: staging_buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(bytes_per_row),
+                        rows_per_image: Some(DENSITY_TEXTURE_DIM),
+                    },
+                },
+                cloud_world.chunks.density_texture().size(),
+            )
+        });
+        move |[x, y, z]| {
+            let row = (z * DENSITY_TEXTURE_DIM + y) * bytes_per_row;
+            let texel = (row + x * DENSITY_TEXEL_SIZE) as usize;
+            std::array::from_fn(|channel| {
+                let byte = texel + channel * 2;
+                half::f16::from_le_bytes([texels[byte], texels[byte + 1]]).to_f32()
+            })
+        }
+    }
 
-            // Marching cubes
-            // This step operates on the centers of the voxels
+    /// Each chunk's triangles, as the mesher wrote them.
+    fn read_meshes(gfx: &Graphics, cloud_world: &CloudWorld) -> Vec<Vec<Triangle>> {
+        let draw_commands = read_back(
+            gfx,
+            cloud_world.chunks.indirect_draw_buffer().size(),
+            |encoder, staging_buffer| {
+                encoder.copy_buffer_to_buffer(
+                    cloud_world.chunks.indirect_draw_buffer(),
+                    0,
+                    staging_buffer,
+                    0,
+                    staging_buffer.size(),
+                )
+            },
+        );
+        let vertices = read_back(
+            gfx,
+            cloud_world.chunks.vertex_buffer().size(),
+            |encoder, staging_buffer| {
+                encoder.copy_buffer_to_buffer(
+                    cloud_world.chunks.vertex_buffer(),
+                    0,
+                    staging_buffer,
+                    0,
+                    staging_buffer.size(),
+                )
+            },
+        );
+        (0..CHUNK_COUNT)
+            .map(|chunk_id| {
+                let offset = indirect_draw_offset(chunk_id) as usize;
+                // The vertex count comes first in each draw command
+                let vertex_count =
+                    bytemuck::pod_read_unaligned::<u32>(&draw_commands[offset..offset + 4]) as u64;
+                assert!(
+                    vertex_count * CLOUD_VERTEX_SIZE <= CHUNK_VERTEX_BUFFER_SIZE,
+                    "Chunk {} wrote {} vertices, past the end of its vertex buffer",
+                    chunk_id,
+                    vertex_count
+                );
+                let start = ChunksConfig::default().chunk_vertex_offset(chunk_id) as usize;
+                let end = start + (vertex_count * CLOUD_VERTEX_SIZE) as usize;
+                bytemuck::cast_slice::<_, Vertex>(&vertices[start..end])
+                    .chunks_exact(3)
+                    .map(|triangle| {
+                        std::array::from_fn(|i| {
+                            let [x, y, z, _] = triangle[i].position;
+                            [x, y, z]
+                        })
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Rounded and rotated to start at the same vertex, so matching triangles have the same key
+    /// whichever vertex the mesher wrote first.
+    fn triangle_key(triangle: &Triangle) -> [[i32; 3]; 3] {
+        let rounded =
+            triangle.map(|vertex| vertex.map(|c| (c / POSITION_TOLERANCE).round() as i32));
+        let first = (0..3).min_by_key(|&i| rounded[i]).unwrap();
+        std::array::from_fn(|i| rounded[(first + i) % 3])
+    }
+
+    fn triangles_match(a: &Triangle, b: &Triangle) -> bool {
+        (0..3).any(|rotation| {
+            (0..3).all(|i| {
+                (0..3).all(|axis| {
+                    (a[i][axis] - b[(i + rotation) % 3][axis]).abs() <= POSITION_TOLERANCE
+                })
+            })
+        })
+    }
+
+    /// The triangles in only one of the meshes, ignoring the order they were written in.
+    fn unmatched_triangles(
+        expected: &[Triangle],
+        actual: &[Triangle],
+    ) -> (Vec<Triangle>, Vec<Triangle>) {
+        let mut expected_by_key: HashMap<_, Vec<Triangle>> = HashMap::new();
+        for triangle in expected {
+            expected_by_key
+                .entry(triangle_key(triangle))
+                .or_default()
+                .push(*triangle);
+        }
+        let mut unexpected: Vec<Triangle> = actual
+            .iter()
+            .filter(|triangle| {
+                expected_by_key
+                    .get_mut(&triangle_key(triangle))
+                    .and_then(Vec::pop)
+                    .is_none()
+            })
+            .copied()
+            .collect();
+        let mut missing: Vec<Triangle> = expected_by_key.into_values().flatten().collect();
+        // Rounding can put the same vertex on either side of a key's boundary
+        unexpected.retain(|triangle| {
+            match missing
+                .iter()
+                .position(|other| triangles_match(triangle, other))
             {
-                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                    label: Some("marching_cubes_compute_pass"),
-                    timestamp_writes: None,
-                });
-                compute_pass.set_pipeline(&self.marching_cubes_pipeline);
-                compute_pass.set_push_constants(0, push_constants);
-                compute_pass.set_bind_group(0, &self.marching_cubes_bind_group, &[]);
-                compute_pass.dispatch_workgroups(5, 5, 5);
+                Some(index) => {
+                    missing.swap_remove(index);
+                    false
+                }
+                None => true,
             }
+        });
+        (missing, unexpected)
+    }
 
-            // Render mesh
-            {
-                let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-                    label: Some("cloud_render_pass"),
-                    color_attachments: &[Some(RenderPassColorAttachment {
-                        view: &output_view,
-                        resolve_target: None,
-                        ops: Operations {
-                            load: if chunk_id == 0 {
-                                LoadOp::Clear(Color::BLACK)
-                            } else {
-                                LoadOp::Load
-                            },
-                            store: StoreOp::Store,
-                        },
-                    })],
-                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                        view: &self.camera.depth_texture().view,
-                        depth_ops: Some(wgpu::Operations {
-                            load: if chunk_id == 0 {
-                                wgpu::LoadOp::Clear(1.0)
-                            } else {
-                                wgpu::LoadOp::Load
-                            },
-                            store: wgpu::StoreOp::Store,
-                        }),
-                        stencil_ops: None,
-                    }),
-                    ..Default::default()
-                });
-                render_pass.set_pipeline(&self.render_pipeline);
-                render_pass.set_push_constants(ShaderStages::VERTEX_FRAGMENT, 0, push_constants);
-                render_pass.set_bind_group(0, &self.main_bind_group, &[]);
-                render_pass.set_vertex_buffer(0, self.cloud_vertex_buffer.slice(..));
-                render_pass.draw_indirect(&self.indirect_draw_buffer, 0);
+    #[test]
+    fn rotated_and_nudged_triangles_match() {
+        let triangle = [[0.0, 0.5, 0.0], [1.0, 0.0, 0.25], [0.0, 0.0, 1.0]];
+        let rotated = [triangle[1], triangle[2], triangle[0]];
+        let mut nudged = triangle;
+        nudged[1][2] += POSITION_TOLERANCE * 0.6;
+        let (missing, unexpected) = unmatched_triangles(&[triangle, triangle], &[rotated, nudged]);
+        assert!(missing.is_empty() && unexpected.is_empty());
+
+        // Flipped triangles face the other way
+        let flipped = [triangle[0], triangle[2], triangle[1]];
+        let (missing, unexpected) = unmatched_triangles(&[triangle], &[flipped]);
+        assert_eq!((missing.len(), unexpected.len()), (1, 1));
+    }
+
+    /// Meshes a frame with `mesher` and checks that every chunk has the triangles `cpu_mesh` builds
+    /// from the chunk's density and gradient, given at each corner like loadDensity.
+    fn assert_gpu_mesh_matches(
+        mesher: Mesher,
+        cpu_mesh: impl Fn(&dyn Fn([i32; 3]) -> [f32; 4]) -> Vec<[f32; 3]>,
+    ) {
+        let gfx = Graphics::new_fallback(64, 64)
+            .block_on()
+            .expect("The mesh parity tests need a software adapter, like Mesa's lavapipe");
+        let mut cloud_world = CloudWorld::new(&gfx, Lighting::default(), PostProcessing::default());
+        cloud_world.mesher = mesher;
+        cloud_world.clock.paused = true;
+        cloud_world.clock.seek(3.0);
+        cloud_world.update();
+        cloud_world.render(&gfx, |_, _, _| {}).unwrap();
+
+        let density = read_density(&gfx, &cloud_world);
+        let meshes = read_meshes(&gfx, &cloud_world);
+        let mut failures = Vec::new();
+        for (chunk_id, gpu_mesh) in meshes.iter().enumerate() {
+            let chunk = [chunk_id & 1, (chunk_id >> 1) & 1, (chunk_id >> 2) & 1]
+                .map(|c| (c as u32 * DENSITY_REGION_DIM) as i32);
+            // The same texels loadDensity reads, see densityTexel
+            let chunk_density = |corner: [i32; 3]| {
+                density(std::array::from_fn(|axis| {
+                    (chunk[axis] + corner[axis] + 1) as u32
+                }))
+            };
+            let cpu_mesh: Vec<Triangle> = cpu_mesh(&chunk_density)
+                .chunks_exact(3)
+                .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+                .collect();
+
+            let (missing, unexpected) = unmatched_triangles(&cpu_mesh, gpu_mesh);
+            if !missing.is_empty() || !unexpected.is_empty() {
+                failures.push(format!(
+                    "Chunk {}: {} triangles on the CPU, {} on the GPU, {} only on the CPU like {:?}, {} only on the GPU like {:?}",
+                    chunk_id,
+                    cpu_mesh.len(),
+                    gpu_mesh.len(),
+                    missing.len(),
+                    missing.first(),
+                    unexpected.len(),
+                    unexpected.first(),
+                ));
             }
-            gfx.queue().submit(std::iter::once(encoder.finish()));
         }
-        output.present();
-
-        // Uncomment below to read back the vertex buffer.
-        //
-        // let (rx, tx) = flume::bounded::<Result<(), BufferAsyncError>>(1);
-        // let draw_buffer_slice = self.cloud_vertex_buffer.slice(..);
-        // draw_buffer_slice.map_async(wgpu::MapMode::Read, move |result| rx.send(result).unwrap());
-        // gfx.device().poll(wgpu::Maintain::Wait);
-        // pollster::block_on(tx.recv_async()).unwrap().unwrap();
-        // {
-        //     let draw_buffer_view = draw_buffer_slice.get_mapped_range();
-        //     draw_buffer_view
-        //         .chunks_exact(std::mem::size_of::<Vertex>())
-        //         .map(|chunk| {
-        //             let vertex = bytemuck::from_bytes::<Vertex>(chunk);
-        //             println!("{:?}", vertex);
-        //         })
-        //         .count();
-        // }
-        // self.cloud_vertex_buffer.unmap();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
 
-        Ok(())
+    #[test]
+    #[ignore = "meshes on the CPU, run with --release -- --ignored cpu_mesher"]
+    fn gpu_marching_cubes_matches_the_cpu_mesher() {
+        assert_gpu_mesh_matches(Mesher::MarchingCubes, |density| {
+            crate::marching_cubes::mesh(VOXELS_PER_CHUNK_DIM, 0.5, |corner| {
+                density(corner.map(|c| c as i32))[0]
+            })
+        });
+    }
+
+    #[test]
+    #[ignore = "meshes on the CPU, run with --release -- --ignored cpu_mesher"]
+    fn gpu_marching_tetrahedra_matches_the_cpu_mesher() {
+        assert_gpu_mesh_matches(Mesher::MarchingTetrahedra, |density| {
+            crate::marching_tetrahedra::mesh(VOXELS_PER_CHUNK_DIM, 0.5, |corner| {
+                density(corner.map(|c| c as i32))[0]
+            })
+        });
+    }
+
+    #[test]
+    #[ignore = "meshes on the CPU, run with --release -- --ignored cpu_mesher"]
+    fn gpu_surface_nets_matches_the_cpu_mesher() {
+        assert_gpu_mesh_matches(Mesher::SurfaceNets, |density| {
+            crate::surface_nets::mesh(VOXELS_PER_CHUNK_DIM, 0.5, false, density)
+        });
+    }
+
+    #[test]
+    #[ignore = "meshes on the CPU, run with --release -- --ignored cpu_mesher"]
+    fn gpu_dual_contouring_matches_the_cpu_mesher() {
+        assert_gpu_mesh_matches(Mesher::DualContouring, |density| {
+            crate::surface_nets::mesh(VOXELS_PER_CHUNK_DIM, 0.5, true, density)
+        });
     }
 }
@@ -0,0 +1,447 @@
+//! The clouds on their own, for drawing them with a device, queue and passes owned by another
+//! renderer. `window::run` is the whole demo, this is just the density, meshing and lit mesh.
+//!
+//! Create the device with [`required_features`] and [`required_limits`], then every frame:
+//! [`Clouds::prepare`] with the camera, [`Clouds::mesh`] into a command encoder outside of any
+//! pass, and [`Clouds::draw`] into a render pass matching the formats in [`CloudsConfig`].
+//! The clouds fill the cube from -8 to 8 along each axis, centered on the origin. Fold where they
+//! sit in the scene into the view projection.
+
+use wgpu::util::DeviceExt as _;
+
+use crate::{
+    camera::CameraUniform,
+    chunks::{
+        self, Chunks, ChunksConfig, CHUNK_COUNT, MESHER_WORKGROUP_DIM, PUSH_CONSTANTS_SIZE,
+        VERTICES_PER_VOXEL,
+    },
+    error::Error,
+    lighting::Lighting,
+    noise::Noise,
+    shader::{self, Shader},
+};
+
+pub use crate::chunks::{Mesher, Vertex, CLOUD_VERTEX_BUFFER_LAYOUT as VERTEX_LAYOUT};
+
+/// Features the device has to be created with.
+pub fn required_features() -> wgpu::Features {
+    // Reading the density back from an Rgba16Float storage texture needs the adapter's formats
+    wgpu::Features::PUSH_CONSTANTS | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+}
+
+/// The default limits, raised to what the clouds need.
+pub fn required_limits() -> wgpu::Limits {
+    wgpu::Limits {
+        max_push_constant_size: PUSH_CONSTANTS_SIZE,
+        max_compute_invocations_per_workgroup: MESHER_WORKGROUP_DIM.pow(3),
+        ..Default::default()
+    }
+}
+
+/// How the clouds are meshed and what they're drawn into. Fixed once the clouds are created.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CloudsConfig {
+    /// Voxels along each side of each of the 2x2x2 chunks.
+    pub voxels_per_chunk_dim: u32,
+    pub mesher: Mesher,
+    /// Room in the vertex buffer, as an average per voxel. Vertices past it are dropped.
+    pub vertices_per_voxel: u64,
+    /// The color target of the render pass the clouds are drawn in.
+    pub color_format: wgpu::TextureFormat,
+    /// The depth target of that pass, if it has one. The clouds test and write depth.
+    pub depth_format: Option<wgpu::TextureFormat>,
+    pub sample_count: u32,
+}
+
+impl Default for CloudsConfig {
+    fn default() -> Self {
+        Self {
+            voxels_per_chunk_dim: 50,
+            mesher: Mesher::default(),
+            vertices_per_voxel: VERTICES_PER_VOXEL,
+            color_format: wgpu::TextureFormat::Rgba16Float,
+            depth_format: Some(wgpu::TextureFormat::Depth32Float),
+            sample_count: 1,
+        }
+    }
+}
+
+/// Where the clouds are seen from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct View {
+    /// Column major, from the clouds' space to clip space.
+    pub view_projection: [[f32; 4]; 4],
+    /// The eye in the clouds' space, for the translucency and rim light.
+    pub eye: [f32; 3],
+}
+
+/// One chunk's mesh, for drawing it with another pipeline, like into a shadow map.
+/// Positions are in voxels from the chunk's corner, see [`ChunkMesh::offset`].
+pub struct ChunkMesh<'a> {
+    pub chunk_id: u32,
+    /// Laid out as [`VERTEX_LAYOUT`].
+    pub vertices: wgpu::BufferSlice<'a>,
+    /// Holds a `wgpu::util::DrawIndirect` at `indirect_offset`.
+    pub indirect_buffer: &'a wgpu::Buffer,
+    pub indirect_offset: u64,
+    /// Voxels from the first chunk's corner to this one's. A position `p` is at
+    /// `((p + offset) / voxels_per_chunk_dim - 1) * 8` in the clouds' space.
+    pub offset: [f32; 3],
+}
+
+/// Builds a mesh of the clouds on the GPU every frame and draws it lit by the sun.
+pub struct Clouds {
+    config: CloudsConfig,
+    /// Written to the GPU by `prepare`.
+    pub noise: Noise,
+    /// Written to the GPU by `prepare`. The fog isn't drawn.
+    pub lighting: Lighting,
+    chunks: Chunks,
+    camera_buffer: wgpu::Buffer,
+    lighting_buffer: wgpu::Buffer,
+    render_pipeline: wgpu::RenderPipeline,
+    render_bind_group: wgpu::BindGroup,
+}
+
+impl Clouds {
+    /// Fails if the device wasn't created with what the clouds need.
+    pub fn new(device: &wgpu::Device, config: CloudsConfig) -> Result<Self, Error> {
+        check_device(device.features(), &device.limits(), &config)?;
+        let noise = Noise::default();
+        let chunks = Chunks::new(device, &noise, chunks_config(&config));
+
+        // Rendering
+        let uniform_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let render_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("clouds_render_bind_group_layout"),
+                entries: &[
+                    // Camera
+                    uniform_entry(0, wgpu::ShaderStages::VERTEX_FRAGMENT),
+                    // Lighting
+                    uniform_entry(1, wgpu::ShaderStages::FRAGMENT),
+                ],
+            });
+        let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("clouds_camera_uniform_buffer"),
+            size: std::mem::size_of::<CameraUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let lighting = Lighting::default();
+        let lighting_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("clouds_lighting_uniform_buffer"),
+            contents: bytemuck::bytes_of(&lighting.uniform()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("clouds_render_bind_group"),
+            layout: &render_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: lighting_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let render_pipeline = create_render_pipeline(
+            device,
+            &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("clouds_render_pipeline_layout"),
+                bind_group_layouts: &[&render_bind_group_layout],
+                push_constant_ranges: &[wgpu::PushConstantRange {
+                    stages: wgpu::ShaderStages::VERTEX,
+                    range: 0..PUSH_CONSTANTS_SIZE,
+                }],
+            }),
+            &shader::create_shader_module_with_resolution(
+                device,
+                Shader::MeshRender,
+                config.voxels_per_chunk_dim,
+            ),
+            &config,
+        );
+
+        Ok(Self {
+            config,
+            noise,
+            lighting,
+            chunks,
+            camera_buffer,
+            lighting_buffer,
+            render_pipeline,
+            render_bind_group,
+        })
+    }
+
+    pub fn config(&self) -> &CloudsConfig {
+        &self.config
+    }
+
+    /// Writes the view, noise and lighting, and empties last frame's meshes.
+    /// Call once a frame, before submitting the encoder `mesh` recorded into.
+    pub fn prepare(&self, queue: &wgpu::Queue, view: &View) {
+        let camera = CameraUniform::from_view_projection(
+            &glm::Mat4::from(view.view_projection),
+            &glm::Vec3::from(view.eye),
+        );
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::bytes_of(&camera));
+        self.chunks.write_noise(queue, &self.noise);
+        queue.write_buffer(
+            &self.lighting_buffer,
+            0,
+            bytemuck::bytes_of(&self.lighting.uniform()),
+        );
+        self.chunks.clear_draws(queue);
+    }
+
+    /// Records the density and meshing passes of every chunk, at `seconds` into the noise's drift.
+    pub fn mesh(&self, encoder: &mut wgpu::CommandEncoder, seconds: f32) {
+        self.chunks
+            .build(encoder, seconds, self.config.mesher, |_| true, |_, _| None);
+    }
+
+    /// Draws the lit mesh. The pass's targets have to match the config.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &self.render_bind_group, &[]);
+        // Drawing only reads the chunk ids
+        self.chunks.draw(render_pass, 0.0);
+    }
+
+    /// The meshes written by the last `mesh`.
+    pub fn chunk_meshes(&self) -> impl Iterator<Item = ChunkMesh<'_>> {
+        let voxels = self.config.voxels_per_chunk_dim as f32;
+        (0..CHUNK_COUNT).map(move |chunk_id| ChunkMesh {
+            chunk_id,
+            vertices: self.chunks.chunk_vertices(chunk_id),
+            indirect_buffer: self.chunks.indirect_draw_buffer(),
+            indirect_offset: chunks::indirect_draw_offset(chunk_id),
+            // Same order as chunkCoord in common.wgsl
+            offset: [0, 1, 2].map(|axis| ((chunk_id >> axis) & 1) as f32 * voxels),
+        })
+    }
+}
+
+fn chunks_config(config: &CloudsConfig) -> ChunksConfig {
+    ChunksConfig {
+        voxels_per_chunk_dim: config.voxels_per_chunk_dim,
+        chunk_count: CHUNK_COUNT,
+        vertices_per_voxel: config.vertices_per_voxel,
+        share_vertices: false,
+    }
+}
+
+fn check_device(
+    features: wgpu::Features,
+    limits: &wgpu::Limits,
+    config: &CloudsConfig,
+) -> Result<(), Error> {
+    let missing = required_features() - features;
+    if !missing.is_empty() {
+        return Err(Error::MissingFeatures(missing));
+    }
+    let required = required_limits();
+    let too_low = |limit, required: u32, supported: u32| {
+        if supported < required {
+            Err(Error::LimitTooLow {
+                limit,
+                required: required as u64,
+                supported: supported as u64,
+            })
+        } else {
+            Ok(())
+        }
+    };
+    too_low(
+        "max_push_constant_size",
+        required.max_push_constant_size,
+        limits.max_push_constant_size,
+    )?;
+    too_low(
+        "max_compute_invocations_per_workgroup",
+        required.max_compute_invocations_per_workgroup,
+        limits.max_compute_invocations_per_workgroup,
+    )?;
+    chunks_config(config).check(limits)
+}
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    config: &CloudsConfig,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("clouds_render_pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vs_main",
+            buffers: &[VERTEX_LAYOUT],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: config.color_format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: config.depth_format.map(|format| wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: config.sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pollster::FutureExt as _;
+
+    use super::*;
+    use crate::graphics::Graphics;
+
+    #[test]
+    fn devices_missing_what_the_clouds_need_are_refused() {
+        let config = CloudsConfig::default();
+        assert_eq!(
+            check_device(required_features(), &required_limits(), &config),
+            Ok(())
+        );
+        assert_eq!(
+            check_device(wgpu::Features::PUSH_CONSTANTS, &required_limits(), &config),
+            Err(Error::MissingFeatures(
+                wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+            ))
+        );
+        assert!(matches!(
+            check_device(required_features(), &wgpu::Limits::default(), &config),
+            Err(Error::LimitTooLow { .. })
+        ));
+        let too_big = CloudsConfig {
+            voxels_per_chunk_dim: 200,
+            ..config
+        };
+        assert!(matches!(
+            check_device(required_features(), &required_limits(), &too_big),
+            Err(Error::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    #[ignore = "renders on the CPU, run with --release -- --ignored clouds"]
+    fn clouds_draw_into_the_callers_pass() {
+        const SIZE: u32 = 64;
+        let gfx = Graphics::new_fallback(SIZE, SIZE)
+            .block_on()
+            .expect("Drawing the clouds needs a software adapter, like Mesa's lavapipe");
+        let device = gfx.device();
+        let config = CloudsConfig {
+            voxels_per_chunk_dim: 20,
+            color_format: wgpu::TextureFormat::Rgba8Unorm,
+            ..Default::default()
+        };
+        let clouds = Clouds::new(device, config).unwrap();
+        let target = |format, label| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: SIZE,
+                    height: SIZE,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            })
+        };
+        let color = target(config.color_format, "test_color_target");
+        let color_view = color.create_view(&Default::default());
+        let depth = target(config.depth_format.unwrap(), "test_depth_target");
+        let depth_view = depth.create_view(&Default::default());
+
+        let view_projection = glm::perspective(1.0, 1.0, 0.1, 100.0)
+            * glm::look_at(
+                &glm::vec3(0.0, 4.0, 24.0),
+                &glm::Vec3::zeros(),
+                &glm::Vec3::y(),
+            );
+        clouds.prepare(
+            gfx.queue(),
+            &View {
+                view_projection: view_projection.into(),
+                eye: [0.0, 4.0, 24.0],
+            },
+        );
+        let mut encoder = device.create_command_encoder(&Default::default());
+        clouds.mesh(&mut encoder, 0.0);
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("test_render_pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &color_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            clouds.draw(&mut render_pass);
+        }
+        gfx.queue().submit(std::iter::once(encoder.finish()));
+
+        let pixels = crate::capture::read_texture(&gfx, &color);
+        let lit = pixels
+            .chunks_exact(4)
+            .filter(|pixel| pixel[..3] != [0, 0, 0])
+            .count();
+        assert!(
+            lit > pixels.len() / 4 / 10,
+            "Only {} pixels were drawn",
+            lit
+        );
+    }
+}
@@ -0,0 +1,329 @@
+use log::info;
+use wgpu::{
+    BindGroup, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferDescriptor,
+    BufferUsages, Color, DepthStencilState, LoadOp, Operations, PipelineLayout, PushConstantRange,
+    RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, ShaderModule, ShaderStages,
+    StoreOp, TextureFormat, TextureViewDimension,
+};
+use winit::keyboard::KeyCode;
+
+use crate::{
+    camera::Camera,
+    chunks::{Chunks, CLOUD_VERTEX_BUFFER_LAYOUT, DENSITY_TEXTURE_DIM, PUSH_CONSTANTS_SIZE},
+    fullscreen,
+    graphics::Graphics,
+    profiler::{GpuPass, Profiler},
+    shader::{self, Shader},
+};
+
+// Texels moved through the density texture per key press in the density slice debug view
+const DENSITY_SLICE_STEP: u32 = 4;
+
+// Mirrors DebugUniform in shaders/debug.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DebugUniform {
+    pub view: u32,
+    pub slice: u32,
+    pub _padding: [u32; 2],
+}
+
+shader::assert_wgsl_layout!(DebugUniform, 16, { view: 0, slice: 4, _padding: 8 });
+
+/// Views for seeing why the mesh looks the way it does, drawn instead of the lit frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum DebugView {
+    #[default]
+    Off = 0,
+    /// The edges of every triangle, over the mesh.
+    Wireframe = 1,
+    /// The mesh's world space normals as colors.
+    Normals = 2,
+    /// Every chunk's mesh in its own color.
+    ChunkTint = 3,
+    /// Hotter where the triangles are smaller on screen.
+    TriangleDensity = 4,
+    /// One slice of the density texture, with the surface outlined.
+    DensitySlice = 5,
+}
+
+impl DebugView {
+    pub fn next(self) -> Self {
+        match self {
+            DebugView::Off => DebugView::Wireframe,
+            DebugView::Wireframe => DebugView::Normals,
+            DebugView::Normals => DebugView::ChunkTint,
+            DebugView::ChunkTint => DebugView::TriangleDensity,
+            DebugView::TriangleDensity => DebugView::DensitySlice,
+            DebugView::DensitySlice => DebugView::Off,
+        }
+    }
+
+    pub fn draws_mesh(self) -> bool {
+        !matches!(self, DebugView::Off | DebugView::DensitySlice)
+    }
+}
+
+/// The current debug view, and the passes drawing it onto the surface.
+pub(crate) struct DebugViews {
+    pub view: DebugView,
+    /// Depth of the density slice debug view, in texels.
+    pub density_slice: u32,
+    buffer: wgpu::Buffer,
+    bind_group: BindGroup,
+    mesh_pipeline_layout: PipelineLayout,
+    slice_pipeline_layout: PipelineLayout,
+    surface_format: TextureFormat,
+    mesh_pipeline: RenderPipeline,
+    slice_pipeline: RenderPipeline,
+}
+
+impl DebugViews {
+    pub fn new(gfx: &Graphics, camera: &Camera, density: &wgpu::TextureView) -> Self {
+        let device = gfx.device();
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("debug_uniform_buffer"),
+            size: std::mem::size_of::<DebugUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("debug_bind_group_layout"),
+            entries: &[
+                // Camera
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Debug view
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Density, read without filtering
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let mesh_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("debug_mesh_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[PushConstantRange {
+                stages: ShaderStages::VERTEX,
+                range: 0..PUSH_CONSTANTS_SIZE,
+            }],
+        });
+        let slice_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("density_slice_pipeline_layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let surface_format = gfx.config().format;
+        let (mesh_pipeline, slice_pipeline) = create_pipelines(
+            device,
+            &mesh_pipeline_layout,
+            &slice_pipeline_layout,
+            &shader::create_shader_module(device, Shader::Debug),
+            surface_format,
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("debug_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera.buffer_binding_resource(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(density),
+                },
+            ],
+        });
+
+        Self {
+            view: DebugView::default(),
+            density_slice: DENSITY_TEXTURE_DIM / 2,
+            buffer,
+            bind_group,
+            mesh_pipeline_layout,
+            slice_pipeline_layout,
+            surface_format,
+            mesh_pipeline,
+            slice_pipeline,
+        }
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device, source: &str) {
+        if let Some((mesh, slice)) =
+            shader::try_create_pipeline(device, Shader::Debug, source, |module| {
+                create_pipelines(
+                    device,
+                    &self.mesh_pipeline_layout,
+                    &self.slice_pipeline_layout,
+                    module,
+                    self.surface_format,
+                )
+            })
+        {
+            self.mesh_pipeline = mesh;
+            self.slice_pipeline = slice;
+        }
+    }
+
+    /// D cycles through the views, the brackets move the density slice.
+    pub fn handle_key(&mut self, key: KeyCode) {
+        match key {
+            KeyCode::KeyD => {
+                self.view = self.view.next();
+                info!("Debug view: {:?}", self.view);
+            }
+            KeyCode::BracketLeft | KeyCode::BracketRight => {
+                self.density_slice = if key == KeyCode::BracketLeft {
+                    self.density_slice.saturating_sub(DENSITY_SLICE_STEP)
+                } else {
+                    (self.density_slice + DENSITY_SLICE_STEP).min(DENSITY_TEXTURE_DIM - 1)
+                };
+                info!("Density slice: {}", self.density_slice);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::bytes_of(&DebugUniform {
+                view: self.view as u32,
+                slice: self.density_slice,
+                _padding: [0; 2],
+            }),
+        );
+    }
+
+    /// Draws the debug view straight onto `target`, in place of everything else.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        profiler: &Profiler,
+        target: &wgpu::TextureView,
+        camera: &Camera,
+        chunks: &Chunks,
+        world_time: f32,
+    ) {
+        if self.view == DebugView::DensitySlice {
+            fullscreen::draw(
+                encoder,
+                "density_slice_pass",
+                target,
+                LoadOp::Clear(Color::BLACK),
+                &self.slice_pipeline,
+                &self.bind_group,
+                profiler.render_pass(GpuPass::Debug),
+            );
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("debug_mesh_pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color {
+                        r: 0.02,
+                        g: 0.02,
+                        b: 0.02,
+                        a: 1.0,
+                    }),
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(camera.depth_texture().depth_attachment()),
+            timestamp_writes: profiler.render_pass(GpuPass::Debug),
+            ..Default::default()
+        });
+        render_pass.set_pipeline(&self.mesh_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        chunks.draw(&mut render_pass, world_time);
+    }
+}
+
+/// The debug mesh pipeline, then the density slice one, both drawing onto the surface.
+fn create_pipelines(
+    device: &wgpu::Device,
+    mesh_layout: &PipelineLayout,
+    slice_layout: &PipelineLayout,
+    module: &ShaderModule,
+    surface_format: TextureFormat,
+) -> (RenderPipeline, RenderPipeline) {
+    let mesh = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("debug_mesh_pipeline"),
+        layout: Some(mesh_layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vs_mesh",
+            buffers: &[CLOUD_VERTEX_BUFFER_LAYOUT],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: "fs_mesh",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: surface_format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+    let slice = fullscreen::create_pipeline(
+        device,
+        "density_slice_pipeline",
+        slice_layout,
+        module,
+        "fs_density_slice",
+        surface_format,
+        None,
+    );
+    (mesh, slice)
+}
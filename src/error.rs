@@ -0,0 +1,56 @@
+use std::fmt;
+
+/// Why the clouds can't be set up on a device.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The device was created without these, see `clouds::required_features`.
+    MissingFeatures(wgpu::Features),
+    /// A limit of the device is below `clouds::required_limits`.
+    LimitTooLow {
+        limit: &'static str,
+        required: u64,
+        supported: u64,
+    },
+    /// A texture or buffer for this many voxels and chunks is bigger than the device allows.
+    TooLarge {
+        resource: &'static str,
+        size: u64,
+        limit: u64,
+    },
+    /// No voxels, chunks or room for vertices.
+    Empty,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingFeatures(features) => {
+                write!(f, "The device needs the features {:?}", features)
+            }
+            Error::LimitTooLow {
+                limit,
+                required,
+                supported,
+            } => write!(
+                f,
+                "The device's {} is {}, the clouds need {}",
+                limit, supported, required
+            ),
+            Error::TooLarge {
+                resource,
+                size,
+                limit,
+            } => write!(
+                f,
+                "The {} would be {}, more than the device's limit of {}",
+                resource, size, limit
+            ),
+            Error::Empty => write!(
+                f,
+                "The clouds need at least one voxel, one chunk and one vertex per voxel"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,141 @@
+use std::time::Instant;
+
+use log::info;
+
+use crate::{
+    chunks::{CHUNK_COUNT, CHUNK_VERTEX_BUFFER_SIZE, CLOUD_VERTEX_SIZE, INDIRECT_DRAW_STRIDE},
+    graphics::Graphics,
+    mesh_stats::MeshStatsReadback,
+    profiler::{CpuStage, Profiler},
+};
+
+// How much of each new frame's time goes into the smoothed frame time shown in the overlay
+const FRAME_TIME_SMOOTHING: f32 = 0.05;
+// Set RUST_LOG=info to see the log
+const LOG_INTERVAL_SECONDS: f32 = 5.0;
+
+/// How long frames take and what the meshers built, logged every few seconds and shown in the
+/// overlay.
+pub(crate) struct FrameStats {
+    last_update_instant: Instant,
+    // Smoothed, for the overlay
+    frame_seconds: f32,
+    last_log_instant: Instant,
+    frames_since_log: u32,
+    profiler: Profiler,
+    mesh_stats: MeshStatsReadback,
+}
+
+impl FrameStats {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self {
+            last_update_instant: Instant::now(),
+            frame_seconds: 0.0,
+            last_log_instant: Instant::now(),
+            frames_since_log: 0,
+            profiler: Profiler::new(device, queue),
+            mesh_stats: MeshStatsReadback::new(
+                device,
+                CHUNK_COUNT,
+                INDIRECT_DRAW_STRIDE,
+                (CHUNK_VERTEX_BUFFER_SIZE / CLOUD_VERTEX_SIZE) as u32,
+            ),
+        }
+    }
+
+    /// For timing the GPU passes.
+    pub fn profiler(&self) -> &Profiler {
+        &self.profiler
+    }
+
+    /// Seconds since the last update started at `update_start`.
+    /// Logs the frame rate and timings, and the mesh stats when `meshed`, every few seconds.
+    pub fn begin_update(&mut self, update_start: Instant, meshed: bool) -> f32 {
+        let dt = (update_start - self.last_update_instant).as_secs_f32();
+        self.last_update_instant = update_start;
+        self.frame_seconds += (dt - self.frame_seconds) * FRAME_TIME_SMOOTHING;
+
+        let seconds_since_log = self.last_log_instant.elapsed().as_secs_f32();
+        if seconds_since_log >= LOG_INTERVAL_SECONDS {
+            info!(
+                "FPS: {}",
+                (1.0 / (seconds_since_log / (self.frames_since_log as f32))).round()
+            );
+            self.profiler.log_and_reset();
+            if let Some(stats) = self.mesh_stats.latest().filter(|_| meshed) {
+                stats.log();
+            }
+            self.frames_since_log = 0;
+            self.last_log_instant = Instant::now();
+        }
+        self.frames_since_log += 1;
+        dt
+    }
+
+    pub fn end_update(&self, update_start: Instant) {
+        self.profiler
+            .record_cpu(CpuStage::Update, update_start.elapsed());
+    }
+
+    /// Starts timing a frame, then times how long `acquire` waits for the surface.
+    pub fn begin_frame<T>(&self, device: &wgpu::Device, acquire: impl FnOnce() -> T) -> T {
+        self.profiler.begin_frame(device);
+        self.mesh_stats.poll(device);
+        let acquire_start = Instant::now();
+        let frame = acquire();
+        self.profiler
+            .record_cpu(CpuStage::Acquire, acquire_start.elapsed());
+        frame
+    }
+
+    /// Submits the frame's commands, then presents it with `present`.
+    /// Reads back the vertex counts from `indirect_draw_buffer` when the frame was meshed.
+    pub fn submit(
+        &self,
+        gfx: &Graphics,
+        mut encoder: wgpu::CommandEncoder,
+        encode_start: Instant,
+        indirect_draw_buffer: Option<&wgpu::Buffer>,
+        present: impl FnOnce(),
+    ) {
+        if let Some(indirect_draw_buffer) = indirect_draw_buffer {
+            self.mesh_stats.copy(&mut encoder, indirect_draw_buffer);
+        }
+        self.profiler.resolve(&mut encoder);
+        self.profiler
+            .record_cpu(CpuStage::Encode, encode_start.elapsed());
+
+        let submit_start = Instant::now();
+        gfx.queue().submit(std::iter::once(encoder.finish()));
+        present();
+        self.profiler.end_frame();
+        self.mesh_stats.end_frame();
+        self.profiler
+            .record_cpu(CpuStage::Submit, submit_start.elapsed());
+    }
+
+    /// Shows the mesh stats only when `meshed`, since they'd be from an earlier frame.
+    pub fn ui(&self, ui: &mut egui::Ui, meshed: bool) {
+        ui.label(format!(
+            "{:.2} ms per frame, {:.0} FPS",
+            self.frame_seconds * 1000.0,
+            1.0 / self.frame_seconds.max(f32::EPSILON)
+        ));
+        if let Some(stats) = self.mesh_stats.latest().filter(|_| meshed) {
+            ui.label(format!(
+                "{} triangles, {:.1}% of the vertex buffer, fullest chunk {:.1}%",
+                stats.triangles(),
+                stats.fill_ratio() * 100.0,
+                stats.fullest_chunk_ratio() * 100.0
+            ));
+        }
+        // Refreshed with the log, every few seconds
+        let summary = self.profiler.last_summary();
+        if !summary.cpu.is_empty() {
+            ui.label(format!("CPU ms: {}", summary.cpu));
+        }
+        if let Some(gpu) = summary.gpu {
+            ui.label(format!("GPU ms: {}", gpu));
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use wgpu::{
+    BindGroup, Color, LoadOp, Operations, PipelineLayout, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipeline, ShaderModule, StoreOp, TextureFormat,
+};
+
+/// Draws a single triangle covering the screen, with the shader's `vs_main`.
+pub fn create_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &PipelineLayout,
+    module: &ShaderModule,
+    entry_point: &str,
+    format: TextureFormat,
+    blend: Option<wgpu::BlendState>,
+) -> RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+/// Draws a single triangle covering the target.
+pub fn draw(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    target: &wgpu::TextureView,
+    load: LoadOp<Color>,
+    pipeline: &RenderPipeline,
+    bind_group: &BindGroup,
+    timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'_>>,
+) {
+    let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: Operations {
+                load,
+                store: StoreOp::Store,
+            },
+        })],
+        timestamp_writes,
+        ..Default::default()
+    });
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+}
@@ -0,0 +1,126 @@
+//! Renders fixed moments of the world on a software adapter and compares them against the
+//! reference images in `tests/golden`, so changes to the shaders that move any pixels get caught.
+//!
+//! Rendering on the CPU is slow and needs a software adapter, so these only run when asked:
+//! `cargo test --release -- --ignored golden`. Set `NUAGE_UPDATE_GOLDEN=1` to write the current
+//! frames as the new references after checking they look right.
+
+use std::path::{Path, PathBuf};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 180;
+// How far apart two pixels can be before they're counted as different, out of about 765
+const PIXEL_TOLERANCE: f32 = 24.0;
+// The share of pixels that can differ, for edges rasterized a little differently
+const DIFFERENT_PIXELS_TOLERANCE: f32 = 0.005;
+
+/// The seconds of world time each reference is rendered at.
+const CASES: &[(&str, f32)] = &[("start", 0.0), ("drifted", 7.5)];
+
+fn reference_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.png", name))
+}
+
+fn load_png(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let decoder = png::Decoder::new(std::fs::File::open(path)?);
+    let mut reader = decoder.read_info()?;
+    let info = reader.info();
+    anyhow::ensure!(
+        (info.width, info.height) == (WIDTH, HEIGHT) && info.color_type == png::ColorType::Rgba,
+        "{:?} isn't a {}x{} RGBA image",
+        path,
+        WIDTH,
+        HEIGHT
+    );
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut pixels)?;
+    Ok(pixels)
+}
+
+/// The share of pixels that look different, out of two RGBA8 images of the same size.
+fn different_pixels(expected: &[u8], actual: &[u8]) -> f32 {
+    let pixels = expected.len() / 4;
+    let different = expected
+        .chunks_exact(4)
+        .zip(actual.chunks_exact(4))
+        .filter(|(expected, actual)| color_distance(expected, actual) > PIXEL_TOLERANCE)
+        .count();
+    different as f32 / pixels.max(1) as f32
+}
+
+/// The "redmean" distance, which weighs the channels by how much the eye notices them.
+fn color_distance(a: &[u8], b: &[u8]) -> f32 {
+    let red_mean = (a[0] as f32 + b[0] as f32) / 2.0;
+    let [red, green, blue] = [0, 1, 2].map(|channel| a[channel] as f32 - b[channel] as f32);
+    ((2.0 + red_mean / 256.0) * red * red
+        + 4.0 * green * green
+        + (2.0 + (255.0 - red_mean) / 256.0) * blue * blue)
+        .sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use pollster::FutureExt as _;
+
+    use super::*;
+    use crate::{
+        capture, cloud_world::CloudWorld, graphics::Graphics, lighting::Lighting,
+        post::PostProcessing,
+    };
+
+    #[test]
+    #[ignore = "renders on the CPU, run with --release -- --ignored golden"]
+    fn frames_match_the_references() {
+        let gfx = Graphics::new_fallback(WIDTH, HEIGHT)
+            .block_on()
+            .expect("The golden images need a software adapter, like Mesa's lavapipe");
+        let mut cloud_world = CloudWorld::new(&gfx, Lighting::default(), PostProcessing::default());
+        cloud_world.clock_mut().paused = true;
+        let update = std::env::var_os("NUAGE_UPDATE_GOLDEN").is_some();
+
+        let mut failures = Vec::new();
+        for &(name, seconds) in CASES {
+            cloud_world.clock_mut().seek(seconds);
+            cloud_world.update();
+            cloud_world.render(&gfx, |_, _, _| {}).unwrap();
+            let pixels = capture::read_texture(&gfx, gfx.frame().unwrap().texture());
+
+            let reference = reference_path(name);
+            if update {
+                std::fs::create_dir_all(reference.parent().unwrap()).unwrap();
+                capture::save_png(&reference, WIDTH, HEIGHT, &pixels).unwrap();
+                continue;
+            }
+            let expected = load_png(&reference)
+                .unwrap_or_else(|e| panic!("{:?}, set NUAGE_UPDATE_GOLDEN=1 to write it", e));
+            let different = different_pixels(&expected, &pixels);
+            if different > DIFFERENT_PIXELS_TOLERANCE {
+                // Kept next to the build to compare by eye
+                let actual = Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join("target/golden")
+                    .join(format!("{}.png", name));
+                std::fs::create_dir_all(actual.parent().unwrap()).unwrap();
+                capture::save_png(&actual, WIDTH, HEIGHT, &pixels).unwrap();
+                failures.push(format!(
+                    "{}: {:.2}% of pixels differ, see {:?}",
+                    name,
+                    different * 100.0,
+                    actual
+                ));
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn small_color_shifts_are_tolerated() {
+        let expected = [100, 150, 200, 255].repeat(100);
+        let mut actual = [102, 149, 203, 255].repeat(100);
+        assert_eq!(different_pixels(&expected, &actual), 0.0);
+
+        actual[..4].copy_from_slice(&[255, 255, 255, 255]);
+        assert_eq!(different_pixels(&expected, &actual), 0.01);
+    }
+}
@@ -1,16 +1,51 @@
-use wgpu::{Surface, SurfaceConfiguration};
+use wgpu::SurfaceConfiguration;
 use winit::window::Window;
 
+// What headless frames are drawn to, and read back from
+const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
 pub struct Graphics {
-    surface: wgpu::Surface,
+    adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
-    // The window must be declared after the surface so
-    // it gets dropped after it as the surface contains
-    // unsafe references to the window's resources.
-    window: Window,
+    target: Target,
+}
+
+/// Where frames end up.
+enum Target {
+    Window {
+        surface: wgpu::Surface,
+        // The window must be declared after the surface so
+        // it gets dropped after it as the surface contains
+        // unsafe references to the window's resources.
+        window: Window,
+    },
+    /// Without a window, frames are drawn to a texture to be read back.
+    Offscreen { texture: wgpu::Texture },
+}
+
+/// The texture a frame is drawn to.
+pub enum Frame<'a> {
+    Surface(wgpu::SurfaceTexture),
+    Offscreen(&'a wgpu::Texture),
+}
+
+impl Frame<'_> {
+    pub fn texture(&self) -> &wgpu::Texture {
+        match self {
+            Frame::Surface(surface_texture) => &surface_texture.texture,
+            Frame::Offscreen(texture) => texture,
+        }
+    }
+
+    /// Shows the frame in the window. Offscreen frames stay in the texture until the next one.
+    pub fn present(self) {
+        if let Frame::Surface(surface_texture) = self {
+            surface_texture.present();
+        }
+    }
 }
 
 impl Graphics {
@@ -36,40 +71,20 @@ impl Graphics {
             })
             .await
             .unwrap();
-
-        // Works on my M2 MacBook Air (2022)
-        let mut device_limits = wgpu::Limits::default();
-        device_limits.max_push_constant_size = 128;
-        device_limits.max_compute_invocations_per_workgroup = 1024;
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty()
-                        | wgpu::Features::PUSH_CONSTANTS
-                        | wgpu::Features::MAPPABLE_PRIMARY_BUFFERS
-                        | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
-                    limits: if cfg!(target_arch = "wasm32") {
-                        wgpu::Limits::downlevel_webgl2_defaults()
-                    } else {
-                        device_limits
-                    },
-                    label: None,
-                },
-                None, // Trace path
-            )
-            .await
-            .unwrap();
+        let (device, queue) = request_device(&adapter).await;
 
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
             .formats
             .iter()
             .copied()
-            .filter(|f| f.is_srgb())
-            .next()
+            .find(|f| f.is_srgb())
             .unwrap_or(surface_caps.formats[0]);
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            // Screenshots copy the frame out of the surface texture, so ask for COPY_SRC
+            // where the platform supports it. Without it they're skipped with an error.
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC),
             format: surface_format,
             width: size.width,
             height: size.height,
@@ -80,17 +95,78 @@ impl Graphics {
         surface.configure(&device, &config);
 
         Self {
-            surface,
+            adapter,
             device,
             queue,
             config,
             size,
-            window,
+            target: Target::Window { surface, window },
         }
     }
 
+    /// Draws to a texture instead of a window, for rendering offline.
+    pub async fn new_headless(width: u32, height: u32) -> anyhow::Result<Self> {
+        Self::new_offscreen(width, height, wgpu::Backends::all(), false).await
+    }
+
+    /// Draws to a texture on a software adapter, which renders the same on any machine.
+    /// Returns `None` when there isn't one, like lavapipe on Linux or WARP on Windows.
+    #[cfg(test)]
+    pub async fn new_fallback(width: u32, height: u32) -> Option<Self> {
+        // wgpu's GL backend can't take the u32 push constants
+        Self::new_offscreen(width, height, wgpu::Backends::PRIMARY, true)
+            .await
+            .ok()
+    }
+
+    async fn new_offscreen(
+        width: u32,
+        height: u32,
+        backends: wgpu::Backends,
+        force_fallback_adapter: bool,
+    ) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends,
+            ..Default::default()
+        });
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter,
+            })
+            .await
+            .ok_or_else(|| anyhow::anyhow!("No GPU adapter found"))?;
+        let (device, queue) = request_device(&adapter).await;
+
+        // Not configured on any surface, but everything sized to the frame reads it
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: OFFSCREEN_FORMAT,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+        };
+        let texture = create_offscreen_texture(&device, &config);
+
+        Ok(Self {
+            adapter,
+            device,
+            queue,
+            config,
+            size: winit::dpi::PhysicalSize::new(width, height),
+            target: Target::Offscreen { texture },
+        })
+    }
+
+    /// Panics when headless.
     pub fn window(&self) -> &Window {
-        &self.window
+        match &self.target {
+            Target::Window { window, .. } => window,
+            Target::Offscreen { .. } => panic!("Headless graphics have no window"),
+        }
     }
 
     pub fn size(&self) -> &winit::dpi::PhysicalSize<u32> {
@@ -101,12 +177,33 @@ impl Graphics {
         &self.device
     }
 
+    /// The largest sample count, up to `requested`, that every format supports on this adapter.
+    pub fn supported_sample_count(&self, requested: u32, formats: &[wgpu::TextureFormat]) -> u32 {
+        [8, 4, 2, 1]
+            .into_iter()
+            .filter(|&count| count <= requested)
+            .find(|&count| {
+                formats.iter().all(|&format| {
+                    self.adapter
+                        .get_texture_format_features(format)
+                        .flags
+                        .sample_count_supported(count)
+                })
+            })
+            .unwrap_or(1)
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            match &mut self.target {
+                Target::Window { surface, .. } => surface.configure(&self.device, &self.config),
+                Target::Offscreen { texture } => {
+                    *texture = create_offscreen_texture(&self.device, &self.config)
+                }
+            }
         }
     }
 
@@ -114,11 +211,65 @@ impl Graphics {
         &self.config
     }
 
-    pub fn surface(&self) -> &Surface {
-        &self.surface
+    /// The next texture to draw to.
+    pub fn frame(&self) -> Result<Frame<'_>, wgpu::SurfaceError> {
+        match &self.target {
+            Target::Window { surface, .. } => surface.get_current_texture().map(Frame::Surface),
+            Target::Offscreen { texture } => Ok(Frame::Offscreen(texture)),
+        }
     }
 
     pub fn queue(&self) -> &wgpu::Queue {
         &self.queue
     }
 }
+
+async fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+    // Works on my M2 MacBook Air (2022)
+    let device_limits = wgpu::Limits {
+        max_push_constant_size: 128,
+        max_compute_invocations_per_workgroup: 1024,
+        ..Default::default()
+    };
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                features: wgpu::Features::empty()
+                    | wgpu::Features::PUSH_CONSTANTS
+                    | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                    // Only for reading back the vertex buffer and profiling, so only when there
+                    | (adapter.features()
+                        & (wgpu::Features::MAPPABLE_PRIMARY_BUFFERS
+                            | wgpu::Features::TIMESTAMP_QUERY)),
+                limits: if cfg!(target_arch = "wasm32") {
+                    wgpu::Limits::downlevel_webgl2_defaults()
+                } else {
+                    device_limits
+                },
+                label: None,
+            },
+            None, // Trace path
+        )
+        .await
+        .unwrap()
+}
+
+fn create_offscreen_texture(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("offscreen_target"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
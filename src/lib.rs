@@ -1,8 +1,37 @@
+pub mod bench;
 mod camera;
+mod capture;
+mod chunks;
+mod clock;
 mod cloud_world;
+pub mod clouds;
+mod debug_view;
+pub mod error;
+mod frame_stats;
+mod fullscreen;
+#[cfg(test)]
+mod golden;
 mod graphics;
-mod marching_cubes;
+pub mod lighting;
+mod lit_mesh;
+pub mod marching_cubes;
+#[cfg(test)]
+mod marching_tetrahedra;
+mod mesh_stats;
+mod msaa;
+pub mod noise;
+pub mod offline;
+mod overlay;
+pub mod post;
+mod profiler;
+mod shader;
+mod shadow;
+mod sky;
+mod ssao;
+#[cfg(test)]
+mod surface_nets;
 mod texture;
+mod volume;
 
 pub mod window;
 
@@ -0,0 +1,151 @@
+use glm::Vec3;
+
+use crate::shader;
+
+/// How the clouds are lit. Shared by the mesh and the raymarched volume.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Lighting {
+    pub sun: Sun,
+    pub ambient: Ambient,
+    pub material: Material,
+    pub fog: Fog,
+}
+
+/// A directional light.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sun {
+    /// Points toward the sun, doesn't need to be normalized.
+    pub direction: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+/// Light coming from the sky above and bounced off the ground below,
+/// blended by how much a surface faces up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ambient {
+    pub sky_color: [f32; 3],
+    pub ground_color: [f32; 3],
+    pub intensity: f32,
+}
+
+/// How the surface of the mesh responds to light.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    pub albedo: [f32; 3],
+    /// How far the diffuse light wraps past the terminator.
+    /// 0 is plain Lambert, 1 lights the whole surface.
+    pub wrap: f32,
+    /// Sunlight seen through the cloud when looking toward the sun.
+    pub translucency: f32,
+    /// Light along the silhouette, where the surface is seen edge on.
+    pub rim_strength: f32,
+    /// Higher values keep the rim light closer to the silhouette.
+    pub rim_power: f32,
+}
+
+/// Haze between the camera and the clouds, tinted by the sky behind them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fog {
+    /// How much of the light is lost per world unit. 0 turns the fog off.
+    pub density: f32,
+}
+
+impl Default for Sun {
+    fn default() -> Self {
+        Self {
+            direction: [0.4, 0.8, 0.6],
+            color: [1.0, 0.95, 0.85],
+            intensity: 1.0,
+        }
+    }
+}
+
+impl Default for Ambient {
+    fn default() -> Self {
+        Self {
+            sky_color: [0.45, 0.55, 0.75],
+            ground_color: [0.25, 0.22, 0.2],
+            intensity: 0.35,
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            albedo: [0.9, 0.9, 0.92],
+            wrap: 0.4,
+            translucency: 0.6,
+            rim_strength: 0.25,
+            rim_power: 3.0,
+        }
+    }
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self { density: 0.015 }
+    }
+}
+
+impl Lighting {
+    pub(crate) fn uniform(&self) -> LightingUniform {
+        let sun_direction = Vec3::from(self.sun.direction)
+            .try_normalize(f32::EPSILON)
+            .unwrap_or(Vec3::y());
+        let scaled = |color: [f32; 3], intensity: f32| {
+            [
+                color[0] * intensity,
+                color[1] * intensity,
+                color[2] * intensity,
+                0.0,
+            ]
+        };
+        let [r, g, b] = self.material.albedo;
+        LightingUniform {
+            sun_direction: [sun_direction.x, sun_direction.y, sun_direction.z, 0.0],
+            sun_color: scaled(self.sun.color, self.sun.intensity),
+            sky_color: scaled(self.ambient.sky_color, self.ambient.intensity),
+            ground_color: scaled(self.ambient.ground_color, self.ambient.intensity),
+            albedo: [r, g, b, 0.0],
+            wrap: self.material.wrap,
+            translucency: self.material.translucency,
+            rim_strength: self.material.rim_strength,
+            rim_power: self.material.rim_power,
+            fog_density: self.fog.density,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+// Mirrors LightingUniform in shaders/common.wgsl
+// Colors are premultiplied by their intensity.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct LightingUniform {
+    pub sun_direction: [f32; 4],
+    pub sun_color: [f32; 4],
+    pub sky_color: [f32; 4],
+    pub ground_color: [f32; 4],
+    pub albedo: [f32; 4],
+    pub wrap: f32,
+    pub translucency: f32,
+    pub rim_strength: f32,
+    pub rim_power: f32,
+    pub fog_density: f32,
+    pub _padding: [f32; 3],
+}
+
+shader::assert_wgsl_layout!(LightingUniform, 112, {
+    sun_direction: 0,
+    sun_color: 16,
+    sky_color: 32,
+    ground_color: 48,
+    albedo: 64,
+    wrap: 80,
+    translucency: 84,
+    rim_strength: 88,
+    rim_power: 92,
+    fog_density: 96,
+});
@@ -0,0 +1,334 @@
+use wgpu::{
+    BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    DepthStencilState, PipelineLayout, PushConstantRange, RenderPassDescriptor, RenderPipeline,
+    ShaderModule, ShaderStages, TextureFormat, TextureViewDimension,
+};
+
+use crate::{
+    camera::Camera,
+    chunks::{Chunks, CLOUD_VERTEX_BUFFER_LAYOUT, PUSH_CONSTANTS_SIZE},
+    graphics::Graphics,
+    msaa::{self, MsaaTargets},
+    post::HDR_FORMAT,
+    profiler::{GpuPass, Profiler},
+    shader::{self, Shader},
+    shadow::ShadowMap,
+    texture,
+};
+
+// The mesh's world space normals, for ambient occlusion
+pub(crate) const NORMAL_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// The chunks' meshes, lit by the sun and shadowed by the shadow map.
+pub(crate) struct LitMesh {
+    // The sky only reads the camera and lighting, so it shares the mesh's bind group
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+    pipeline_layout: PipelineLayout,
+    pipeline: RenderPipeline,
+    depth_prepass_pipeline: RenderPipeline,
+    // Samples per pixel of the lit mesh
+    sample_count: u32,
+    // Only there when multisampling
+    msaa: Option<MsaaTargets>,
+    normal: texture::Texture,
+}
+
+impl LitMesh {
+    /// Multisampled with up to `msaa_samples` samples per pixel.
+    pub fn new(
+        gfx: &Graphics,
+        camera: &Camera,
+        lighting_buffer: &wgpu::Buffer,
+        shadow_map: &ShadowMap,
+        msaa_samples: u32,
+    ) -> Self {
+        let device = gfx.device();
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("render_bind_group_layout"),
+            entries: &[
+                // Camera
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX_FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Lighting
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Shadow cascades
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Shadow map
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("world_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera.buffer_binding_resource(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: lighting_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: shadow_map.uniform_binding_resource(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(shadow_map.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(shadow_map.sampler()),
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("render_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[PushConstantRange {
+                stages: ShaderStages::VERTEX,
+                range: 0..PUSH_CONSTANTS_SIZE,
+            }],
+        });
+        let sample_count = msaa::sample_count(gfx, msaa_samples);
+        let (pipeline, depth_prepass_pipeline) = create_pipelines(
+            device,
+            &pipeline_layout,
+            &shader::create_shader_module(device, Shader::ChunkRender),
+            sample_count,
+        );
+
+        Self {
+            bind_group_layout,
+            bind_group,
+            pipeline_layout,
+            pipeline,
+            depth_prepass_pipeline,
+            sample_count,
+            msaa: MsaaTargets::new(gfx, sample_count),
+            normal: create_normal_target(gfx),
+        }
+    }
+
+    pub fn resize(&mut self, gfx: &Graphics) {
+        self.msaa = MsaaTargets::new(gfx, self.sample_count);
+        self.normal = create_normal_target(gfx);
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device, source: &str) {
+        if let Some((pipeline, depth_prepass)) =
+            shader::try_create_pipeline(device, Shader::ChunkRender, source, |module| {
+                create_pipelines(device, &self.pipeline_layout, module, self.sample_count)
+            })
+        {
+            self.pipeline = pipeline;
+            self.depth_prepass_pipeline = depth_prepass;
+        }
+    }
+
+    /// The camera, lighting and shadow map.
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+
+    /// The mesh's world space normals, drawn along with its color.
+    pub fn normal(&self) -> &texture::Texture {
+        &self.normal
+    }
+
+    /// Draws the lit mesh into `hdr` and its normals, and its depth into the camera's.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        profiler: &Profiler,
+        hdr: &texture::Texture,
+        camera: &Camera,
+        chunks: &Chunks,
+        world_time: f32,
+    ) {
+        {
+            let msaa = self.msaa.as_ref();
+            let depth = msaa.map_or(camera.depth_texture(), |msaa| &msaa.depth);
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("cloud_render_pass"),
+                color_attachments: &[
+                    msaa::color_attachment(hdr, msaa.map(|msaa| &msaa.color)),
+                    msaa::color_attachment(&self.normal, msaa.map(|msaa| &msaa.normal)),
+                ],
+                depth_stencil_attachment: Some(depth.depth_attachment()),
+                timestamp_writes: profiler.render_pass(GpuPass::Mesh),
+                ..Default::default()
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            chunks.draw(&mut render_pass, world_time);
+        }
+        // Ambient occlusion can't read the depth when it's multisampled
+        if self.msaa.is_some() {
+            self.render_depth(encoder, profiler, camera, chunks, world_time);
+        }
+    }
+
+    /// Only draws the mesh's depth into the camera's.
+    pub fn render_depth(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        profiler: &Profiler,
+        camera: &Camera,
+        chunks: &Chunks,
+        world_time: f32,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("depth_prepass"),
+            depth_stencil_attachment: Some(camera.depth_texture().depth_attachment()),
+            timestamp_writes: profiler.render_pass(GpuPass::DepthPrepass),
+            ..Default::default()
+        });
+        render_pass.set_pipeline(&self.depth_prepass_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        chunks.draw(&mut render_pass, world_time);
+    }
+}
+
+fn create_normal_target(gfx: &Graphics) -> texture::Texture {
+    texture::Texture::create_render_target(
+        gfx.device(),
+        gfx.config(),
+        NORMAL_FORMAT,
+        "normal_target",
+    )
+}
+
+/// The lit mesh pipeline, then the depth prepass one.
+fn create_pipelines(
+    device: &wgpu::Device,
+    layout: &PipelineLayout,
+    module: &ShaderModule,
+    sample_count: u32,
+) -> (RenderPipeline, RenderPipeline) {
+    (
+        create_pipeline(
+            device,
+            "chunk_render_pipeline",
+            layout,
+            module,
+            Some(HDR_FORMAT),
+            sample_count,
+        ),
+        // Lays down the depth of the mesh for the hybrid render mode,
+        // and for ambient occlusion when the lit mesh is multisampled
+        create_pipeline(
+            device,
+            "chunk_depth_prepass_pipeline",
+            layout,
+            module,
+            None,
+            1,
+        ),
+    )
+}
+
+/// Without a color format, the pipeline only writes depth.
+fn create_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &PipelineLayout,
+    module: &ShaderModule,
+    format: Option<TextureFormat>,
+    sample_count: u32,
+) -> RenderPipeline {
+    let color_targets = format.map(|format| {
+        [
+            Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+            Some(wgpu::ColorTargetState {
+                format: NORMAL_FORMAT,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+        ]
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vs_main",
+            buffers: &[CLOUD_VERTEX_BUFFER_LAYOUT],
+        },
+        fragment: color_targets.as_ref().map(|targets| wgpu::FragmentState {
+            module,
+            entry_point: "fs_main",
+            targets,
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None, //Some(wgpu::Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+    })
+}
@@ -1,8 +1,30 @@
-use nuage::window;
+use nuage::{
+    bench::{self, BenchOptions},
+    lighting::Lighting,
+    offline::{self, RenderOptions},
+    post::PostProcessing,
+    window,
+};
 use pollster::FutureExt as _;
 
 fn main() -> anyhow::Result<()> {
     env_logger::init();
-    window::run().block_on()?;
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        None => window::run(Lighting::default(), PostProcessing::default()).block_on()?,
+        Some("render") => offline::render(
+            RenderOptions::parse(args)?,
+            Lighting::default(),
+            PostProcessing::default(),
+        )
+        .block_on()?,
+        Some("bench") => bench::run(BenchOptions::parse(args)?).block_on()?,
+        Some(command) => anyhow::bail!(
+            "Unknown command {}\n{}\n{}",
+            command,
+            offline::USAGE,
+            bench::USAGE
+        ),
+    }
     Ok(())
 }
@@ -283,3 +283,215 @@ pub const TRI_TABLE: [[i32; 16]; 256] = [
         -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
     ],
 ];
+
+/// Offsets of the corners of a voxel, in the order the tables index them.
+/// Bit `i` of a cube index is set when corner `i` is at or below the iso level.
+pub const CORNERS: [[u32; 3]; 8] = [
+    [0, 0, 0],
+    [1, 0, 0],
+    [1, 0, 1],
+    [0, 0, 1],
+    [0, 1, 0],
+    [1, 1, 0],
+    [1, 1, 1],
+    [0, 1, 1],
+];
+
+/// The corners at the ends of each edge. Bit `i` of an `EDGE_TABLE` entry refers to edge `i`.
+pub const EDGE_CORNERS: [[usize; 2]; 12] = [
+    [0, 1],
+    [1, 2],
+    [2, 3],
+    [3, 0],
+    [4, 5],
+    [5, 6],
+    [6, 7],
+    [7, 4],
+    [0, 4],
+    [1, 5],
+    [2, 6],
+    [3, 7],
+];
+
+const EPSILON: f32 = 0.0000001;
+
+fn cube_index(values: &[f32; 8], iso_level: f32) -> usize {
+    values
+        .iter()
+        .enumerate()
+        .filter(|(_, &value)| value <= iso_level)
+        .fold(0, |index, (corner, _)| index | (1 << corner))
+}
+
+/// Meshes `voxels_per_dim^3` voxels on the CPU the same way `marching_cubes.wgsl` does on the GPU.
+/// `density` is sampled at every corner, from 0 through `voxels_per_dim` on each axis.
+/// Returns a triangle list of positions in voxel units.
+pub fn mesh(
+    voxels_per_dim: u32,
+    iso_level: f32,
+    density: impl Fn([u32; 3]) -> f32,
+) -> Vec<[f32; 3]> {
+    let mut vertices = Vec::new();
+    for z in 0..voxels_per_dim {
+        for y in 0..voxels_per_dim {
+            for x in 0..voxels_per_dim {
+                let positions =
+                    CORNERS.map(|[cx, cy, cz]| [x + cx, y + cy, z + cz].map(|c| c as f32));
+                let values = CORNERS.map(|[cx, cy, cz]| density([x + cx, y + cy, z + cz]));
+                let index = cube_index(&values, iso_level);
+
+                for &edge in TRI_TABLE[index].iter().take_while(|&&edge| edge != -1) {
+                    let [a, b] = EDGE_CORNERS[edge as usize];
+                    // Same interpolation as vertexInterp in the shader
+                    let mu = (iso_level - values[a]).abs().max(EPSILON)
+                        / (values[b] - values[a]).abs().max(EPSILON);
+                    let mu = mu.clamp(0.0, 1.0);
+                    vertices.push(std::array::from_fn(|axis| {
+                        positions[a][axis] + (positions[b][axis] - positions[a][axis]) * mu
+                    }));
+                }
+            }
+        }
+    }
+    vertices
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    // A sphere in a 16^3 grid, off center so no corner sits exactly on the surface
+    pub(crate) const SPHERE_DIM: u32 = 16;
+    const SPHERE_RADIUS: f32 = 5.3;
+    const SPHERE_CENTER: f32 = SPHERE_DIM as f32 / 2.0 + 0.1;
+
+    pub(crate) fn sphere_distance(point: [f32; 3]) -> f32 {
+        point
+            .map(|c| (c - SPHERE_CENTER).powi(2))
+            .iter()
+            .sum::<f32>()
+            .sqrt()
+    }
+
+    /// Falls off linearly through 0.5 at the sphere's radius.
+    pub(crate) fn sphere_density(point: [f32; 3]) -> f32 {
+        0.5 + (SPHERE_RADIUS - sphere_distance(point)) / SPHERE_DIM as f32
+    }
+
+    pub(crate) fn assert_on_sphere(vertices: &[[f32; 3]], tolerance: f32) {
+        for &vertex in vertices {
+            assert!(
+                (sphere_distance(vertex) - SPHERE_RADIUS).abs() < tolerance,
+                "{:?} is off the sphere",
+                vertex
+            );
+        }
+    }
+
+    /// Every triangle edge should be shared by exactly two triangles.
+    pub(crate) fn assert_closed(vertices: &[[f32; 3]]) {
+        assert!(!vertices.is_empty());
+        assert_eq!(vertices.len() % 3, 0);
+        let key = |v: [f32; 3]| v.map(|c| (c * 1000.0).round() as i32);
+        let mut edges = HashMap::new();
+        for triangle in vertices.chunks(3) {
+            for i in 0..3 {
+                let (a, b) = (key(triangle[i]), key(triangle[(i + 1) % 3]));
+                *edges
+                    .entry(if a < b { (a, b) } else { (b, a) })
+                    .or_insert(0) += 1;
+            }
+        }
+        let open = edges.values().filter(|&&count| count != 2).count();
+        assert_eq!(
+            open,
+            0,
+            "{} of {} edges aren't shared by two triangles",
+            open,
+            edges.len()
+        );
+    }
+
+    /// The edges a `TRI_TABLE` entry uses, as an `EDGE_TABLE` style bitmask.
+    fn used_edges(index: usize) -> u32 {
+        TRI_TABLE[index]
+            .iter()
+            .take_while(|&&edge| edge != -1)
+            .fold(0, |mask, &edge| mask | (1 << edge))
+    }
+
+    #[test]
+    fn edge_table_flags_edges_between_inside_and_outside_corners() {
+        for (index, &edges) in EDGE_TABLE.iter().enumerate() {
+            let expected = EDGE_CORNERS
+                .iter()
+                .enumerate()
+                .filter(|(_, [a, b])| (index >> a) & 1 != (index >> b) & 1)
+                .fold(0, |mask, (edge, _)| mask | (1 << edge));
+            assert_eq!(edges, expected, "EDGE_TABLE[{}]", index);
+        }
+    }
+
+    #[test]
+    fn tri_table_only_uses_flagged_edges() {
+        for (index, &edges) in EDGE_TABLE.iter().enumerate() {
+            assert_eq!(
+                used_edges(index),
+                edges,
+                "TRI_TABLE[{}] doesn't match EDGE_TABLE[{}]",
+                index,
+                index
+            );
+        }
+    }
+
+    #[test]
+    fn tri_table_entries_are_whole_triangles_terminated_by_minus_one() {
+        for (index, entry) in TRI_TABLE.iter().enumerate() {
+            let count = entry.iter().take_while(|&&edge| edge != -1).count();
+            assert_eq!(count % 3, 0, "TRI_TABLE[{}] has a partial triangle", index);
+            assert!(count < entry.len(), "TRI_TABLE[{}] isn't terminated", index);
+            assert!(
+                entry[count..].iter().all(|&edge| edge == -1),
+                "TRI_TABLE[{}] has edges after the terminator",
+                index
+            );
+            assert!(
+                entry[..count].iter().all(|&edge| (0..12).contains(&edge)),
+                "TRI_TABLE[{}] has an invalid edge",
+                index
+            );
+        }
+    }
+
+    #[test]
+    fn complementary_cube_indices_cross_the_same_edges() {
+        for (index, &edges) in EDGE_TABLE.iter().enumerate() {
+            let complement = 255 - index;
+            assert_eq!(edges, EDGE_TABLE[complement], "EDGE_TABLE[{}]", index);
+            assert_eq!(
+                used_edges(index),
+                used_edges(complement),
+                "TRI_TABLE[{}]",
+                index
+            );
+        }
+    }
+
+    #[test]
+    fn empty_and_full_grids_have_no_triangles() {
+        assert!(mesh(4, 0.5, |_| 0.0).is_empty());
+        assert!(mesh(4, 0.5, |_| 1.0).is_empty());
+    }
+
+    #[test]
+    fn sphere_is_closed_and_on_the_surface() {
+        let vertices = mesh(SPHERE_DIM, 0.5, |corner| {
+            sphere_density(corner.map(|c| c as f32))
+        });
+        assert_closed(&vertices);
+        assert_on_sphere(&vertices, 0.1);
+    }
+}
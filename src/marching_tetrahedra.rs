@@ -0,0 +1,138 @@
+//! A CPU port of `marching_tetrahedra.wgsl`, to check that the surfaces it builds are closed and
+//! that the shader writes the same triangles.
+
+use std::array;
+
+const EPSILON: f32 = 0.0000001;
+
+/// Each tetrahedron walks from corner 0 to corner 7 along the axes in a different order.
+/// Corner `i` is offset by `(i & 1, (i >> 1) & 1, (i >> 2) & 1)`.
+const TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 1, 3, 7],
+    [0, 1, 5, 7],
+    [0, 2, 3, 7],
+    [0, 2, 6, 7],
+    [0, 4, 5, 7],
+    [0, 4, 6, 7],
+];
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    array::from_fn(|axis| a[axis] - b[axis])
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// Winds the triangle counter-clockwise when seen from the `outward` side, like writeTriangle.
+fn wind([a, b, c]: [[f32; 3]; 3], outward: [f32; 3]) -> [[f32; 3]; 3] {
+    if dot(cross(sub(b, a), sub(c, a)), outward) >= 0.0 {
+        [a, b, c]
+    } else {
+        [a, c, b]
+    }
+}
+
+/// Meshes `voxels_per_dim^3` voxels the way the shader does.
+/// `density` is sampled at every corner, from 0 through `voxels_per_dim` on each axis.
+/// Returns a triangle list of positions in voxel units.
+pub fn mesh(
+    voxels_per_dim: u32,
+    iso_level: f32,
+    density: impl Fn([u32; 3]) -> f32,
+) -> Vec<[f32; 3]> {
+    let mut vertices = Vec::new();
+    for z in 0..voxels_per_dim {
+        for y in 0..voxels_per_dim {
+            for x in 0..voxels_per_dim {
+                let corners: [[u32; 3]; 8] = array::from_fn(|i| {
+                    let i = i as u32;
+                    [x + (i & 1), y + ((i >> 1) & 1), z + ((i >> 2) & 1)]
+                });
+                let values = corners.map(&density);
+
+                // Always interpolate from the lower corner, so the voxels sharing an edge agree
+                let edge_vertex = |a: usize, b: usize| {
+                    let (low, high) = (a.min(b), a.max(b));
+                    let delta = values[high] - values[low];
+                    let mu = if delta.abs() > EPSILON {
+                        ((iso_level - values[low]) / delta).clamp(0.0, 1.0)
+                    } else {
+                        0.5
+                    };
+                    array::from_fn(|axis| {
+                        let (low, high) = (corners[low][axis] as f32, corners[high][axis] as f32);
+                        low + (high - low) * mu
+                    })
+                };
+
+                for tetrahedron in TETRAHEDRA {
+                    let (inside, outside): (Vec<usize>, Vec<usize>) = tetrahedron
+                        .iter()
+                        .partition(|&&corner| values[corner] > iso_level);
+                    // From the inside corners toward the outside ones
+                    let outward = tetrahedron.iter().fold([0.0; 3], |outward, &corner| {
+                        let sign = if values[corner] > iso_level {
+                            -1.0
+                        } else {
+                            1.0
+                        };
+                        array::from_fn(|axis| outward[axis] + sign * corners[corner][axis] as f32)
+                    });
+                    match inside.len() {
+                        1 => vertices.extend(wind(
+                            array::from_fn(|i| edge_vertex(inside[0], outside[i])),
+                            outward,
+                        )),
+                        3 => vertices.extend(wind(
+                            array::from_fn(|i| edge_vertex(outside[0], inside[i])),
+                            outward,
+                        )),
+                        2 => {
+                            // The crossed edges form a quad, in this order around its boundary
+                            let ac = edge_vertex(inside[0], outside[0]);
+                            let ad = edge_vertex(inside[0], outside[1]);
+                            let bd = edge_vertex(inside[1], outside[1]);
+                            let bc = edge_vertex(inside[1], outside[0]);
+                            vertices.extend(wind([ac, ad, bd], outward));
+                            vertices.extend(wind([ac, bd, bc], outward));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    vertices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marching_cubes::tests::{
+        assert_closed, assert_on_sphere, sphere_density, SPHERE_DIM,
+    };
+
+    #[test]
+    fn empty_and_full_grids_have_no_triangles() {
+        assert!(mesh(4, 0.5, |_| 0.0).is_empty());
+        assert!(mesh(4, 0.5, |_| 1.0).is_empty());
+    }
+
+    #[test]
+    fn sphere_is_closed_and_on_the_surface() {
+        let vertices = mesh(SPHERE_DIM, 0.5, |corner| {
+            sphere_density(corner.map(|c| c as f32))
+        });
+        assert_closed(&vertices);
+        assert_on_sphere(&vertices, 0.1);
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    cell::{Cell, RefCell},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use log::{info, warn};
+use wgpu::util::DrawIndirect;
+
+/// How many frames can be in flight before a frame's counts are skipped.
+const STAGING_BUFFER_COUNT: usize = 3;
+const DRAW_COMMAND_SIZE: u64 = std::mem::size_of::<DrawIndirect>() as u64;
+
+/// What the meshers built in one frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshStats {
+    pub vertices_per_chunk: Vec<u32>,
+    /// Vertices that fit in each chunk's part of the vertex buffer.
+    pub vertex_capacity: u32,
+}
+
+impl MeshStats {
+    /// Reads the vertex count of each chunk's indirect draw command.
+    fn from_draw_commands(bytes: &[u8], vertex_capacity: u32) -> Self {
+        Self {
+            vertices_per_chunk: bytes
+                .chunks_exact(DRAW_COMMAND_SIZE as usize)
+                .map(|command| bytemuck::pod_read_unaligned::<u32>(&command[..4]))
+                .collect(),
+            vertex_capacity,
+        }
+    }
+
+    pub fn triangles(&self) -> u32 {
+        self.vertices_per_chunk.iter().sum::<u32>() / 3
+    }
+
+    /// How much of the whole vertex buffer is used.
+    pub fn fill_ratio(&self) -> f32 {
+        let total: u32 = self.vertices_per_chunk.iter().sum();
+        total as f32 / (self.vertex_capacity as f32 * self.vertices_per_chunk.len() as f32)
+    }
+
+    /// How much of its part of the vertex buffer the fullest chunk uses.
+    /// Past 1 the mesher ran out of room and triangles were dropped.
+    pub fn fullest_chunk_ratio(&self) -> f32 {
+        let fullest = self.vertices_per_chunk.iter().copied().max().unwrap_or(0);
+        fullest as f32 / self.vertex_capacity as f32
+    }
+
+    pub fn log(&self) {
+        info!(
+            "Mesh: {} triangles, {:.1}% of the vertex buffer, fullest chunk {:.1}%, vertices per chunk {:?}",
+            self.triangles(),
+            self.fill_ratio() * 100.0,
+            self.fullest_chunk_ratio() * 100.0,
+            self.vertices_per_chunk
+        );
+        if self.fullest_chunk_ratio() > 1.0 {
+            warn!("A chunk's mesh didn't fit in the vertex buffer, raise VERTICES_PER_VOXEL");
+        }
+    }
+}
+
+/// Copies the indirect draw commands into a ring of staging buffers and maps them,
+/// so the vertex counts arrive a few frames late without stalling the GPU.
+pub struct MeshStatsReadback {
+    staging: Vec<StagingBuffer>,
+    // The staging buffer the next frame is copied into
+    next: Cell<usize>,
+    // Set when this frame's commands were copied, to be mapped once submitted
+    copied: Cell<Option<usize>>,
+    chunk_count: u32,
+    // Bytes between the draw commands of consecutive chunks
+    stride: u64,
+    vertex_capacity: u32,
+    latest: RefCell<Option<MeshStats>>,
+}
+
+struct StagingBuffer {
+    buffer: wgpu::Buffer,
+    // Copied into and not read back yet
+    in_flight: Cell<bool>,
+    // Set from the map callback once it can be read
+    mapped: Arc<AtomicBool>,
+}
+
+impl MeshStatsReadback {
+    pub fn new(device: &wgpu::Device, chunk_count: u32, stride: u64, vertex_capacity: u32) -> Self {
+        let staging = (0..STAGING_BUFFER_COUNT)
+            .map(|_| StagingBuffer {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("mesh_stats_staging_buffer"),
+                    size: DRAW_COMMAND_SIZE * chunk_count as u64,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                in_flight: Cell::new(false),
+                mapped: Arc::new(AtomicBool::new(false)),
+            })
+            .collect();
+        Self {
+            staging,
+            next: Cell::new(0),
+            copied: Cell::new(None),
+            chunk_count,
+            stride,
+            vertex_capacity,
+            latest: RefCell::new(None),
+        }
+    }
+
+    /// The most recent frame's stats that made it back, if any has.
+    pub fn latest(&self) -> Option<MeshStats> {
+        self.latest.borrow().clone()
+    }
+
+    /// Keeps the stats of every frame read back since the last call.
+    pub fn poll(&self, device: &wgpu::Device) {
+        device.poll(wgpu::Maintain::Poll);
+        // Oldest first, so the latest stats end up being the newest frame's
+        for offset in 0..STAGING_BUFFER_COUNT {
+            let staging = &self.staging[(self.next.get() + offset) % STAGING_BUFFER_COUNT];
+            if !staging.mapped.swap(false, Ordering::Acquire) {
+                continue;
+            }
+            {
+                let data = staging.buffer.slice(..).get_mapped_range();
+                *self.latest.borrow_mut() =
+                    Some(MeshStats::from_draw_commands(&data, self.vertex_capacity));
+            }
+            staging.buffer.unmap();
+            staging.in_flight.set(false);
+        }
+    }
+
+    /// Copies this frame's draw commands, after the meshers have run.
+    /// Skipped when every staging buffer is still waiting to be read.
+    pub fn copy(&self, encoder: &mut wgpu::CommandEncoder, indirect_draw_buffer: &wgpu::Buffer) {
+        let index = self.next.get();
+        let staging = &self.staging[index];
+        if staging.in_flight.get() {
+            return;
+        }
+        for chunk_id in 0..self.chunk_count as u64 {
+            encoder.copy_buffer_to_buffer(
+                indirect_draw_buffer,
+                chunk_id * self.stride,
+                &staging.buffer,
+                chunk_id * DRAW_COMMAND_SIZE,
+                DRAW_COMMAND_SIZE,
+            );
+        }
+        staging.in_flight.set(true);
+        self.copied.set(Some(index));
+        self.next.set((index + 1) % STAGING_BUFFER_COUNT);
+    }
+
+    /// Starts mapping the buffer copied into this frame, once it has been submitted.
+    pub fn end_frame(&self) {
+        let Some(index) = self.copied.take() else {
+            return;
+        };
+        let mapped = self.staging[index].mapped.clone();
+        self.staging[index]
+            .buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                if result.is_ok() {
+                    mapped.store(true, Ordering::Release);
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw_commands(vertex_counts: &[u32]) -> Vec<u8> {
+        vertex_counts
+            .iter()
+            .flat_map(|&count| [count, 1, 0, 0])
+            .flat_map(u32::to_ne_bytes)
+            .collect()
+    }
+
+    #[test]
+    fn vertex_counts_are_read_from_the_draw_commands() {
+        let stats = MeshStats::from_draw_commands(&draw_commands(&[3, 0, 300, 6]), 600);
+        assert_eq!(stats.vertices_per_chunk, vec![3, 0, 300, 6]);
+        assert_eq!(stats.triangles(), 103);
+    }
+
+    #[test]
+    fn fill_ratios_are_against_the_chunk_capacity() {
+        let stats = MeshStats::from_draw_commands(&draw_commands(&[100, 300]), 400);
+        assert_eq!(stats.fill_ratio(), 0.5);
+        assert_eq!(stats.fullest_chunk_ratio(), 0.75);
+    }
+}
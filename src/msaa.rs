@@ -0,0 +1,67 @@
+use log::info;
+use wgpu::{Color, LoadOp, Operations, RenderPassColorAttachment, StoreOp};
+
+use crate::{graphics::Graphics, lit_mesh::NORMAL_FORMAT, post::HDR_FORMAT, texture};
+
+/// The most samples per pixel up to `requested` that the lit mesh's targets support.
+pub(crate) fn sample_count(gfx: &Graphics, requested: u32) -> u32 {
+    let sample_count = gfx.supported_sample_count(
+        requested,
+        &[HDR_FORMAT, NORMAL_FORMAT, texture::Texture::DEPTH_FORMAT],
+    );
+    if sample_count != requested {
+        info!(
+            "{}x multisampling isn't supported, using {}x",
+            requested, sample_count
+        );
+    }
+    sample_count
+}
+
+/// Where the lit mesh is drawn when multisampling.
+/// The colors are resolved into the HDR and normal targets, the depth is drawn again without
+/// multisampling for the passes that read it.
+pub(crate) struct MsaaTargets {
+    pub color: texture::Texture,
+    pub normal: texture::Texture,
+    pub depth: texture::Texture,
+}
+
+impl MsaaTargets {
+    /// None when not multisampling.
+    pub fn new(gfx: &Graphics, sample_count: u32) -> Option<Self> {
+        if sample_count == 1 {
+            return None;
+        }
+        let target = |format, label| {
+            texture::Texture::create_multisampled_target(
+                gfx.device(),
+                gfx.config(),
+                format,
+                sample_count,
+                label,
+            )
+        };
+        Some(Self {
+            color: target(HDR_FORMAT, "msaa_color_target"),
+            normal: target(NORMAL_FORMAT, "msaa_normal_target"),
+            depth: target(texture::Texture::DEPTH_FORMAT, "msaa_depth_target"),
+        })
+    }
+}
+
+/// Draws to the target directly, or to its multisampled version and then resolves to it.
+/// Cleared to transparent for the sky to show through.
+pub(crate) fn color_attachment<'a>(
+    target: &'a texture::Texture,
+    msaa_target: Option<&'a texture::Texture>,
+) -> Option<RenderPassColorAttachment<'a>> {
+    Some(RenderPassColorAttachment {
+        view: &msaa_target.unwrap_or(target).view,
+        resolve_target: msaa_target.map(|_| &target.view),
+        ops: Operations {
+            load: LoadOp::Clear(Color::TRANSPARENT),
+            store: StoreOp::Store,
+        },
+    })
+}
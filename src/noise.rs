@@ -0,0 +1,74 @@
+use crate::shader;
+
+/// The noise the density is built from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Noise {
+    /// Features per chunk of the first octave.
+    pub frequency: f32,
+    /// How much the frequency grows from one octave to the next.
+    pub lacunarity: f32,
+    /// How much each octave adds. Octaves weighted 0 are skipped.
+    pub octave_weights: [f32; 4],
+    /// How fast the noise drifts, in chunks per second.
+    pub speed: f32,
+    /// Scales the summed octaves before they're sharpened.
+    pub gain: f32,
+    /// Higher values thin out the clouds and harden their edges.
+    pub sharpness: f32,
+    /// The density the surface is drawn at, from 0 to 1.
+    pub iso_level: f32,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            frequency: 0.5,
+            lacunarity: 2.0,
+            octave_weights: [1.0, 0.5, 0.2, 0.0],
+            speed: 1.0 / 14.0,
+            gain: 2.0,
+            sharpness: 1.2,
+            iso_level: 0.5,
+        }
+    }
+}
+
+impl Noise {
+    pub(crate) fn uniform(&self) -> NoiseUniform {
+        NoiseUniform {
+            octave_weights: self.octave_weights,
+            frequency: self.frequency,
+            lacunarity: self.lacunarity,
+            speed: self.speed,
+            gain: self.gain,
+            sharpness: self.sharpness,
+            iso_level: self.iso_level,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+// Mirrors NoiseUniform in shaders/cloud_density.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct NoiseUniform {
+    pub octave_weights: [f32; 4],
+    pub frequency: f32,
+    pub lacunarity: f32,
+    pub speed: f32,
+    pub gain: f32,
+    pub sharpness: f32,
+    pub iso_level: f32,
+    pub _padding: [f32; 2],
+}
+
+shader::assert_wgsl_layout!(NoiseUniform, 48, {
+    octave_weights: 0,
+    frequency: 16,
+    lacunarity: 20,
+    speed: 24,
+    gain: 28,
+    sharpness: 32,
+    iso_level: 36,
+    _padding: 40,
+});
@@ -0,0 +1,237 @@
+use std::{
+    io::Write as _,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+};
+
+use anyhow::{bail, Context as _};
+use log::info;
+
+use crate::{
+    capture, cloud_world::CloudWorld, graphics::Graphics, lighting::Lighting, post::PostProcessing,
+};
+
+/// How to render a sequence of frames without a window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderOptions {
+    pub width: u32,
+    pub height: u32,
+    /// Frames per second of world time.
+    pub fps: f32,
+    pub frames: u32,
+    /// Seconds of world time at the first frame.
+    pub start: f32,
+    /// Where the numbered PNGs are written.
+    pub output_dir: PathBuf,
+    /// Also encodes the frames into this video with ffmpeg, which has to be installed.
+    pub video: Option<PathBuf>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            fps: 30.0,
+            frames: 300,
+            start: 0.0,
+            output_dir: PathBuf::from("frames"),
+            video: None,
+        }
+    }
+}
+
+pub const USAGE: &str = "\
+Usage: nuage render [options]
+  --size <width>x<height>  Resolution of each frame [default: 1920x1080]
+  --fps <fps>              Frames per second of world time [default: 30]
+  --frames <count>         How many frames to render [default: 300]
+  --start <seconds>        World time of the first frame [default: 0]
+  --out <dir>              Where the numbered PNGs go [default: frames]
+  --video <file>           Also pipe the frames to ffmpeg to encode this file";
+
+impl RenderOptions {
+    /// Reads the options following the `render` subcommand.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("{} needs a value\n{}", flag, USAGE))
+            };
+            match flag.as_str() {
+                "--size" => {
+                    let size = value()?;
+                    let (width, height) = size
+                        .split_once('x')
+                        .and_then(|(width, height)| {
+                            Some((width.parse().ok()?, height.parse().ok()?))
+                        })
+                        .filter(|&(width, height)| width > 0 && height > 0)
+                        .with_context(|| format!("Expected a size like 1920x1080, got {}", size))?;
+                    options.width = width;
+                    options.height = height;
+                }
+                "--fps" => options.fps = value()?.parse().context("--fps")?,
+                "--frames" => options.frames = value()?.parse().context("--frames")?,
+                "--start" => options.start = value()?.parse().context("--start")?,
+                "--out" => options.output_dir = value()?.into(),
+                "--video" => options.video = Some(value()?.into()),
+                _ => bail!("Unknown option {}\n{}", flag, USAGE),
+            }
+        }
+        if !options.fps.is_finite() || options.fps <= 0.0 {
+            bail!("--fps has to be a number above 0");
+        }
+        // ffmpeg's yuv420p encoder can't take odd sizes, so catch them before rendering anything
+        if options.video.is_some() && (options.width % 2 == 1 || options.height % 2 == 1) {
+            bail!(
+                "--video needs an even width and height, got {}x{}",
+                options.width,
+                options.height
+            );
+        }
+        Ok(options)
+    }
+
+    /// World time of a frame. Every frame is placed from the start, so none depends on the ones before it.
+    fn frame_seconds(&self, frame: u32) -> f32 {
+        self.start + frame as f32 / self.fps
+    }
+}
+
+/// Renders the frames one at a time, writing each before drawing the next.
+pub async fn render(
+    options: RenderOptions,
+    lighting: Lighting,
+    post_processing: PostProcessing,
+) -> anyhow::Result<()> {
+    let gfx = Graphics::new_headless(options.width, options.height).await?;
+    let mut cloud_world = CloudWorld::new(&gfx, lighting, post_processing);
+    // Moved by hand from frame to frame
+    cloud_world.clock_mut().paused = true;
+
+    std::fs::create_dir_all(&options.output_dir)
+        .with_context(|| format!("Couldn't create {:?}", options.output_dir))?;
+    let mut ffmpeg = options
+        .video
+        .as_ref()
+        .map(|video| spawn_ffmpeg(&options, video))
+        .transpose()?;
+
+    if let Err(e) = render_frames(&options, &gfx, &mut cloud_world, ffmpeg.as_mut()) {
+        if let Some(mut ffmpeg) = ffmpeg {
+            // Otherwise it's left waiting on its input for frames that never come
+            let _ = ffmpeg.kill();
+            let _ = ffmpeg.wait();
+        }
+        return Err(e);
+    }
+
+    if let Some(mut ffmpeg) = ffmpeg {
+        // Closing its input lets it finish the file
+        drop(ffmpeg.stdin.take());
+        let status = ffmpeg.wait()?;
+        if !status.success() {
+            bail!("ffmpeg failed with {}", status);
+        }
+    }
+    Ok(())
+}
+
+/// Renders, saves and pipes every frame to ffmpeg, if it was started.
+fn render_frames(
+    options: &RenderOptions,
+    gfx: &Graphics,
+    cloud_world: &mut CloudWorld,
+    mut ffmpeg: Option<&mut Child>,
+) -> anyhow::Result<()> {
+    for frame in 0..options.frames {
+        cloud_world.clock_mut().seek(options.frame_seconds(frame));
+        cloud_world.update();
+        cloud_world.render(gfx, |_, _, _| {})?;
+        let pixels = capture::read_texture(gfx, gfx.frame()?.texture());
+
+        let path = options.output_dir.join(format!("frame_{:05}.png", frame));
+        capture::save_png(&path, options.width, options.height, &pixels)?;
+        if let Some(ffmpeg) = &mut ffmpeg {
+            ffmpeg
+                .stdin
+                .as_mut()
+                .unwrap()
+                .write_all(&pixels)
+                .context("ffmpeg stopped reading frames")?;
+        }
+        info!("Rendered frame {} of {}", frame + 1, options.frames);
+    }
+    Ok(())
+}
+
+/// Starts ffmpeg reading raw RGBA frames from its input.
+fn spawn_ffmpeg(options: &RenderOptions, video: &Path) -> anyhow::Result<Child> {
+    Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error"])
+        .args(["-f", "rawvideo", "-pixel_format", "rgba"])
+        .args([
+            "-video_size",
+            &format!("{}x{}", options.width, options.height),
+        ])
+        .args(["-framerate", &options.fps.to_string()])
+        .args(["-i", "-"])
+        // Most players only play 4:2:0, which needs even sizes
+        .args(["-pix_fmt", "yuv420p"])
+        .arg(video)
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Couldn't start ffmpeg, is it installed?")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> anyhow::Result<RenderOptions> {
+        RenderOptions::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn options_default_when_left_out() {
+        assert_eq!(parse(&[]).unwrap(), RenderOptions::default());
+    }
+
+    #[test]
+    fn every_option_is_read() {
+        let options = parse(&[
+            "--size", "640x360", "--fps", "60", "--frames", "12", "--start", "2.5", "--out",
+            "clips", "--video", "clip.mp4",
+        ])
+        .unwrap();
+        assert_eq!(
+            options,
+            RenderOptions {
+                width: 640,
+                height: 360,
+                fps: 60.0,
+                frames: 12,
+                start: 2.5,
+                output_dir: PathBuf::from("clips"),
+                video: Some(PathBuf::from("clip.mp4")),
+            }
+        );
+        assert_eq!(options.frame_seconds(30), 3.0);
+    }
+
+    #[test]
+    fn bad_options_are_errors() {
+        assert!(parse(&["--size", "640"]).is_err());
+        assert!(parse(&["--size", "0x360"]).is_err());
+        assert!(parse(&["--fps", "0"]).is_err());
+        assert!(parse(&["--fps", "NaN"]).is_err());
+        assert!(parse(&["--fps", "inf"]).is_err());
+        assert!(parse(&["--size", "641x360", "--video", "clip.mp4"]).is_err());
+        assert!(parse(&["--size", "641x360"]).is_ok());
+        assert!(parse(&["--frames"]).is_err());
+        assert!(parse(&["--loop"]).is_err());
+    }
+}
@@ -0,0 +1,320 @@
+use std::fmt::Debug;
+
+use egui_wgpu::renderer::ScreenDescriptor;
+use wgpu::{LoadOp, Operations, RenderPassColorAttachment, RenderPassDescriptor, StoreOp};
+use winit::{event::WindowEvent, window::Window};
+
+use crate::{
+    camera::Orbit,
+    chunks::{Mesher, CHUNK_COUNT, DENSITY_TEXTURE_DIM},
+    clock::Clock,
+    cloud_world::RenderMode,
+    debug_view::DebugView,
+    frame_stats::FrameStats,
+    graphics::Graphics,
+    lighting::Lighting,
+    noise::Noise,
+    post::PostProcessing,
+};
+
+/// Sliders for tuning the clouds while they're drawn, made with egui.
+/// Laid out before rendering, then drawn on top of the finished frame.
+pub struct Overlay {
+    context: egui::Context,
+    state: egui_winit::State,
+    renderer: egui_wgpu::Renderer,
+    visible: bool,
+    // Laid out by `run`, waiting to be drawn by `paint`
+    frame: Option<OverlayFrame>,
+}
+
+struct OverlayFrame {
+    primitives: Vec<egui::ClippedPrimitive>,
+    textures_delta: egui::TexturesDelta,
+    pixels_per_point: f32,
+}
+
+impl Overlay {
+    pub fn new(gfx: &Graphics) -> Self {
+        let context = egui::Context::default();
+        let state = egui_winit::State::new(
+            context.clone(),
+            egui::ViewportId::ROOT,
+            gfx.window(),
+            Some(gfx.window().scale_factor() as f32),
+            Some(gfx.device().limits().max_texture_dimension_2d as usize),
+        );
+        Self {
+            context,
+            state,
+            renderer: egui_wgpu::Renderer::new(gfx.device(), gfx.config().format, None, 1),
+            visible: true,
+            frame: None,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// Whether egui used the event, in which case nothing else should.
+    pub fn handle_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        self.visible && self.state.on_window_event(window, event).consumed
+    }
+
+    /// Lays out this frame's overlay with `build`, unless it's hidden.
+    pub fn run(&mut self, window: &Window, build: impl FnOnce(&egui::Context)) {
+        if !self.visible {
+            return;
+        }
+        let input = self.state.take_egui_input(window);
+        let output = self.context.run(input, build);
+        self.state
+            .handle_platform_output(window, output.platform_output);
+        self.frame = Some(OverlayFrame {
+            primitives: self
+                .context
+                .tessellate(output.shapes, output.pixels_per_point),
+            textures_delta: output.textures_delta,
+            pixels_per_point: output.pixels_per_point,
+        });
+    }
+
+    /// Draws what was laid out by the last `run` onto the target.
+    pub fn paint(
+        &mut self,
+        gfx: &Graphics,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+    ) {
+        let Some(frame) = self.frame.take() else {
+            return;
+        };
+        let screen = ScreenDescriptor {
+            size_in_pixels: [gfx.config().width, gfx.config().height],
+            pixels_per_point: frame.pixels_per_point,
+        };
+        for (id, delta) in &frame.textures_delta.set {
+            self.renderer
+                .update_texture(gfx.device(), gfx.queue(), *id, delta);
+        }
+        // Only paint callbacks record commands of their own, and the overlay has none
+        let _ = self.renderer.update_buffers(
+            gfx.device(),
+            gfx.queue(),
+            encoder,
+            &frame.primitives,
+            &screen,
+        );
+        {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("overlay_pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+            self.renderer
+                .render(&mut render_pass, &frame.primitives, &screen);
+        }
+        for id in &frame.textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+    }
+}
+
+/// Everything the tuning window shows and changes, borrowed from the world while it's laid out.
+pub(crate) struct Tuning<'a> {
+    pub stats: &'a FrameStats,
+    // Whether the mesh stats are from this frame's mesher
+    pub meshed: bool,
+    pub mesher: &'a mut Mesher,
+    pub render_mode: &'a mut RenderMode,
+    pub debug_view: &'a mut DebugView,
+    pub density_slice: &'a mut u32,
+    pub noise: &'a mut Noise,
+    pub clock: &'a mut Clock,
+    pub chunks_enabled: &'a mut [bool; CHUNK_COUNT as usize],
+    pub lighting: &'a mut Lighting,
+    pub post_processing: &'a mut PostProcessing,
+    pub orbit: &'a mut Orbit,
+}
+
+/// Lays out the controls for everything that can be changed while running.
+pub(crate) fn tuning_window(ctx: &egui::Context, tuning: Tuning<'_>) {
+    egui::Window::new("Clouds")
+        .default_width(280.0)
+        .show(ctx, |ui| {
+            egui::CollapsingHeader::new("Stats")
+                .default_open(true)
+                .show(ui, |ui| tuning.stats.ui(ui, tuning.meshed));
+            egui::CollapsingHeader::new("View")
+                .default_open(true)
+                .show(ui, |ui| {
+                    enum_combo(ui, "mesher", tuning.mesher, Mesher::next);
+                    enum_combo(ui, "render mode", tuning.render_mode, RenderMode::next);
+                    enum_combo(ui, "debug view", tuning.debug_view, DebugView::next);
+                    ui.add(
+                        egui::Slider::new(tuning.density_slice, 0..=DENSITY_TEXTURE_DIM - 1)
+                            .text("density slice"),
+                    );
+                });
+            ui.collapsing("Noise", |ui| noise(ui, tuning.noise));
+            ui.collapsing("Time", |ui| clock(ui, tuning.clock));
+            ui.collapsing("Chunks", |ui| chunks(ui, tuning.chunks_enabled));
+            ui.collapsing("Lighting", |ui| lighting(ui, tuning.lighting));
+            ui.collapsing("Post processing", |ui| {
+                post_processing(ui, tuning.post_processing)
+            });
+            ui.collapsing("Camera", |ui| orbit(ui, tuning.orbit));
+        });
+}
+
+/// Picks any value of an enum that `next` cycles through, starting from its default.
+pub fn enum_combo<T: Copy + Debug + Default + PartialEq>(
+    ui: &mut egui::Ui,
+    label: &str,
+    value: &mut T,
+    next: impl Fn(T) -> T,
+) {
+    egui::ComboBox::from_label(label)
+        .selected_text(format!("{:?}", value))
+        .show_ui(ui, |ui| {
+            let mut option = T::default();
+            loop {
+                ui.selectable_value(value, option, format!("{:?}", option));
+                option = next(option);
+                if option == T::default() {
+                    break;
+                }
+            }
+        });
+}
+
+pub fn noise(ui: &mut egui::Ui, noise: &mut Noise) {
+    ui.add(egui::Slider::new(&mut noise.iso_level, 0.0..=1.0).text("iso level"));
+    ui.add(
+        egui::Slider::new(&mut noise.frequency, 0.05..=4.0)
+            .logarithmic(true)
+            .text("frequency"),
+    );
+    ui.add(egui::Slider::new(&mut noise.lacunarity, 1.0..=4.0).text("lacunarity"));
+    for (octave, weight) in noise.octave_weights.iter_mut().enumerate() {
+        ui.add(egui::Slider::new(weight, 0.0..=1.0).text(format!("octave {}", octave + 1)));
+    }
+    ui.add(egui::Slider::new(&mut noise.speed, 0.0..=0.5).text("speed"));
+    ui.add(egui::Slider::new(&mut noise.gain, 0.5..=4.0).text("gain"));
+    ui.add(egui::Slider::new(&mut noise.sharpness, 0.5..=3.0).text("sharpness"));
+}
+
+/// Chunks left out are still in the density texture, but aren't meshed.
+pub fn chunks(ui: &mut egui::Ui, enabled: &mut [bool; CHUNK_COUNT as usize]) {
+    // Four to a row, one row per layer of the grid
+    egui::Grid::new("chunk_grid").show(ui, |ui| {
+        for (chunk_id, enabled) in enabled.iter_mut().enumerate() {
+            let coord = [chunk_id & 1, (chunk_id >> 1) & 1, (chunk_id >> 2) & 1];
+            ui.checkbox(enabled, format!("{:?}", coord));
+            if chunk_id % 4 == 3 {
+                ui.end_row();
+            }
+        }
+    });
+}
+
+pub fn clock(ui: &mut egui::Ui, clock: &mut Clock) {
+    let mut seconds = clock.seconds();
+    if ui
+        .add(egui::DragValue::new(&mut seconds).speed(0.1).suffix(" s"))
+        .changed()
+    {
+        clock.seek(seconds);
+    }
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut clock.paused, "paused");
+        if ui
+            .add_enabled(clock.paused, egui::Button::new("step"))
+            .clicked()
+        {
+            clock.step();
+        }
+    });
+    ui.add(egui::Slider::new(&mut clock.speed, 0.0..=10.0).text("speed"));
+    let mut fixed = clock.fixed_timestep.is_some();
+    ui.checkbox(&mut fixed, "fixed timestep");
+    let mut timestep = clock
+        .fixed_timestep
+        .unwrap_or(crate::clock::DEFAULT_STEP_SECONDS);
+    ui.add_enabled(
+        fixed,
+        egui::Slider::new(&mut timestep, 0.001..=1.0)
+            .logarithmic(true)
+            .text("seconds per frame"),
+    );
+    clock.fixed_timestep = fixed.then_some(timestep);
+}
+
+pub fn lighting(ui: &mut egui::Ui, lighting: &mut Lighting) {
+    vector(ui, "sun direction", &mut lighting.sun.direction);
+    color(ui, "sun color", &mut lighting.sun.color);
+    ui.add(egui::Slider::new(&mut lighting.sun.intensity, 0.0..=4.0).text("sun intensity"));
+    color(ui, "sky color", &mut lighting.ambient.sky_color);
+    color(ui, "ground color", &mut lighting.ambient.ground_color);
+    ui.add(egui::Slider::new(&mut lighting.ambient.intensity, 0.0..=2.0).text("ambient intensity"));
+    color(ui, "albedo", &mut lighting.material.albedo);
+    ui.add(egui::Slider::new(&mut lighting.material.wrap, 0.0..=1.0).text("wrap"));
+    ui.add(egui::Slider::new(&mut lighting.material.translucency, 0.0..=2.0).text("translucency"));
+    ui.add(egui::Slider::new(&mut lighting.material.rim_strength, 0.0..=1.0).text("rim strength"));
+    ui.add(egui::Slider::new(&mut lighting.material.rim_power, 0.5..=8.0).text("rim power"));
+    ui.add(
+        egui::Slider::new(&mut lighting.fog.density, 0.0..=0.1)
+            .logarithmic(true)
+            .text("fog density"),
+    );
+}
+
+/// Multisampling is left out, it's chosen once when the targets are made.
+pub fn post_processing(ui: &mut egui::Ui, post_processing: &mut PostProcessing) {
+    ui.add(
+        egui::Slider::new(&mut post_processing.exposure, 0.125..=8.0)
+            .logarithmic(true)
+            .text("exposure"),
+    );
+    ui.checkbox(&mut post_processing.bloom.enabled, "bloom");
+    ui.add(
+        egui::Slider::new(&mut post_processing.bloom.threshold, 0.0..=4.0).text("bloom threshold"),
+    );
+    ui.add(
+        egui::Slider::new(&mut post_processing.bloom.intensity, 0.0..=1.0).text("bloom intensity"),
+    );
+}
+
+pub fn orbit(ui: &mut egui::Ui, orbit: &mut Orbit) {
+    ui.add(egui::Slider::new(&mut orbit.speed, 0.0..=1.0).text("orbit speed"));
+    ui.add(egui::Slider::new(&mut orbit.radius, 2.0..=40.0).text("orbit radius"));
+    ui.add(egui::Slider::new(&mut orbit.height, 0.0..=20.0).text("orbit height"));
+}
+
+fn vector(ui: &mut egui::Ui, label: &str, vector: &mut [f32; 3]) {
+    ui.horizontal(|ui| {
+        for component in vector.iter_mut() {
+            ui.add(
+                egui::DragValue::new(component)
+                    .speed(0.01)
+                    .clamp_range(-1.0..=1.0),
+            );
+        }
+        ui.label(label);
+    });
+}
+
+fn color(ui: &mut egui::Ui, label: &str, color: &mut [f32; 3]) {
+    ui.horizontal(|ui| {
+        ui.color_edit_button_rgb(color);
+        ui.label(label);
+    });
+}
@@ -0,0 +1,481 @@
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    Color, LoadOp, PipelineLayout, RenderPipeline, ShaderModule, ShaderStages, TextureFormat,
+    TextureViewDimension,
+};
+
+use crate::{
+    fullscreen,
+    graphics::Graphics,
+    profiler::{GpuPass, Profiler},
+    shader::{self, Shader},
+    texture,
+};
+
+// Everything is drawn in HDR, then tone mapped onto the surface
+pub(crate) const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+// The bloom is blurred by downsampling the bright parts of the frame this many times, starting at half size
+const BLOOM_MIP_COUNT: u32 = 5;
+const ADDITIVE_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent::OVER,
+};
+
+/// How the HDR frame is turned into what's shown on screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PostProcessing {
+    /// Scales the frame's brightness before tone mapping.
+    pub exposure: f32,
+    pub bloom: Bloom,
+    /// Samples per pixel along the edges of the mesh, 1 turns multisampling off.
+    /// Lowered to the most the adapter supports.
+    pub msaa_samples: u32,
+}
+
+/// Glow around the brightest parts of the frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bloom {
+    pub enabled: bool,
+    /// How bright a pixel needs to be, after exposure, to start glowing.
+    pub threshold: f32,
+    /// How much of the glow is added back to the frame.
+    pub intensity: f32,
+}
+
+impl Default for PostProcessing {
+    fn default() -> Self {
+        Self {
+            exposure: 1.0,
+            bloom: Bloom::default(),
+            msaa_samples: 4,
+        }
+    }
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold: 1.0,
+            intensity: 0.3,
+        }
+    }
+}
+
+impl PostProcessing {
+    pub(crate) fn uniform(&self) -> PostUniform {
+        PostUniform {
+            exposure: self.exposure,
+            bloom_threshold: self.bloom.threshold,
+            bloom_intensity: if self.bloom.enabled {
+                self.bloom.intensity
+            } else {
+                0.0
+            },
+            _padding: 0.0,
+        }
+    }
+}
+
+// Mirrors PostUniform in shaders/post.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct PostUniform {
+    pub exposure: f32,
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    pub _padding: f32,
+}
+
+shader::assert_wgsl_layout!(PostUniform, 16, {
+    exposure: 0,
+    bloom_threshold: 4,
+    bloom_intensity: 8,
+    _padding: 12,
+});
+
+/// The HDR target everything is drawn into, and the bloom and tone mapping passes that put it on
+/// the surface.
+pub(crate) struct PostPasses {
+    buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    bind_group_layout: BindGroupLayout,
+    // Split from the rest since the first bloom mip is drawn to by the bloom passes
+    tonemap_bind_group_layout: BindGroupLayout,
+    pipeline_layout: PipelineLayout,
+    tonemap_pipeline_layout: PipelineLayout,
+    surface_format: TextureFormat,
+    prefilter_pipeline: RenderPipeline,
+    downsample_pipeline: RenderPipeline,
+    upsample_pipeline: RenderPipeline,
+    tonemap_pipeline: RenderPipeline,
+    targets: PostTargets,
+}
+
+/// Drawn at the surface's size, so recreated when it's resized.
+struct PostTargets {
+    hdr: texture::Texture,
+    // One view per mip, each drawn to by one bloom pass
+    bloom_mip_views: Vec<wgpu::TextureView>,
+    // Sample the HDR target, then each bloom mip
+    hdr_bind_group: BindGroup,
+    bloom_bind_groups: Vec<BindGroup>,
+    tonemap_bind_group: BindGroup,
+}
+
+impl PostPasses {
+    pub fn new(gfx: &Graphics, post_processing: &PostProcessing) -> Self {
+        let device = gfx.device();
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("post_uniform_buffer"),
+            contents: bytemuck::bytes_of(&post_processing.uniform()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("post_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let filterable_texture = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let entries = [
+            // Source
+            filterable_texture(0),
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ];
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("post_bind_group_layout"),
+            entries: &entries,
+        });
+        let tonemap_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("tonemap_bind_group_layout"),
+                entries: &[
+                    entries[0],
+                    entries[1],
+                    entries[2],
+                    // Bloom
+                    filterable_texture(3),
+                ],
+            });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("post_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let tonemap_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("tonemap_pipeline_layout"),
+                bind_group_layouts: &[&tonemap_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let surface_format = gfx.config().format;
+        let (prefilter_pipeline, downsample_pipeline, upsample_pipeline, tonemap_pipeline) =
+            create_pipelines(
+                device,
+                &pipeline_layout,
+                &tonemap_pipeline_layout,
+                &shader::create_shader_module(device, Shader::Post),
+                surface_format,
+            );
+        let targets = PostTargets::new(
+            gfx,
+            &bind_group_layout,
+            &tonemap_bind_group_layout,
+            &sampler,
+            &buffer,
+        );
+
+        Self {
+            buffer,
+            sampler,
+            bind_group_layout,
+            tonemap_bind_group_layout,
+            pipeline_layout,
+            tonemap_pipeline_layout,
+            surface_format,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            tonemap_pipeline,
+            targets,
+        }
+    }
+
+    pub fn resize(&mut self, gfx: &Graphics) {
+        self.targets = PostTargets::new(
+            gfx,
+            &self.bind_group_layout,
+            &self.tonemap_bind_group_layout,
+            &self.sampler,
+            &self.buffer,
+        );
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device, source: &str) {
+        if let Some((prefilter, downsample, upsample, tonemap)) =
+            shader::try_create_pipeline(device, Shader::Post, source, |module| {
+                create_pipelines(
+                    device,
+                    &self.pipeline_layout,
+                    &self.tonemap_pipeline_layout,
+                    module,
+                    self.surface_format,
+                )
+            })
+        {
+            self.prefilter_pipeline = prefilter;
+            self.downsample_pipeline = downsample;
+            self.upsample_pipeline = upsample;
+            self.tonemap_pipeline = tonemap;
+        }
+    }
+
+    pub fn write_uniform(&self, queue: &wgpu::Queue, post_processing: &PostProcessing) {
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::bytes_of(&post_processing.uniform()),
+        );
+    }
+
+    /// Where the frame is drawn before it's tone mapped.
+    pub fn hdr(&self) -> &texture::Texture {
+        &self.targets.hdr
+    }
+
+    /// Blooms the HDR target when `bloom` is set, then tone maps it onto `output`.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        profiler: &Profiler,
+        output: &wgpu::TextureView,
+        bloom: bool,
+    ) {
+        let targets = &self.targets;
+        if bloom {
+            // Down the mip chain, starting from the bright parts of the frame
+            for mip in 0..BLOOM_MIP_COUNT as usize {
+                let (pipeline, source) = if mip == 0 {
+                    (&self.prefilter_pipeline, &targets.hdr_bind_group)
+                } else {
+                    (
+                        &self.downsample_pipeline,
+                        &targets.bloom_bind_groups[mip - 1],
+                    )
+                };
+                fullscreen::draw(
+                    encoder,
+                    "bloom_downsample_pass",
+                    &targets.bloom_mip_views[mip],
+                    LoadOp::Clear(Color::BLACK),
+                    pipeline,
+                    source,
+                    profiler.render_pass(GpuPass::Bloom),
+                );
+            }
+            // And back up, adding each mip to the larger one
+            for mip in (0..BLOOM_MIP_COUNT as usize - 1).rev() {
+                fullscreen::draw(
+                    encoder,
+                    "bloom_upsample_pass",
+                    &targets.bloom_mip_views[mip],
+                    LoadOp::Load,
+                    &self.upsample_pipeline,
+                    &targets.bloom_bind_groups[mip + 1],
+                    profiler.render_pass(GpuPass::Bloom),
+                );
+            }
+        }
+        fullscreen::draw(
+            encoder,
+            "tonemap_pass",
+            output,
+            LoadOp::Clear(Color::BLACK),
+            &self.tonemap_pipeline,
+            &targets.tonemap_bind_group,
+            profiler.render_pass(GpuPass::Tonemap),
+        );
+    }
+}
+
+impl PostTargets {
+    fn new(
+        gfx: &Graphics,
+        layout: &BindGroupLayout,
+        tonemap_layout: &BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        buffer: &wgpu::Buffer,
+    ) -> Self {
+        let hdr = texture::Texture::create_render_target(
+            gfx.device(),
+            gfx.config(),
+            HDR_FORMAT,
+            "hdr_target",
+        );
+        let bloom_texture = gfx.device().create_texture(&wgpu::TextureDescriptor {
+            label: Some("bloom_texture"),
+            size: wgpu::Extent3d {
+                width: (gfx.config().width / 2).max(1),
+                height: (gfx.config().height / 2).max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: BLOOM_MIP_COUNT,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let bloom_mip_views: Vec<_> = (0..BLOOM_MIP_COUNT)
+            .map(|mip| {
+                bloom_texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("bloom_mip_view"),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let bind_group = |label, view| {
+            gfx.device().create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        let hdr_bind_group = bind_group("hdr_bind_group", &hdr.view);
+        let bloom_bind_groups = bloom_mip_views
+            .iter()
+            .map(|view| bind_group("bloom_bind_group", view))
+            .collect();
+        let tonemap_bind_group = gfx.device().create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("tonemap_bind_group"),
+            layout: tonemap_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&hdr.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&bloom_mip_views[0]),
+                },
+            ],
+        });
+
+        Self {
+            hdr,
+            bloom_mip_views,
+            hdr_bind_group,
+            bloom_bind_groups,
+            tonemap_bind_group,
+        }
+    }
+}
+
+/// The bloom prefilter, downsample and upsample pipelines, then the tone mapping one.
+fn create_pipelines(
+    device: &wgpu::Device,
+    layout: &PipelineLayout,
+    tonemap_layout: &PipelineLayout,
+    module: &ShaderModule,
+    surface_format: TextureFormat,
+) -> (
+    RenderPipeline,
+    RenderPipeline,
+    RenderPipeline,
+    RenderPipeline,
+) {
+    (
+        fullscreen::create_pipeline(
+            device,
+            "bloom_prefilter_pipeline",
+            layout,
+            module,
+            "fs_prefilter",
+            HDR_FORMAT,
+            None,
+        ),
+        fullscreen::create_pipeline(
+            device,
+            "bloom_downsample_pipeline",
+            layout,
+            module,
+            "fs_downsample",
+            HDR_FORMAT,
+            None,
+        ),
+        fullscreen::create_pipeline(
+            device,
+            "bloom_upsample_pipeline",
+            layout,
+            module,
+            "fs_upsample",
+            HDR_FORMAT,
+            Some(ADDITIVE_BLEND),
+        ),
+        fullscreen::create_pipeline(
+            device,
+            "tonemap_pipeline",
+            tonemap_layout,
+            module,
+            "fs_tonemap",
+            surface_format,
+            None,
+        ),
+    )
+}
@@ -0,0 +1,423 @@
+use std::{
+    cell::{Cell, RefCell},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use log::info;
+
+/// Passes timed on the GPU, when the device supports timestamp queries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GpuPass {
+    Density,
+    Meshing,
+    Shadows,
+    Mesh,
+    DepthPrepass,
+    AmbientOcclusion,
+    Volume,
+    Sky,
+    Bloom,
+    Tonemap,
+    Debug,
+}
+
+impl GpuPass {
+    const ALL: [GpuPass; 11] = [
+        GpuPass::Density,
+        GpuPass::Meshing,
+        GpuPass::Shadows,
+        GpuPass::Mesh,
+        GpuPass::DepthPrepass,
+        GpuPass::AmbientOcclusion,
+        GpuPass::Volume,
+        GpuPass::Sky,
+        GpuPass::Bloom,
+        GpuPass::Tonemap,
+        GpuPass::Debug,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            GpuPass::Density => "density",
+            GpuPass::Meshing => "meshing",
+            GpuPass::Shadows => "shadows",
+            GpuPass::Mesh => "mesh",
+            GpuPass::DepthPrepass => "depth prepass",
+            GpuPass::AmbientOcclusion => "ambient occlusion",
+            GpuPass::Volume => "volume",
+            GpuPass::Sky => "sky",
+            GpuPass::Bloom => "bloom",
+            GpuPass::Tonemap => "tone mapping",
+            GpuPass::Debug => "debug view",
+        }
+    }
+}
+
+/// Parts of a frame timed on the CPU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuStage {
+    /// Moving the camera and everything else done before drawing.
+    Update,
+    /// Waiting for the surface to hand over the next frame.
+    Acquire,
+    /// Writing the uniforms and recording the passes.
+    Encode,
+    /// Submitting the commands and presenting the frame.
+    Submit,
+}
+
+impl CpuStage {
+    const ALL: [CpuStage; 4] = [
+        CpuStage::Update,
+        CpuStage::Acquire,
+        CpuStage::Encode,
+        CpuStage::Submit,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            CpuStage::Update => "update",
+            CpuStage::Acquire => "acquire",
+            CpuStage::Encode => "encode",
+            CpuStage::Submit => "submit",
+        }
+    }
+}
+
+// Passes beyond this in a frame go untimed
+const MAX_TIMED_PASSES: u32 = 64;
+const QUERY_SIZE: u64 = std::mem::size_of::<u64>() as u64;
+
+/// Time spent in each pass and stage, summed until the next log.
+/// Every pass gets its own pair of timestamps, which are read back a frame or more later
+/// without stalling the GPU. Frames are skipped while the last ones are still being read.
+pub struct Profiler {
+    // Only there with TIMESTAMP_QUERY
+    timestamps: Option<Timestamps>,
+    gpu: RefCell<Totals<{ GpuPass::ALL.len() }>>,
+    cpu: RefCell<Totals<{ CpuStage::ALL.len() }>>,
+    last_summary: RefCell<Summary>,
+}
+
+/// The milliseconds per frame from the last log, kept around to be shown.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Summary {
+    pub cpu: String,
+    // Only there when the GPU was timed
+    pub gpu: Option<String>,
+}
+
+struct Timestamps {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // Nanoseconds per tick
+    period: f32,
+    // The pass timed by each pair of queries this frame
+    passes: RefCell<Vec<GpuPass>>,
+    // And the frame being read back, if any
+    readback_passes: RefCell<Vec<GpuPass>>,
+    // Set from the map callback once the readback buffer can be read
+    mapped: Arc<AtomicBool>,
+    map_requested: Cell<bool>,
+}
+
+/// Summed durations and how often each was timed, per kind of pass or stage.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Totals<const N: usize> {
+    seconds: [f64; N],
+    counts: [u32; N],
+    frames: u32,
+}
+
+impl<const N: usize> Default for Totals<N> {
+    fn default() -> Self {
+        Self {
+            seconds: [0.0; N],
+            counts: [0; N],
+            frames: 0,
+        }
+    }
+}
+
+impl<const N: usize> Totals<N> {
+    fn add(&mut self, index: usize, seconds: f64) {
+        self.seconds[index] += seconds;
+        self.counts[index] += 1;
+    }
+
+    /// Milliseconds per frame of everything timed, and per pass for those timed more than once a frame.
+    fn summary(&self, labels: impl Iterator<Item = &'static str>) -> String {
+        let frames = self.frames.max(1) as f64;
+        labels
+            .enumerate()
+            .filter(|&(index, _)| self.counts[index] > 0)
+            .map(|(index, label)| {
+                let per_frame = self.seconds[index] * 1000.0 / frames;
+                if self.counts[index] as f64 > frames {
+                    let each = self.seconds[index] * 1000.0 / self.counts[index] as f64;
+                    format!("{} {:.3} ({:.3} each)", label, per_frame, each)
+                } else {
+                    format!("{} {:.3}", label, per_frame)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl Profiler {
+    /// Only times the CPU unless the device has TIMESTAMP_QUERY.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let timestamps = device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| {
+                let size = QUERY_SIZE * 2 * MAX_TIMED_PASSES as u64;
+                Timestamps {
+                    query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                        label: Some("profiler_query_set"),
+                        ty: wgpu::QueryType::Timestamp,
+                        count: 2 * MAX_TIMED_PASSES,
+                    }),
+                    resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("profiler_resolve_buffer"),
+                        size,
+                        usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                        mapped_at_creation: false,
+                    }),
+                    readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("profiler_readback_buffer"),
+                        size,
+                        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    }),
+                    period: queue.get_timestamp_period(),
+                    passes: RefCell::new(Vec::new()),
+                    readback_passes: RefCell::new(Vec::new()),
+                    mapped: Arc::new(AtomicBool::new(false)),
+                    map_requested: Cell::new(false),
+                }
+            });
+        if timestamps.is_none() {
+            info!("Timestamp queries aren't supported, only timing the CPU");
+        }
+        Self {
+            timestamps,
+            gpu: RefCell::new(Totals::default()),
+            cpu: RefCell::new(Totals::default()),
+            last_summary: RefCell::new(Summary::default()),
+        }
+    }
+
+    pub fn record_cpu(&self, stage: CpuStage, duration: Duration) {
+        let index = CpuStage::ALL.iter().position(|&s| s == stage).unwrap();
+        self.cpu.borrow_mut().add(index, duration.as_secs_f64());
+    }
+
+    /// Collects the timings of earlier frames that have been read back, and starts timing a new one.
+    pub fn begin_frame(&self, device: &wgpu::Device) {
+        self.cpu.borrow_mut().frames += 1;
+        let Some(timestamps) = &self.timestamps else {
+            return;
+        };
+        timestamps.passes.borrow_mut().clear();
+
+        // Runs the map callback if the GPU is done
+        device.poll(wgpu::Maintain::Poll);
+        if !timestamps.mapped.swap(false, Ordering::Acquire) {
+            return;
+        }
+        {
+            let data = timestamps.readback_buffer.slice(..).get_mapped_range();
+            let ticks: &[u64] = bytemuck::cast_slice(&data);
+            let mut gpu = self.gpu.borrow_mut();
+            for (pass, seconds) in pass_seconds(
+                &timestamps.readback_passes.borrow(),
+                ticks,
+                timestamps.period,
+            ) {
+                let index = GpuPass::ALL.iter().position(|&p| p == pass).unwrap();
+                gpu.add(index, seconds);
+            }
+            gpu.frames += 1;
+        }
+        timestamps.readback_buffer.unmap();
+        timestamps.readback_passes.borrow_mut().clear();
+    }
+
+    fn reading_back(timestamps: &Timestamps) -> bool {
+        !timestamps.readback_passes.borrow().is_empty()
+    }
+
+    /// The next pair of queries, unless they're all taken or the last frame is still being read.
+    fn next_queries(&self, pass: GpuPass) -> Option<(&wgpu::QuerySet, u32)> {
+        let timestamps = self.timestamps.as_ref()?;
+        if Self::reading_back(timestamps) {
+            return None;
+        }
+        let mut passes = timestamps.passes.borrow_mut();
+        let index = passes.len() as u32;
+        if index >= MAX_TIMED_PASSES {
+            return None;
+        }
+        passes.push(pass);
+        Some((&timestamps.query_set, 2 * index))
+    }
+
+    pub fn compute_pass(&self, pass: GpuPass) -> Option<wgpu::ComputePassTimestampWrites<'_>> {
+        self.next_queries(pass)
+            .map(|(query_set, index)| wgpu::ComputePassTimestampWrites {
+                query_set,
+                beginning_of_pass_write_index: Some(index),
+                end_of_pass_write_index: Some(index + 1),
+            })
+    }
+
+    pub fn render_pass(&self, pass: GpuPass) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        self.next_queries(pass)
+            .map(|(query_set, index)| wgpu::RenderPassTimestampWrites {
+                query_set,
+                beginning_of_pass_write_index: Some(index),
+                end_of_pass_write_index: Some(index + 1),
+            })
+    }
+
+    /// Copies this frame's timestamps to be read back, after its last pass.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(timestamps) = &self.timestamps else {
+            return;
+        };
+        let passes = timestamps.passes.borrow();
+        if passes.is_empty() || Self::reading_back(timestamps) {
+            return;
+        }
+        let query_count = 2 * passes.len() as u32;
+        encoder.resolve_query_set(
+            &timestamps.query_set,
+            0..query_count,
+            &timestamps.resolve_buffer,
+            0,
+        );
+        encoder.copy_buffer_to_buffer(
+            &timestamps.resolve_buffer,
+            0,
+            &timestamps.readback_buffer,
+            0,
+            QUERY_SIZE * query_count as u64,
+        );
+        *timestamps.readback_passes.borrow_mut() = passes.clone();
+        timestamps.map_requested.set(true);
+    }
+
+    /// Starts reading back the resolved timestamps, once the frame has been submitted.
+    pub fn end_frame(&self) {
+        let Some(timestamps) = &self.timestamps else {
+            return;
+        };
+        if !timestamps.map_requested.replace(false) {
+            return;
+        }
+        let mapped = timestamps.mapped.clone();
+        timestamps
+            .readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                if result.is_ok() {
+                    mapped.store(true, Ordering::Release);
+                }
+            });
+    }
+
+    /// What was logged last, empty until the first log.
+    pub fn last_summary(&self) -> Summary {
+        self.last_summary.borrow().clone()
+    }
+
+    /// Logs the average time per frame since the last log.
+    pub fn log_and_reset(&self) {
+        let mut cpu = self.cpu.borrow_mut();
+        let cpu_summary = cpu.summary(CpuStage::ALL.iter().map(|stage| stage.label()));
+        info!("CPU ms per frame: {}", cpu_summary);
+        *cpu = Totals::default();
+
+        let mut gpu = self.gpu.borrow_mut();
+        let gpu_summary =
+            (gpu.frames > 0).then(|| gpu.summary(GpuPass::ALL.iter().map(|pass| pass.label())));
+        if let Some(gpu_summary) = &gpu_summary {
+            info!("GPU ms per frame: {}", gpu_summary);
+        }
+        *gpu = Totals::default();
+
+        *self.last_summary.borrow_mut() = Summary {
+            cpu: cpu_summary,
+            gpu: gpu_summary,
+        };
+    }
+}
+
+/// Seconds spent in each pass, from the begin and end ticks written around it.
+fn pass_seconds<'a>(
+    passes: &'a [GpuPass],
+    ticks: &'a [u64],
+    period: f32,
+) -> impl Iterator<Item = (GpuPass, f64)> + 'a {
+    passes
+        .iter()
+        .zip(ticks.chunks_exact(2))
+        .map(move |(&pass, pair)| {
+            // Some drivers reorder timestamps across passes, never count those as negative
+            let elapsed = pair[1].saturating_sub(pair[0]);
+            (pass, elapsed as f64 * period as f64 / 1e9)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_take_the_time_between_their_ticks() {
+        let passes = [GpuPass::Density, GpuPass::Mesh];
+        let ticks = [100, 300, 300, 250];
+        let seconds: Vec<_> = pass_seconds(&passes, &ticks, 2.0).collect();
+        assert_eq!(
+            seconds,
+            vec![(GpuPass::Density, 400e-9), (GpuPass::Mesh, 0.0)]
+        );
+    }
+
+    #[test]
+    fn passes_timed_more_than_once_a_frame_are_also_averaged_each() {
+        let mut totals = Totals::<2> {
+            frames: 2,
+            ..Default::default()
+        };
+        for _ in 0..8 {
+            totals.add(0, 0.001);
+        }
+        totals.add(1, 0.004);
+        totals.add(1, 0.002);
+        assert_eq!(
+            totals.summary(["density", "mesh"].into_iter()),
+            "density 4.000 (1.000 each), mesh 3.000"
+        );
+    }
+
+    #[test]
+    fn untimed_passes_are_left_out() {
+        let mut totals = Totals::<2> {
+            frames: 1,
+            ..Default::default()
+        };
+        totals.add(1, 0.002);
+        assert_eq!(
+            totals.summary(["density", "mesh"].into_iter()),
+            "mesh 2.000"
+        );
+    }
+}
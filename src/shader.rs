@@ -0,0 +1,649 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{anyhow, Context as _};
+use log::{error, info};
+use pollster::FutureExt as _;
+
+use crate::{chunks::VOXELS_PER_CHUNK_DIM, shadow};
+
+/// Environment variable that turns on shader hot reloading, e.g. `NUAGE_HOT_RELOAD=1 cargo run`.
+const HOT_RELOAD_ENV_VAR: &str = "NUAGE_HOT_RELOAD";
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Constants declared at the top of every shader, so they can't drift from the Rust side.
+pub const SHADER_CONSTANTS: &[(&str, u32)] = &[
+    ("VOXELS_PER_CHUNK_DIM", VOXELS_PER_CHUNK_DIM),
+    ("SHADOW_CASCADE_COUNT", shadow::CASCADE_COUNT as u32),
+];
+
+/// Files that shaders can pull in with `#include "<file name>"`.
+const INCLUDES: &[(&str, &str)] = &[
+    ("common.wgsl", include_str!("./shaders/common.wgsl")),
+    ("atmosphere.wgsl", include_str!("./shaders/atmosphere.wgsl")),
+    ("mesher.wgsl", include_str!("./shaders/mesher.wgsl")),
+    (
+        "surface_shading.wgsl",
+        include_str!("./shaders/surface_shading.wgsl"),
+    ),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shader {
+    CloudDensity,
+    MarchingCubes,
+    MarchingTetrahedra,
+    SurfaceNets,
+    ChunkRender,
+    MeshRender,
+    ShadowMap,
+    Ssao,
+    Sky,
+    CloudVolume,
+    Post,
+    Debug,
+}
+
+impl Shader {
+    pub const ALL: [Shader; 12] = [
+        Shader::CloudDensity,
+        Shader::MarchingCubes,
+        Shader::MarchingTetrahedra,
+        Shader::SurfaceNets,
+        Shader::ChunkRender,
+        Shader::MeshRender,
+        Shader::ShadowMap,
+        Shader::Ssao,
+        Shader::Sky,
+        Shader::CloudVolume,
+        Shader::Post,
+        Shader::Debug,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Shader::CloudDensity => "cloud_density_shader",
+            Shader::MarchingCubes => "marching_cubes_shader",
+            Shader::MarchingTetrahedra => "marching_tetrahedra_shader",
+            Shader::SurfaceNets => "surface_nets_shader",
+            Shader::ChunkRender => "chunk_render_shader",
+            Shader::MeshRender => "mesh_render_shader",
+            Shader::ShadowMap => "shadow_map_shader",
+            Shader::Ssao => "ssao_shader",
+            Shader::Sky => "sky_shader",
+            Shader::CloudVolume => "cloud_volume_shader",
+            Shader::Post => "post_shader",
+            Shader::Debug => "debug_shader",
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            Shader::CloudDensity => "cloud_density.wgsl",
+            Shader::MarchingCubes => "marching_cubes.wgsl",
+            Shader::MarchingTetrahedra => "marching_tetrahedra.wgsl",
+            Shader::SurfaceNets => "surface_nets.wgsl",
+            Shader::ChunkRender => "chunk_render.wgsl",
+            Shader::MeshRender => "mesh_render.wgsl",
+            Shader::ShadowMap => "shadow_map.wgsl",
+            Shader::Ssao => "ssao.wgsl",
+            Shader::Sky => "sky.wgsl",
+            Shader::CloudVolume => "cloud_volume.wgsl",
+            Shader::Post => "post.wgsl",
+            Shader::Debug => "debug.wgsl",
+        }
+    }
+
+    /// The source compiled into the binary.
+    fn embedded_source(self) -> &'static str {
+        match self {
+            Shader::CloudDensity => include_str!("./shaders/cloud_density.wgsl"),
+            Shader::MarchingCubes => include_str!("./shaders/marching_cubes.wgsl"),
+            Shader::MarchingTetrahedra => include_str!("./shaders/marching_tetrahedra.wgsl"),
+            Shader::SurfaceNets => include_str!("./shaders/surface_nets.wgsl"),
+            Shader::ChunkRender => include_str!("./shaders/chunk_render.wgsl"),
+            Shader::MeshRender => include_str!("./shaders/mesh_render.wgsl"),
+            Shader::ShadowMap => include_str!("./shaders/shadow_map.wgsl"),
+            Shader::Ssao => include_str!("./shaders/ssao.wgsl"),
+            Shader::Sky => include_str!("./shaders/sky.wgsl"),
+            Shader::CloudVolume => include_str!("./shaders/cloud_volume.wgsl"),
+            Shader::Post => include_str!("./shaders/post.wgsl"),
+            Shader::Debug => include_str!("./shaders/debug.wgsl"),
+        }
+    }
+
+    /// Where the shader lives in the source tree, for reloading it while the app runs.
+    pub fn path(self) -> PathBuf {
+        shader_path(self.file_name())
+    }
+
+    /// Builds the final WGSL for this shader: the given constants are declared up front
+    /// and every `#include` is replaced by the file it names.
+    /// Sources are read from disk when `from_disk` is set, otherwise the embedded ones are used.
+    pub fn compose(self, constants: &[(&str, u32)], from_disk: bool) -> anyhow::Result<String> {
+        let source = load(self.file_name(), self.embedded_source(), from_disk)?;
+
+        let mut composed = String::new();
+        for (name, value) in constants {
+            composed.push_str(&format!("const {name}: u32 = {value}u;\n"));
+        }
+        append_with_includes(&mut composed, &source, from_disk, &mut HashSet::new())
+            .with_context(|| format!("Failed to compose {}", self.file_name()))?;
+        Ok(composed)
+    }
+
+    pub fn create_module(self, device: &wgpu::Device, source: &str) -> wgpu::ShaderModule {
+        device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(self.label()),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
+        })
+    }
+}
+
+fn shader_path(file_name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("src")
+        .join("shaders")
+        .join(file_name)
+}
+
+fn load(
+    file_name: &str,
+    embedded: &'static str,
+    from_disk: bool,
+) -> anyhow::Result<Cow<'static, str>> {
+    if from_disk {
+        let path = shader_path(file_name);
+        let source = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Cow::Owned(source))
+    } else {
+        Ok(Cow::Borrowed(embedded))
+    }
+}
+
+/// Each file is only included once, so includes can't recurse or declare things twice.
+fn append_with_includes(
+    composed: &mut String,
+    source: &str,
+    from_disk: bool,
+    included: &mut HashSet<&'static str>,
+) -> anyhow::Result<()> {
+    for line in source.lines() {
+        let Some(include) = line.trim().strip_prefix("#include") else {
+            composed.push_str(line);
+            composed.push('\n');
+            continue;
+        };
+
+        let file_name = include.trim().trim_matches('"');
+        let &(file_name, embedded) = INCLUDES
+            .iter()
+            .find(|(name, _)| *name == file_name)
+            .ok_or_else(|| anyhow!("Unknown include \"{}\"", file_name))?;
+        if included.insert(file_name) {
+            let source = load(file_name, embedded, from_disk)?;
+            append_with_includes(composed, &source, from_disk, included)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn hot_reload_enabled() -> bool {
+    std::env::var_os(HOT_RELOAD_ENV_VAR).is_some()
+}
+
+/// Runs `f`, capturing any validation error it raises instead of letting wgpu panic.
+/// Shader compilation errors from naga are reported this way.
+pub fn catch_validation_errors<T>(
+    device: &wgpu::Device,
+    f: impl FnOnce() -> T,
+) -> Result<T, wgpu::Error> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = f();
+    match device.pop_error_scope().block_on() {
+        Some(error) => Err(error),
+        None => Ok(value),
+    }
+}
+
+/// The embedded shader, composed with `SHADER_CONSTANTS`.
+pub(crate) fn create_shader_module(device: &wgpu::Device, shader: Shader) -> wgpu::ShaderModule {
+    let source = shader
+        .compose(SHADER_CONSTANTS, false)
+        .expect("Embedded shaders should compose");
+    shader.create_module(device, &source)
+}
+
+/// The shaders composed for another number of voxels per chunk, for meshing outside of `CloudWorld`.
+pub(crate) fn create_shader_module_with_resolution(
+    device: &wgpu::Device,
+    shader: Shader,
+    voxels_per_chunk_dim: u32,
+) -> wgpu::ShaderModule {
+    let constants: Vec<_> = SHADER_CONSTANTS
+        .iter()
+        .map(|&(name, value)| match name {
+            "VOXELS_PER_CHUNK_DIM" => (name, voxels_per_chunk_dim),
+            _ => (name, value),
+        })
+        .collect();
+    let source = shader
+        .compose(&constants, false)
+        .expect("Embedded shaders should compose");
+    shader.create_module(device, &source)
+}
+
+/// Builds pipelines from a reloaded shader, keeping wgpu from panicking if it doesn't compile.
+pub(crate) fn try_create_pipeline<T>(
+    device: &wgpu::Device,
+    shader: Shader,
+    source: &str,
+    create: impl FnOnce(&wgpu::ShaderModule) -> T,
+) -> Option<T> {
+    let result = catch_validation_errors(device, || create(&shader.create_module(device, source)));
+    match result {
+        Ok(pipelines) => {
+            info!("Reloaded {}", shader.label());
+            Some(pipelines)
+        }
+        Err(e) => {
+            error!(
+                "Failed to reload {}, keeping the last good pipeline:\n{}",
+                shader.label(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Polls the shader files on disk for changes.
+/// We only have a handful of small files, so checking modification times
+/// a few times a second is plenty.
+pub struct ShaderWatcher {
+    // Each watched file, along with the shaders that need recompiling when it changes
+    watched: Vec<(PathBuf, Option<SystemTime>, Vec<Shader>)>,
+    last_poll_instant: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> Self {
+        let shaders = Shader::ALL
+            .iter()
+            .map(|&shader| (shader.path(), vec![shader]));
+        // Includes are cheap to over-approximate: any change recompiles everything.
+        let includes = INCLUDES
+            .iter()
+            .map(|(file_name, _)| (shader_path(file_name), Shader::ALL.to_vec()));
+        Self {
+            watched: shaders
+                .chain(includes)
+                .map(|(path, shaders)| {
+                    let modified = modified_time(&path);
+                    (path, modified, shaders)
+                })
+                .collect(),
+            last_poll_instant: Instant::now(),
+        }
+    }
+
+    /// Returns the shaders whose files changed since the last poll.
+    pub fn poll(&mut self) -> Vec<Shader> {
+        if self.last_poll_instant.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll_instant = Instant::now();
+
+        let mut changed = Vec::new();
+        for (path, last_modified, shaders) in self.watched.iter_mut() {
+            let modified = modified_time(path);
+            if modified.is_some() && modified != *last_modified {
+                *last_modified = modified;
+                for shader in shaders {
+                    if !changed.contains(shader) {
+                        changed.push(*shader);
+                    }
+                }
+            }
+        }
+        changed
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Fails the build when the size or member offsets of a `#[repr(C)]` struct change from the
+/// numbers given. Those numbers are typed in by hand, it's the `*_matches_wgsl` tests below that
+/// compare them with naga's layout of the WGSL struct the Rust one mirrors.
+macro_rules! assert_wgsl_layout {
+    ($type:ty, $size:expr, { $($field:ident: $offset:expr),* $(,)? }) => {
+        const _: () = {
+            assert!(std::mem::size_of::<$type>() == $size);
+            $(assert!(std::mem::offset_of!($type, $field) == $offset);)*
+        };
+    };
+}
+pub(crate) use assert_wgsl_layout;
+
+#[cfg(test)]
+mod tests {
+    use std::mem::{offset_of, size_of};
+
+    use naga::valid::{Capabilities, ValidationFlags, Validator};
+    use wgpu::util::DrawIndirect;
+
+    use super::*;
+    use crate::{
+        camera::CameraUniform,
+        chunks::{PushConstants, Vertex, VERTEX_ATTRIBUTES},
+        debug_view::{DebugUniform, DebugView},
+        lighting::LightingUniform,
+        noise::NoiseUniform,
+        post::PostUniform,
+        shadow::ShadowUniform,
+    };
+
+    fn parse(shader: Shader) -> naga::Module {
+        let source = shader.compose(SHADER_CONSTANTS, false).unwrap();
+        let module = naga::front::wgsl::parse_str(&source)
+            .unwrap_or_else(|e| panic!("{}", e.emit_to_string(&source)));
+        Validator::new(ValidationFlags::all(), Capabilities::all())
+            .validate(&module)
+            .unwrap_or_else(|e| panic!("{} failed validation: {:?}", shader.label(), e));
+        module
+    }
+
+    /// The size of the named struct and the offset of each of its members.
+    fn struct_layout(module: &naga::Module, name: &str) -> Option<(usize, Vec<(String, usize)>)> {
+        module.types.iter().find_map(|(_, ty)| match &ty.inner {
+            naga::TypeInner::Struct { members, span } if ty.name.as_deref() == Some(name) => {
+                let members = members
+                    .iter()
+                    .map(|m| (m.name.clone().unwrap_or_default(), m.offset as usize))
+                    .collect();
+                Some((*span as usize, members))
+            }
+            _ => None,
+        })
+    }
+
+    fn layout(size: usize, members: &[(&str, usize)]) -> Option<(usize, Vec<(String, usize)>)> {
+        Some((
+            size,
+            members
+                .iter()
+                .map(|&(name, offset)| (name.to_owned(), offset))
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn shaders_compose_and_validate() {
+        for shader in Shader::ALL {
+            parse(shader);
+        }
+    }
+
+    #[test]
+    fn constants_are_injected() {
+        for shader in Shader::ALL {
+            let module = parse(shader);
+            for &(name, value) in SHADER_CONSTANTS {
+                let (_, constant) = module
+                    .constants
+                    .iter()
+                    .find(|(_, c)| c.name.as_deref() == Some(name))
+                    .unwrap_or_else(|| panic!("{} is missing {}", shader.label(), name));
+                assert!(matches!(
+                    module.const_expressions[constant.init],
+                    naga::Expression::Literal(naga::Literal::U32(v)) if v == value
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn push_constants_match_wgsl() {
+        for shader in Shader::ALL {
+            assert_eq!(
+                struct_layout(&parse(shader), "PushConstants"),
+                layout(
+                    size_of::<PushConstants>(),
+                    &[
+                        ("time", offset_of!(PushConstants, time)),
+                        ("chunk_id", offset_of!(PushConstants, chunk_id)),
+                    ]
+                ),
+                "{}",
+                shader.label()
+            );
+        }
+    }
+
+    #[test]
+    fn vertex_matches_wgsl() {
+        assert_eq!(
+            struct_layout(&parse(Shader::MarchingCubes), "Vertex"),
+            layout(
+                size_of::<Vertex>(),
+                &[
+                    ("position", offset_of!(Vertex, position)),
+                    ("normal", offset_of!(Vertex, normal)),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn lighting_uniform_matches_wgsl() {
+        for shader in [
+            Shader::ChunkRender,
+            Shader::MeshRender,
+            Shader::Sky,
+            Shader::CloudVolume,
+        ] {
+            assert_eq!(
+                struct_layout(&parse(shader), "LightingUniform"),
+                layout(
+                    size_of::<LightingUniform>(),
+                    &[
+                        ("sun_direction", offset_of!(LightingUniform, sun_direction)),
+                        ("sun_color", offset_of!(LightingUniform, sun_color)),
+                        ("sky_color", offset_of!(LightingUniform, sky_color)),
+                        ("ground_color", offset_of!(LightingUniform, ground_color)),
+                        ("albedo", offset_of!(LightingUniform, albedo)),
+                        ("wrap", offset_of!(LightingUniform, wrap)),
+                        ("translucency", offset_of!(LightingUniform, translucency)),
+                        ("rim_strength", offset_of!(LightingUniform, rim_strength)),
+                        ("rim_power", offset_of!(LightingUniform, rim_power)),
+                        ("fog_density", offset_of!(LightingUniform, fog_density)),
+                    ]
+                ),
+                "{}",
+                shader.label()
+            );
+        }
+    }
+
+    #[test]
+    fn post_uniform_matches_wgsl() {
+        assert_eq!(
+            struct_layout(&parse(Shader::Post), "PostUniform"),
+            layout(
+                size_of::<PostUniform>(),
+                &[
+                    ("exposure", offset_of!(PostUniform, exposure)),
+                    ("bloom_threshold", offset_of!(PostUniform, bloom_threshold)),
+                    ("bloom_intensity", offset_of!(PostUniform, bloom_intensity)),
+                    ("_padding", offset_of!(PostUniform, _padding)),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn noise_uniform_matches_wgsl() {
+        assert_eq!(
+            struct_layout(&parse(Shader::CloudDensity), "NoiseUniform"),
+            layout(
+                size_of::<NoiseUniform>(),
+                &[
+                    ("octave_weights", offset_of!(NoiseUniform, octave_weights)),
+                    ("frequency", offset_of!(NoiseUniform, frequency)),
+                    ("lacunarity", offset_of!(NoiseUniform, lacunarity)),
+                    ("speed", offset_of!(NoiseUniform, speed)),
+                    ("gain", offset_of!(NoiseUniform, gain)),
+                    ("sharpness", offset_of!(NoiseUniform, sharpness)),
+                    ("iso_level", offset_of!(NoiseUniform, iso_level)),
+                    ("_padding", offset_of!(NoiseUniform, _padding)),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn camera_uniform_matches_wgsl() {
+        for shader in [Shader::ChunkRender, Shader::MeshRender, Shader::Sky] {
+            assert_eq!(
+                struct_layout(&parse(shader), "CameraUniform"),
+                layout(
+                    size_of::<CameraUniform>(),
+                    &[
+                        ("view_proj", offset_of!(CameraUniform, view_proj)),
+                        (
+                            "inverse_view_proj",
+                            offset_of!(CameraUniform, inverse_view_proj)
+                        ),
+                        ("eye", offset_of!(CameraUniform, eye)),
+                    ]
+                ),
+                "{}",
+                shader.label()
+            );
+        }
+    }
+
+    #[test]
+    fn shadow_uniform_matches_wgsl() {
+        assert_eq!(
+            struct_layout(&parse(Shader::ChunkRender), "ShadowUniform"),
+            layout(
+                size_of::<ShadowUniform>(),
+                &[("view_proj", offset_of!(ShadowUniform, view_proj))]
+            )
+        );
+    }
+
+    #[test]
+    fn debug_uniform_matches_wgsl() {
+        assert_eq!(
+            struct_layout(&parse(Shader::Debug), "DebugUniform"),
+            layout(
+                size_of::<DebugUniform>(),
+                &[
+                    ("view", offset_of!(DebugUniform, view)),
+                    ("slice", offset_of!(DebugUniform, slice)),
+                    ("_padding", offset_of!(DebugUniform, _padding)),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn debug_views_match_wgsl() {
+        let module = parse(Shader::Debug);
+        for (name, view) in [
+            ("VIEW_WIREFRAME", DebugView::Wireframe),
+            ("VIEW_NORMALS", DebugView::Normals),
+            ("VIEW_CHUNK_TINT", DebugView::ChunkTint),
+            ("VIEW_TRIANGLE_DENSITY", DebugView::TriangleDensity),
+        ] {
+            let (_, constant) = module
+                .constants
+                .iter()
+                .find(|(_, c)| c.name.as_deref() == Some(name))
+                .unwrap_or_else(|| panic!("{} is missing", name));
+            assert!(
+                matches!(
+                    module.const_expressions[constant.init],
+                    naga::Expression::Literal(naga::Literal::U32(v)) if v == view as u32
+                ),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn indirect_draw_command_matches_wgpu() {
+        let (size, _) =
+            struct_layout(&parse(Shader::MarchingCubes), "IndirectDrawCommand").unwrap();
+        assert_eq!(size, size_of::<DrawIndirect>());
+    }
+
+    #[test]
+    fn vertex_attributes_match_render_inputs() {
+        let module = parse(Shader::ChunkRender);
+        let entry_point = module
+            .entry_points
+            .iter()
+            .find(|e| e.name == "vs_main")
+            .unwrap();
+        let input_ty = entry_point.function.arguments[0].ty;
+        let naga::TypeInner::Struct { members, .. } = &module.types[input_ty].inner else {
+            panic!("vs_main should take a struct");
+        };
+
+        assert_eq!(members.len(), VERTEX_ATTRIBUTES.len());
+        for member in members {
+            let Some(naga::Binding::Location { location, .. }) = member.binding else {
+                panic!("Vertex inputs should have locations");
+            };
+            let attribute = VERTEX_ATTRIBUTES
+                .iter()
+                .find(|a| a.shader_location == location)
+                .unwrap();
+            let naga::TypeInner::Vector { size, width, .. } = module.types[member.ty].inner else {
+                panic!("Vertex inputs should be vectors");
+            };
+            assert_eq!(
+                size as u64 * width as u64,
+                attribute.format.size(),
+                "location {}",
+                location
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_includes_are_errors() {
+        let mut composed = String::new();
+        let result = append_with_includes(
+            &mut composed,
+            "#include \"missing.wgsl\"",
+            false,
+            &mut HashSet::new(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn includes_are_only_expanded_once() {
+        let mut composed = String::new();
+        append_with_includes(
+            &mut composed,
+            "#include \"common.wgsl\"\n#include \"common.wgsl\"",
+            false,
+            &mut HashSet::new(),
+        )
+        .unwrap();
+        assert_eq!(composed.matches("struct PushConstants").count(), 1);
+    }
+}
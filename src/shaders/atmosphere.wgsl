@@ -0,0 +1,93 @@
+// A single scattering model of the Earth's atmosphere, marched in kilometers.
+// Rayleigh scattering off air molecules turns the sky blue, Mie scattering off larger particles
+// makes the haze around the sun.
+
+const EARTH_RADIUS: f32 = 6360.0;
+const ATMOSPHERE_RADIUS: f32 = 6420.0;
+const RAYLEIGH_SCALE_HEIGHT: f32 = 7.994;
+const MIE_SCALE_HEIGHT: f32 = 1.2;
+const RAYLEIGH_SCATTERING: vec3<f32> = vec3<f32>(5.8e-3, 13.5e-3, 33.1e-3);
+const MIE_SCATTERING: f32 = 21e-3;
+const MIE_G: f32 = 0.76;
+const SKY_VIEW_STEPS: u32 = 16u;
+const SKY_LIGHT_STEPS: u32 = 8u;
+// Brings the sky up to the brightness of the sunlit clouds
+const SKY_INTENSITY: f32 = 20.0;
+
+// Distance from the origin to where the ray leaves the sphere, assuming it starts inside
+fn sphereExit(origin: vec3<f32>, direction: vec3<f32>, radius: f32) -> f32 {
+    let b = dot(origin, direction);
+    let c = dot(origin, origin) - radius * radius;
+    return -b + sqrt(max(b * b - c, 0.0));
+}
+
+// Optical depth of air molecules (x) and larger particles (y) over a step at this point
+fn densityAt(position: vec3<f32>) -> vec2<f32> {
+    let height = length(position) - EARTH_RADIUS;
+    return exp(-height / vec2(RAYLEIGH_SCALE_HEIGHT, MIE_SCALE_HEIGHT));
+}
+
+// The light scattered toward the viewer, standing on the ground and looking along `direction`.
+// Below the horizon, the sky is mirrored and darkened to stand in for the ground.
+fn skyRadiance(direction: vec3<f32>, sun_direction: vec3<f32>, sun_color: vec3<f32>) -> vec3<f32> {
+    let view = normalize(vec3(direction.x, max(abs(direction.y), 0.001), direction.z));
+    let origin = vec3(0.0, EARTH_RADIUS + 0.001, 0.0);
+    let view_length = sphereExit(origin, view, ATMOSPHERE_RADIUS);
+    let step_length = view_length / f32(SKY_VIEW_STEPS);
+
+    var rayleigh = vec3(0.0);
+    var mie = vec3(0.0);
+    var view_depth = vec2(0.0);
+    for (var i = 0u; i < SKY_VIEW_STEPS; i++) {
+        let position = origin + view * ((f32(i) + 0.5) * step_length);
+        let density = densityAt(position) * step_length;
+        view_depth += density;
+
+        // Light reaching this point from the sun, skipped when the Earth is in the way
+        let light_length = sphereExit(position, sun_direction, ATMOSPHERE_RADIUS);
+        let light_step_length = light_length / f32(SKY_LIGHT_STEPS);
+        var light_depth = vec2(0.0);
+        var in_shadow = false;
+        for (var j = 0u; j < SKY_LIGHT_STEPS; j++) {
+            let light_position = position + sun_direction * ((f32(j) + 0.5) * light_step_length);
+            if (length(light_position) < EARTH_RADIUS) {
+                in_shadow = true;
+                break;
+            }
+            light_depth += densityAt(light_position) * light_step_length;
+        }
+        if (in_shadow) {
+            continue;
+        }
+
+        let optical_depth = view_depth + light_depth;
+        let attenuation = exp(-(RAYLEIGH_SCATTERING * optical_depth.x + MIE_SCATTERING * 1.1 * optical_depth.y));
+        rayleigh += density.x * attenuation;
+        mie += density.y * attenuation;
+    }
+
+    let cos_theta = dot(view, sun_direction);
+    let rayleigh_phase = 3.0 / (16.0 * PI) * (1.0 + cos_theta * cos_theta);
+    let g2 = MIE_G * MIE_G;
+    let mie_phase = 3.0 / (8.0 * PI) * ((1.0 - g2) * (1.0 + cos_theta * cos_theta))
+        / ((2.0 + g2) * pow(1.0 + g2 - 2.0 * MIE_G * cos_theta, 1.5));
+    var radiance = sun_color * SKY_INTENSITY
+        * (rayleigh * RAYLEIGH_SCATTERING * rayleigh_phase + mie * MIE_SCATTERING * mie_phase);
+
+    if (direction.y < 0.0) {
+        radiance *= mix(1.0, 0.4, smoothstep(0.0, -0.2, direction.y));
+    }
+    return radiance;
+}
+
+// Fades what's at `distance` along `direction` into the sky behind it
+fn aerialPerspective(
+    color: vec3<f32>,
+    distance: f32,
+    direction: vec3<f32>,
+    lighting: LightingUniform,
+) -> vec3<f32> {
+    let transmittance = exp(-distance * lighting.fog_density);
+    let sky = skyRadiance(direction, lighting.sun_direction.xyz, lighting.sun_color.rgb);
+    return color * transmittance + sky * (1.0 - transmittance);
+}
@@ -1,17 +1,26 @@
-struct PushConstants {
-    time: f32,
-    chunk_id: u32
+#include "common.wgsl"
+#include "atmosphere.wgsl"
+#include "surface_shading.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(0) @binding(1)
+var<uniform> lighting: LightingUniform;
+
+// The sun's view of each shadow cascade, see shadow.rs
+struct ShadowUniform {
+    view_proj: array<mat4x4<f32>, SHADOW_CASCADE_COUNT>,
 }
 
-var<push_constant> push: PushConstants;
+@group(0) @binding(2)
+var<uniform> shadow: ShadowUniform;
 
-const VOXELS_PER_CHUNK_DIM: u32 = 50u;
+@group(0) @binding(3)
+var shadow_map: texture_depth_2d_array;
 
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-};
-@group(0) @binding(0)
-var<uniform> camera: CameraUniform;
+@group(0) @binding(4)
+var shadow_sampler: sampler_comparison;
 
 struct VertexInput {
     @location(0) position: vec4<f32>,
@@ -20,49 +29,71 @@ struct VertexInput {
 
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
-    @location(0) color: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
     @location(1) normal: vec3<f32>,
 }
 
 @vertex
 fn vs_main(in: VertexInput) -> VertexOutput {
     var out: VertexOutput;
-    // Align to (0, 0, 0)
-    let chunk_offset = vec3<f32>(
-        f32(((push.chunk_id >> 0u) & 1u) * VOXELS_PER_CHUNK_DIM),
-        f32(((push.chunk_id >> 1u) & 1u) * VOXELS_PER_CHUNK_DIM),
-        f32(((push.chunk_id >> 2u) & 1u) * VOXELS_PER_CHUNK_DIM)
-    );
+    let chunk_offset = vec3<f32>(chunkCoord() * VOXELS_PER_CHUNK_DIM);
 
-    out.position = camera.view_proj * vec4((((in.position.xyz + chunk_offset) / f32(VOXELS_PER_CHUNK_DIM)) - 1.0) * 8.0, 1.0);
-    let color_time = push.time * 0.5;
-    out.color = mix(
-        vec4<f32>(
-            cos(0.4 + color_time * 0.9) * 0.5 + 0.5,
-            (sin(0.6 + color_time * 0.6) * 0.5 + 0.5) * 0.2,
-            cos(0.8 + color_time * 0.3) * 0.5 + 0.5,
-            1.0
-        ),
-        vec4<f32>(
-            (cos(0.5 + color_time * 0.2) * 0.5 + 0.5) * 0.2,
-            (cos(0.7 + color_time * 0.5) * 0.5 + 0.5) * 0.2,
-            sin(0.9 + color_time * 0.8) * 0.5 + 0.5,
-            1.0),
-        in.normal.w);
+    out.world_position = voxelToWorld(in.position.xyz + chunk_offset);
+    out.position = camera.view_proj * vec4(out.world_position, 1.0);
     out.normal = in.normal.xyz;
     return out;
 }
 
 struct FragmentOutput {
     @location(0) color: vec4<f32>,
+    // For the ambient occlusion pass
+    @location(1) normal: vec4<f32>,
 }
 
-const AMBIENT: f32 = 0.1;
+// Moves the shadow lookup off the surface, in shadow map texels, to keep it from shadowing itself
+const SHADOW_NORMAL_OFFSET: f32 = 1.5;
+
+// How much sunlight reaches this point, from 0 in full shadow to 1.
+// Uses the sharpest cascade that covers it and filters 3x3 texels around it.
+fn sunVisibility(world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+    for (var cascade = 0u; cascade < SHADOW_CASCADE_COUNT; cascade++) {
+        let view_proj = shadow.view_proj[cascade];
+        // The size of a texel in world units, from how much the cascade's projection scales x
+        let scale_x = length(vec3(view_proj[0].x, view_proj[1].x, view_proj[2].x));
+        let texel_world_size = 2.0 * texel.x / scale_x;
+        let offset_position = world_position + normal * texel_world_size * SHADOW_NORMAL_OFFSET;
+        let clip = view_proj * vec4(offset_position, 1.0);
+        let ndc = clip.xyz / clip.w;
+        let uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+        if (all(uv > texel) && all(uv < 1.0 - texel) && ndc.z < 1.0) {
+            var visibility = 0.0;
+            for (var y = -1; y <= 1; y++) {
+                for (var x = -1; x <= 1; x++) {
+                    visibility += textureSampleCompareLevel(
+                        shadow_map,
+                        shadow_sampler,
+                        uv + vec2<f32>(vec2(x, y)) * texel,
+                        cascade,
+                        ndc.z
+                    );
+                }
+            }
+            return visibility / 9.0;
+        }
+    }
+    // Past the last cascade
+    return 1.0;
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> FragmentOutput {
     var out: FragmentOutput;
-    let light_dir: vec3<f32> = normalize(vec3(sin(push.time * 0.25), cos(push.time * 0.8), cos(push.time * 0.2)));
-    out.color = vec4(in.color.xyz * min(max(-dot(normalize(in.normal), light_dir), 0.0) + AMBIENT, 1.0), 1.0);
+    let normal = surfaceNormal(in.normal);
+    let eye_offset = camera.eye.xyz - in.world_position;
+    let to_eye = normalize(eye_offset);
+    let color = shadeSurface(normal, to_eye, sunVisibility(in.world_position, normal), lighting);
+    out.color = vec4(aerialPerspective(color, length(eye_offset), -to_eye, lighting), 1.0);
+    out.normal = vec4(normal, 1.0);
     return out;
-}
\ No newline at end of file
+}
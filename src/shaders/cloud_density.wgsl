@@ -1,13 +1,23 @@
-struct PushConstants {
-  time: f32,
-  chunk_id: u32
-}
+#include "common.wgsl"
 
-var<push_constant> push: PushConstants;
+struct NoiseUniform {
+    // Octaves weighted 0 are skipped
+    octave_weights: vec4<f32>,
+    frequency: f32,
+    lacunarity: f32,
+    speed: f32,
+    gain: f32,
+    sharpness: f32,
+    iso_level: f32,
+    _padding: vec2<f32>,
+}
 
 @group(0) @binding(0)
 var density: texture_storage_3d<rgba16float, write>;
 
+@group(0) @binding(1)
+var<uniform> params: NoiseUniform;
+
 // Simplex noise implementation from Stefan Gustavson
 // https://github.com/stegu/webgl-noise/blob/master/src/noise2D.glsl
 fn mod289_3(x: vec3<f32>) -> vec3<f32> { return x - floor(x * (1.0 / 289.0)) * 289.0; }
@@ -39,35 +49,60 @@ fn snoise(v: vec2<f32>) -> f32 {
 
 // 3D simplex noise, with layered octaves.
 fn noise(v: vec3<f32>) -> f32 {
-  let cloud_time = push.time / 14.0;
+  let cloud_time = push.time * params.speed;
   var out = 0.0;
-  
-  var freq = 0.5;
-  out += snoise(vec2(v.x * freq, v.y * freq + cloud_time)) * snoise(vec2(v.y * freq + cloud_time, v.z * freq));
 
-  freq = 1.0;
-  out += snoise(vec2(v.x * freq, v.y * freq + cloud_time)) * snoise(vec2(v.y * freq + cloud_time, v.z * freq)) * 0.5;
+  var freq = params.frequency;
+  for (var octave = 0; octave < 4; octave++) {
+    let weight = params.octave_weights[octave];
+    if (weight != 0.0) {
+      out += snoise(vec2(v.x * freq, v.y * freq + cloud_time)) * snoise(vec2(v.y * freq + cloud_time, v.z * freq)) * weight;
+    }
+    freq *= params.lacunarity;
+  }
 
-  freq = 2.0;
-  out += snoise(vec2(v.x * freq, v.y * freq + cloud_time)) * snoise(vec2(v.y * freq + cloud_time, v.z * freq)) * 0.2;
-
-  return clamp(pow(out * 2.0, 1.2), 0.0, 1.0);
+  return clamp(pow(out * params.gain, params.sharpness), 0.0, 1.0);
 }
 
 const GRADIENT_D: f32 = 0.0001;
-const VOXELS_PER_CHUNK_DIM: u32 = 50u;
+// How far in from the edges of the world the clouds thin out, in chunks
+const EDGE_FADE: f32 = 0.1;
+
+// Goes from 0 on the faces of the 2x2x2 grid of chunks to 1 EDGE_FADE inside them,
+// so clouds crossing a face get closed off instead of showing their inside
+fn edgeFade(v: vec3<f32>) -> f32 {
+    let inside = min(v, 2.0 - v);
+    return smoothstep(0.0, EDGE_FADE, min(inside.x, min(inside.y, inside.z)));
+}
+
+// The meshers and the volume look for ISO_LEVEL, so the noise is shifted to move the surface
+fn cloudDensity(v: vec3<f32>) -> f32 {
+    return (noise(v) + ISO_LEVEL - params.iso_level) * edgeFade(v);
+}
 
 @compute @workgroup_size(10, 9, 8)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    let x = f32(global_id.x + (((push.chunk_id >> 0u) & 1u) * VOXELS_PER_CHUNK_DIM)) / f32(VOXELS_PER_CHUNK_DIM);
-    let y = f32(global_id.y + (((push.chunk_id >> 1u) & 1u) * VOXELS_PER_CHUNK_DIM)) / f32(VOXELS_PER_CHUNK_DIM);
-    let z = f32(global_id.z + (((push.chunk_id >> 2u) & 1u) * VOXELS_PER_CHUNK_DIM)) / f32(VOXELS_PER_CHUNK_DIM);
+    // One thread per texel of this chunk's region, including the padding
+    if (any(global_id >= vec3(DENSITY_REGION_DIM))) {
+        return;
+    }
+    let corner = vec3<i32>(global_id) - 1;
+    let world_corner = corner + vec3<i32>(chunkCoord() * VOXELS_PER_CHUNK_DIM);
+    let x = f32(world_corner.x) / f32(VOXELS_PER_CHUNK_DIM);
+    let y = f32(world_corner.y) / f32(VOXELS_PER_CHUNK_DIM);
+    let z = f32(world_corner.z) / f32(VOXELS_PER_CHUNK_DIM);
 
-    var sample = noise(vec3(x, y, z));
+    let sample = cloudDensity(vec3(x, y, z));
     // Compute gradient for normals using central differences
-    var gradient = normalize(vec3<f32>(
-      (noise(vec3(x + GRADIENT_D, y, z)) - sample) / GRADIENT_D, 
-      (noise(vec3(x, y + GRADIENT_D, z)) - sample) / GRADIENT_D, 
-      (noise(vec3(x, y, z + GRADIENT_D)) - sample) / GRADIENT_D));
-    textureStore(density, global_id, vec4<f32>(sample, gradient));
-}
\ No newline at end of file
+    let slope = vec3<f32>(
+      (cloudDensity(vec3(x + GRADIENT_D, y, z)) - sample) / GRADIENT_D, 
+      (cloudDensity(vec3(x, y + GRADIENT_D, z)) - sample) / GRADIENT_D, 
+      (cloudDensity(vec3(x, y, z + GRADIENT_D)) - sample) / GRADIENT_D);
+    // The density is flat at 0 on and past the faces of the grid, where the edge fade ends.
+    // Normalizing there gives NaN, which the meshers would blend into the normals on the faces.
+    var gradient = vec3(0.0);
+    if (any(slope != vec3(0.0))) {
+        gradient = normalize(slope);
+    }
+    textureStore(density, densityTexel(chunkCoord(), corner), vec4<f32>(sample, gradient));
+}
@@ -0,0 +1,177 @@
+#include "common.wgsl"
+#include "atmosphere.wgsl"
+
+// Raymarches the density of the whole 2x2x2 grid of chunks instead of drawing the mesh.
+// Light is absorbed following Beer–Lambert, scattered toward the camera with a
+// Henyey–Greenstein phase function, and shadowed by marching a few steps toward the sun.
+// fs_main marches through the whole world, fs_hybrid only marches a short distance around the
+// mesh surface, using the depth of the mesh drawn beforehand to skip the empty space.
+// The result is fogged at the average depth of what it hit, and blended over the sky.
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(0) @binding(1)
+var density: texture_3d<f32>;
+
+@group(0) @binding(2)
+var density_sampler: sampler;
+
+// Only read by fs_hybrid
+@group(0) @binding(3)
+var mesh_depth: texture_2d<f32>;
+
+@group(0) @binding(4)
+var<uniform> lighting: LightingUniform;
+
+// How quickly light is absorbed inside the cloud, per world unit
+const EXTINCTION: f32 = 4.0;
+// The density range over which the cloud edge fades in around ISO_LEVEL
+const EDGE_SOFTNESS: f32 = 0.15;
+const STEP_SIZE: f32 = 0.1;
+const MAX_STEPS: u32 = 300u;
+const LIGHT_STEPS: u32 = 6u;
+const LIGHT_STEP_SIZE: f32 = 0.3;
+// Clouds scatter mostly forward, which gives the bright silver lining when looking toward the sun.
+// A second, backward lobe keeps the side facing the sun from looking flat.
+const PHASE_FORWARD_G: f32 = 0.6;
+const PHASE_BACKWARD_G: f32 = -0.3;
+// How far in front of and behind the mesh surface fs_hybrid marches.
+// The soft edge of the volume starts a little outside the mesh.
+const HYBRID_START_OFFSET: f32 = 0.3;
+const HYBRID_DEPTH: f32 = 2.0;
+
+// Undoes the 1 / 4π of the phase function, so the sun is as bright as on the mesh
+const SUN_SCALE: f32 = 4.0 * PI;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// A single triangle covering the screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.ndc = uv * 2.0 - 1.0;
+    out.position = vec4(out.ndc, 0.0, 1.0);
+    return out;
+}
+
+fn sampleDensity(world: vec3<f32>) -> f32 {
+    let voxel = (world / WORLD_HALF_SIZE + 1.0) * f32(VOXELS_PER_CHUNK_DIM);
+    let chunk = clamp(floor(voxel / f32(VOXELS_PER_CHUNK_DIM)), vec3(0.0), vec3(1.0));
+    let corner = voxel - chunk * f32(VOXELS_PER_CHUNK_DIM);
+    // Same as densityTexel, plus half a texel to land on the texel centers
+    let texel = chunk * f32(DENSITY_REGION_DIM) + corner + 1.5;
+    let uvw = texel / vec3<f32>(textureDimensions(density));
+    return textureSampleLevel(density, density_sampler, uvw, 0.0).x;
+}
+
+fn extinction(world: vec3<f32>) -> f32 {
+    let density = sampleDensity(world);
+    return EXTINCTION * smoothstep(ISO_LEVEL - EDGE_SOFTNESS, ISO_LEVEL + EDGE_SOFTNESS, density);
+}
+
+fn henyeyGreenstein(cos_theta: f32, g: f32) -> f32 {
+    let g2 = g * g;
+    return (1.0 - g2) / (4.0 * PI * pow(1.0 + g2 - 2.0 * g * cos_theta, 1.5));
+}
+
+// Where the ray enters and leaves the world's bounds, entry > exit on a miss
+fn intersectWorld(origin: vec3<f32>, direction: vec3<f32>) -> vec2<f32> {
+    let t0 = (vec3(-WORLD_HALF_SIZE) - origin) / direction;
+    let t1 = (vec3(WORLD_HALF_SIZE) - origin) / direction;
+    let near = min(t0, t1);
+    let far = max(t0, t1);
+    return vec2(max(max(near.x, near.y), max(near.z, 0.0)), min(min(far.x, far.y), far.z));
+}
+
+// How much sunlight reaches this point through the cloud
+fn sunTransmittance(world: vec3<f32>) -> f32 {
+    var optical_depth = 0.0;
+    for (var i = 1u; i <= LIGHT_STEPS; i++) {
+        optical_depth += extinction(world + lighting.sun_direction.xyz * (f32(i) * LIGHT_STEP_SIZE)) * LIGHT_STEP_SIZE;
+    }
+    return exp(-optical_depth);
+}
+
+// Cheap per-pixel noise, used to jitter the ray start and hide banding
+fn hash(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+fn march(origin: vec3<f32>, direction: vec3<f32>, start: f32, end: f32, pixel: vec2<f32>) -> vec4<f32> {
+    let cos_theta = dot(direction, lighting.sun_direction.xyz);
+    let phase = mix(
+        henyeyGreenstein(cos_theta, PHASE_FORWARD_G),
+        henyeyGreenstein(cos_theta, PHASE_BACKWARD_G),
+        0.5
+    );
+    var color = vec3(0.0);
+    var transmittance = 1.0;
+    // Distance along the ray, weighted by how much each step adds to the opacity
+    var weighted_distance = 0.0;
+    var t = start + hash(pixel) * STEP_SIZE;
+    for (var i = 0u; i < MAX_STEPS && t < end; i++) {
+        let world = origin + direction * t;
+        let sigma = extinction(world);
+        if (sigma > 0.0) {
+            let light = lighting.sun_color.rgb * SUN_SCALE * sunTransmittance(world) * phase + lighting.sky_color.rgb;
+            let step_transmittance = exp(-sigma * STEP_SIZE);
+            // Integrates the light scattered over the step, rather than assuming it's constant
+            color += transmittance * light * (1.0 - step_transmittance);
+            weighted_distance += t * transmittance * (1.0 - step_transmittance);
+            transmittance *= step_transmittance;
+            if (transmittance < 0.01) {
+                break;
+            }
+        }
+        t += STEP_SIZE;
+    }
+
+    let alpha = 1.0 - transmittance;
+    if (alpha <= 0.0) {
+        return vec4(0.0);
+    }
+    let fogged = aerialPerspective(color / alpha, weighted_distance / alpha, direction, lighting);
+    // Premultiplied alpha
+    return vec4(fogged * alpha, alpha);
+}
+
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = camera.inverse_view_proj * vec4(ndc, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let origin = camera.eye.xyz;
+    let direction = normalize(unproject(in.ndc, 1.0) - origin);
+
+    let bounds = intersectWorld(origin, direction);
+    if (bounds.x >= bounds.y) {
+        return vec4(0.0);
+    }
+    return march(origin, direction, bounds.x, bounds.y, in.position.xy);
+}
+
+@fragment
+fn fs_hybrid(in: VertexOutput) -> @location(0) vec4<f32> {
+    let depth = textureLoad(mesh_depth, vec2<i32>(in.position.xy), 0).x;
+    if (depth >= 1.0) {
+        // Nothing to start from
+        return vec4(0.0);
+    }
+
+    let origin = camera.eye.xyz;
+    let surface = unproject(in.ndc, depth);
+    let direction = normalize(surface - origin);
+    let distance_to_surface = distance(surface, origin);
+
+    let bounds = intersectWorld(origin, direction);
+    let start = max(distance_to_surface - HYBRID_START_OFFSET, bounds.x);
+    let end = min(distance_to_surface + HYBRID_DEPTH, bounds.y);
+    return march(origin, direction, start, end, in.position.xy);
+}
@@ -0,0 +1,69 @@
+// Declarations shared by every shader.
+// The Rust side mirrors these in chunks.rs.
+// VOXELS_PER_CHUNK_DIM is injected by the Rust side when the shader is composed.
+
+struct PushConstants {
+    time: f32,
+    chunk_id: u32
+}
+
+var<push_constant> push: PushConstants;
+
+// Position of the current chunk in the 2x2x2 grid of chunks
+fn chunkCoord() -> vec3<u32> {
+    return vec3<u32>(
+        (push.chunk_id >> 0u) & 1u,
+        (push.chunk_id >> 1u) & 1u,
+        (push.chunk_id >> 2u) & 1u
+    );
+}
+
+const PI: f32 = 3.14159265;
+
+// The surface sits where the density crosses this value
+const ISO_LEVEL: f32 = 0.5;
+
+// The density of every chunk lives in one texture, in a region of DENSITY_REGION_DIM^3 texels per chunk.
+// Each region has one voxel of padding on the low side of each axis,
+// so the meshers can look into the neighbouring chunk.
+const DENSITY_REGION_DIM: u32 = VOXELS_PER_CHUNK_DIM + 2u;
+
+// Texel holding a corner of the given chunk, corners go from -1 through VOXELS_PER_CHUNK_DIM
+fn densityTexel(chunk: vec3<u32>, corner: vec3<i32>) -> vec3<i32> {
+    return vec3<i32>(chunk * DENSITY_REGION_DIM) + corner + 1;
+}
+
+// Half the side length of the rendered 2x2x2 grid of chunks, which is centered on the origin
+const WORLD_HALF_SIZE: f32 = 8.0;
+
+// Maps a position in voxels, relative to the first chunk, to world space
+fn voxelToWorld(voxel: vec3<f32>) -> vec3<f32> {
+    return (voxel / f32(VOXELS_PER_CHUNK_DIM) - 1.0) * WORLD_HALF_SIZE;
+}
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inverse_view_proj: mat4x4<f32>,
+    eye: vec4<f32>,
+};
+
+// Colors are premultiplied by their intensity, see lighting.rs
+struct LightingUniform {
+    // Points toward the sun
+    sun_direction: vec4<f32>,
+    sun_color: vec4<f32>,
+    sky_color: vec4<f32>,
+    ground_color: vec4<f32>,
+    albedo: vec4<f32>,
+    wrap: f32,
+    translucency: f32,
+    rim_strength: f32,
+    rim_power: f32,
+    // Fraction of the light lost per world unit on its way to the camera
+    fog_density: f32,
+};
+
+struct Vertex {
+    position: vec4<f32>,
+    normal: vec4<f32>
+};
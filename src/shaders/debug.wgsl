@@ -0,0 +1,167 @@
+#include "common.wgsl"
+
+// Views for seeing what the meshers and the density pass are doing.
+// Drawn straight onto the surface, without lighting or post processing.
+
+// Matches DebugView in cloud_world.rs
+const VIEW_WIREFRAME: u32 = 1u;
+const VIEW_NORMALS: u32 = 2u;
+const VIEW_CHUNK_TINT: u32 = 3u;
+const VIEW_TRIANGLE_DENSITY: u32 = 4u;
+
+struct DebugUniform {
+    view: u32,
+    // Depth of the density slice, in texels
+    slice: u32,
+    _padding: vec2<u32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(0) @binding(1)
+var<uniform> debug: DebugUniform;
+
+@group(0) @binding(2)
+var density: texture_3d<f32>;
+
+struct VertexInput {
+    @builtin(vertex_index) index: u32,
+    @location(0) position: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+}
+
+struct MeshOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    // Every three vertices make a triangle, so each corner gets one axis
+    @location(1) barycentric: vec3<f32>,
+    @location(2) @interpolate(flat) chunk_id: u32,
+}
+
+@vertex
+fn vs_mesh(in: VertexInput) -> MeshOutput {
+    var out: MeshOutput;
+    let chunk_offset = vec3<f32>(chunkCoord() * VOXELS_PER_CHUNK_DIM);
+    let world_position = voxelToWorld(in.position.xyz + chunk_offset);
+    out.position = camera.view_proj * vec4(world_position, 1.0);
+    out.normal = in.normal.xyz;
+    let corner = in.index % 3u;
+    out.barycentric = vec3(f32(corner == 0u), f32(corner == 1u), f32(corner == 2u));
+    out.chunk_id = push.chunk_id;
+    return out;
+}
+
+const WIREFRAME_WIDTH: f32 = 1.0;
+const WIREFRAME_COLOR: vec3<f32> = vec3<f32>(1.0, 0.6, 0.1);
+// Triangles covering this many pixels or fewer are drawn hottest
+const HOTTEST_TRIANGLE_PIXELS: f32 = 1.0;
+// Each step of the heatmap is twice the pixels per triangle, so this many steps reach the coldest color
+const TRIANGLE_DENSITY_OCTAVES: f32 = 10.0;
+
+// Blue through green and yellow to red
+fn heat(t: f32) -> vec3<f32> {
+    let x = clamp(t, 0.0, 1.0);
+    let ramp = 1.5 - abs(4.0 * x - vec3(3.0, 2.0, 1.0));
+    return clamp(ramp, vec3(0.0), vec3(1.0));
+}
+
+fn chunkTint(chunk_id: u32) -> vec3<f32> {
+    let coord = vec3<f32>(
+        f32((chunk_id >> 0u) & 1u),
+        f32((chunk_id >> 1u) & 1u),
+        f32((chunk_id >> 2u) & 1u)
+    );
+    return mix(vec3(0.25), vec3(1.0), coord);
+}
+
+@fragment
+fn fs_mesh(in: MeshOutput) -> @location(0) vec4<f32> {
+    // Same as the lit mesh, gradients can cancel out
+    let gradient_length = length(in.normal);
+    var normal = vec3(0.0, 1.0, 0.0);
+    if (gradient_length > 0.0001) {
+        normal = -in.normal / gradient_length;
+    }
+    // Enough shading to see the shape without any lights
+    let shade = 0.35 + 0.65 * (normal.y * 0.5 + 0.5);
+
+    // Derivatives are taken before branching
+    let barycentric_width = fwidth(in.barycentric);
+    let dx = dpdx(in.barycentric.xy);
+    let dy = dpdy(in.barycentric.xy);
+
+    var color = normal * 0.5 + 0.5;
+    switch debug.view {
+        case VIEW_WIREFRAME: {
+            let edge = smoothstep(vec3(0.0), barycentric_width * WIREFRAME_WIDTH, in.barycentric);
+            let line = 1.0 - min(edge.x, min(edge.y, edge.z));
+            color = mix(vec3(0.15 * shade), WIREFRAME_COLOR, line);
+        }
+        case VIEW_CHUNK_TINT: {
+            color = chunkTint(in.chunk_id) * shade;
+        }
+        case VIEW_TRIANGLE_DENSITY: {
+            // The barycentrics go from 0 to 1 across the triangle,
+            // so their screen space derivatives give its area in pixels
+            let pixels = 0.5 / max(abs(dx.x * dy.y - dx.y * dy.x), 1e-8);
+            let octaves = log2(max(pixels / HOTTEST_TRIANGLE_PIXELS, 1.0));
+            color = heat(1.0 - octaves / TRIANGLE_DENSITY_OCTAVES) * shade;
+        }
+        case VIEW_NORMALS, default: {}
+    }
+    return vec4(color, 1.0);
+}
+
+struct SliceOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// A single triangle covering the screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> SliceOutput {
+    var out: SliceOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+const SLICE_INSIDE_COLOR: vec3<f32> = vec3<f32>(1.0, 0.55, 0.2);
+const SLICE_REGION_COLOR: vec3<f32> = vec3<f32>(0.2, 0.4, 1.0);
+
+fn densityAt(texel: vec2<i32>, slice: u32) -> f32 {
+    let clamped = clamp(texel, vec2(0), vec2<i32>(textureDimensions(density).xy) - 1);
+    return textureLoad(density, vec3(vec2<u32>(clamped), slice), 0).x;
+}
+
+// One slice of the whole density texture, with every chunk's region in it.
+// The surface is outlined, and the borders between regions are drawn in blue.
+@fragment
+fn fs_density_slice(in: SliceOutput) -> @location(0) vec4<f32> {
+    let size = textureDimensions(density);
+    let slice = min(debug.slice, size.z - 1u);
+    let texel = min(vec2<u32>(in.uv * vec2<f32>(size.xy)), size.xy - 1u);
+    // Interpolated by hand, the density can't be filtered on every backend
+    let position = in.uv * vec2<f32>(size.xy) - 0.5;
+    let low = vec2<i32>(floor(position));
+    let t = fract(position);
+    let value = mix(
+        mix(densityAt(low, slice), densityAt(low + vec2(1, 0), slice), t.x),
+        mix(densityAt(low + vec2(0, 1), slice), densityAt(low + vec2(1, 1), slice), t.x),
+        t.y
+    );
+
+    var color = vec3(value);
+    if (value >= ISO_LEVEL) {
+        color *= SLICE_INSIDE_COLOR;
+    }
+    // Bright where the density crosses the iso level, about a pixel wide
+    let iso_distance = abs(value - ISO_LEVEL) / max(fwidth(value), 1e-4);
+    color = mix(vec3(1.0), color, smoothstep(0.5, 1.5, iso_distance));
+    if (any(texel % DENSITY_REGION_DIM == vec2(0u))) {
+        color = mix(color, SLICE_REGION_COLOR, 0.5);
+    }
+    return vec4(color, 1.0);
+}
@@ -1,33 +1,7 @@
-struct PushConstants {
-    time: f32,
-    chunk_id: u32
-}
-
-var<push_constant> push: PushConstants;
+#include "common.wgsl"
+#include "mesher.wgsl"
 
 const EPSILON: f32 = 0.0000001;
-const VOXELS_PER_CHUNK_DIM: u32 = 50u;
-
-struct IndirectDrawCommand {
-    vertex_count: atomic<u32>,
-    instance_count: u32,
-    first_vertex: u32,
-    first_instance: u32
-};
-
-struct Vertex {
-    position: vec4<f32>,
-    normal: vec4<f32>
-};
-
-@group(0) @binding(0)
-var density: texture_storage_3d<rgba16float, read>;
-
-@group(0) @binding(1)
-var<storage, read_write> draw_command: IndirectDrawCommand;
-
-@group(0) @binding(2)
-var<storage, read_write> vertices: array<Vertex>;
 
 @group(0) @binding(3)
 var<storage, read> edge_table: array<u32, 256>;
@@ -38,28 +12,16 @@ var<storage, read> tri_table: array<array<i32, 16>, 256>;
 
 
 fn vertexInterp(iso_level: f32, p1: vec3<u32>, p2: vec3<u32>, n1: vec3<f32>, n2: vec3<f32>, v1: f32, v2: f32) -> Vertex {
-    let _p1 = vec4<f32>(vec3<f32>(p1), 1.0);
-    let _p2 = vec4<f32>(vec3<f32>(p2), 1.0);
     let mu = clamp(max(abs(iso_level - v1), EPSILON) / max(abs(v2 - v1), EPSILON), 0.0, 1.0);
-
-    var vert = Vertex();
-    vert.position = mix(_p1, _p2, mu);
-
-    let chunk_offset = vec3<f32>(
-        f32((push.chunk_id >> 0u) & 1u),
-        f32((push.chunk_id >> 1u) & 1u),
-        f32((push.chunk_id >> 2u) & 1u)
-    );
-    let global_xyz = vert.position.xyz / f32(VOXELS_PER_CHUNK_DIM) + chunk_offset;
-
-    vert.normal = vec4(mix(n1, n2, mu), (global_xyz.y / 2.0));
-
-    return vert;
+    return makeVertex(mix(vec3<f32>(p1), vec3<f32>(p2), mu), mix(n1, n2, mu));
 }
 
-@compute @workgroup_size(10, 10, 10)
+@compute @workgroup_size(8, 8, 8)
 fn main(@builtin(global_invocation_id) thread_id : vec3<u32>) {
-    let iso_level = 0.5;
+    if (any(thread_id >= vec3(VOXELS_PER_CHUNK_DIM))) {
+        return;
+    }
+    let iso_level = ISO_LEVEL;
     let positions = array<vec3<u32>, 8>(
         thread_id + vec3<u32>(0u, 0u, 0u),
         thread_id + vec3<u32>(1u, 0u, 0u),
@@ -71,14 +33,14 @@ fn main(@builtin(global_invocation_id) thread_id : vec3<u32>) {
         thread_id + vec3<u32>(0u, 1u, 1u)
     );
     let data = array<vec4<f32>, 8>(
-        textureLoad(density, positions[0u]),
-        textureLoad(density, positions[1u]),
-        textureLoad(density, positions[2u]),
-        textureLoad(density, positions[3u]),
-        textureLoad(density, positions[4u]),
-        textureLoad(density, positions[5u]),
-        textureLoad(density, positions[6u]),
-        textureLoad(density, positions[7u]),
+        loadDensity(vec3<i32>(positions[0u])),
+        loadDensity(vec3<i32>(positions[1u])),
+        loadDensity(vec3<i32>(positions[2u])),
+        loadDensity(vec3<i32>(positions[3u])),
+        loadDensity(vec3<i32>(positions[4u])),
+        loadDensity(vec3<i32>(positions[5u])),
+        loadDensity(vec3<i32>(positions[6u])),
+        loadDensity(vec3<i32>(positions[7u])),
     );
     let cube_index =
         (u32(step(data[0u].x, iso_level)) << 0u) | 
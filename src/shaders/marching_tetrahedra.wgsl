@@ -0,0 +1,158 @@
+#include "common.wgsl"
+#include "mesher.wgsl"
+
+// Marching tetrahedra: each voxel is split into six tetrahedra around its main diagonal.
+// Neighbouring voxels split their shared faces along the same diagonal and a tetrahedron
+// has no ambiguous cases, so unlike marching cubes the surface never has holes.
+
+const EPSILON: f32 = 0.0000001;
+
+struct Corner {
+    position: vec3<f32>,
+    data: vec4<f32>,
+}
+
+// Corner i of the current voxel is offset by (i & 1, (i >> 1) & 1, (i >> 2) & 1)
+var<private> corners: array<Corner, 8>;
+
+fn isInside(corner: u32) -> bool {
+    return corners[corner].data.x > ISO_LEVEL;
+}
+
+// Bit i is set when corner i of the tetrahedron is inside the cloud
+fn insideMask(tetrahedron: vec4<u32>) -> u32 {
+    var mask = 0u;
+    for (var i = 0u; i < 4u; i++) {
+        if (isInside(tetrahedron[i])) {
+            mask |= 1u << i;
+        }
+    }
+    return mask;
+}
+
+fn triangleCount(tetrahedron: vec4<u32>) -> u32 {
+    switch countOneBits(insideMask(tetrahedron)) {
+        case 1u, 3u: {
+            return 1u;
+        }
+        case 2u: {
+            return 2u;
+        }
+        default: {
+            return 0u;
+        }
+    }
+}
+
+fn edgeVertex(a: u32, b: u32) -> Vertex {
+    // Always interpolate from the lower corner, so the voxels sharing an edge agree exactly
+    let low = corners[min(a, b)];
+    let high = corners[max(a, b)];
+    let delta = high.data.x - low.data.x;
+    var mu = 0.5;
+    if (abs(delta) > EPSILON) {
+        mu = clamp((ISO_LEVEL - low.data.x) / delta, 0.0, 1.0);
+    }
+    return makeVertex(mix(low.position, high.position, mu), mix(low.data.yzw, high.data.yzw, mu));
+}
+
+// Writes a triangle wound counter-clockwise when seen from outside the cloud
+fn writeTriangle(index: u32, a: Vertex, b: Vertex, c: Vertex, outward: vec3<f32>) {
+    let normal = cross(b.position.xyz - a.position.xyz, c.position.xyz - a.position.xyz);
+    vertices[index] = a;
+    if (dot(normal, outward) >= 0.0) {
+        vertices[index + 1u] = b;
+        vertices[index + 2u] = c;
+    } else {
+        vertices[index + 1u] = c;
+        vertices[index + 2u] = b;
+    }
+}
+
+@compute @workgroup_size(8, 8, 8)
+fn main(@builtin(global_invocation_id) thread_id: vec3<u32>) {
+    if (any(thread_id >= vec3(VOXELS_PER_CHUNK_DIM))) {
+        return;
+    }
+
+    for (var i = 0u; i < 8u; i++) {
+        let position = thread_id + vec3<u32>(i & 1u, (i >> 1u) & 1u, (i >> 2u) & 1u);
+        corners[i] = Corner(vec3<f32>(position), loadDensity(vec3<i32>(position)));
+    }
+
+    // Each tetrahedron walks from corner 0 to corner 7 along the axes in a different order
+    var tetrahedra = array<vec4<u32>, 6>(
+        vec4<u32>(0u, 1u, 3u, 7u),
+        vec4<u32>(0u, 1u, 5u, 7u),
+        vec4<u32>(0u, 2u, 3u, 7u),
+        vec4<u32>(0u, 2u, 6u, 7u),
+        vec4<u32>(0u, 4u, 5u, 7u),
+        vec4<u32>(0u, 4u, 6u, 7u),
+    );
+
+    var triangle_count = 0u;
+    for (var t = 0u; t < 6u; t++) {
+        triangle_count += triangleCount(tetrahedra[t]);
+    }
+    if (triangle_count == 0u) {
+        return;
+    }
+    var vertex_idx = atomicAdd(&draw_command.vertex_count, triangle_count * 3u);
+
+    for (var t = 0u; t < 6u; t++) {
+        let tetrahedron = tetrahedra[t];
+        let mask = insideMask(tetrahedron);
+
+        // Sort the corners so the inside ones come first
+        var sorted: array<u32, 4>;
+        var inside_count = 0u;
+        var outward = vec3(0.0);
+        for (var i = 0u; i < 4u; i++) {
+            if (((mask >> i) & 1u) == 1u) {
+                sorted[inside_count] = tetrahedron[i];
+                inside_count++;
+                outward -= corners[tetrahedron[i]].position;
+            }
+        }
+        var outside_count = inside_count;
+        for (var i = 0u; i < 4u; i++) {
+            if (((mask >> i) & 1u) == 0u) {
+                sorted[outside_count] = tetrahedron[i];
+                outside_count++;
+                outward += corners[tetrahedron[i]].position;
+            }
+        }
+
+        switch inside_count {
+            case 1u: {
+                writeTriangle(
+                    vertex_idx,
+                    edgeVertex(sorted[0], sorted[1]),
+                    edgeVertex(sorted[0], sorted[2]),
+                    edgeVertex(sorted[0], sorted[3]),
+                    outward);
+                vertex_idx += 3u;
+            }
+            case 3u: {
+                writeTriangle(
+                    vertex_idx,
+                    edgeVertex(sorted[3], sorted[0]),
+                    edgeVertex(sorted[3], sorted[1]),
+                    edgeVertex(sorted[3], sorted[2]),
+                    outward);
+                vertex_idx += 3u;
+            }
+            case 2u: {
+                // The crossed edges form a quad, in this order around its boundary
+                let ac = edgeVertex(sorted[0], sorted[2]);
+                let ad = edgeVertex(sorted[0], sorted[3]);
+                let bd = edgeVertex(sorted[1], sorted[3]);
+                let bc = edgeVertex(sorted[1], sorted[2]);
+                writeTriangle(vertex_idx, ac, ad, bd, outward);
+                writeTriangle(vertex_idx + 3u, ac, bd, bc, outward);
+                vertex_idx += 6u;
+            }
+            default: {}
+        }
+    }
+}
@@ -0,0 +1,40 @@
+// The lit mesh on its own, for drawing the clouds into another renderer's pass, see clouds.rs.
+// No shadows, fog or second target, and the color is left in linear HDR for the caller to tone map.
+
+#include "common.wgsl"
+#include "surface_shading.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(0) @binding(1)
+var<uniform> lighting: LightingUniform;
+
+struct VertexInput {
+    @location(0) position: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    let chunk_offset = vec3<f32>(chunkCoord() * VOXELS_PER_CHUNK_DIM);
+
+    out.world_position = voxelToWorld(in.position.xyz + chunk_offset);
+    out.position = camera.view_proj * vec4(out.world_position, 1.0);
+    out.normal = in.normal.xyz;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = surfaceNormal(in.normal);
+    let to_eye = normalize(camera.eye.xyz - in.world_position);
+    return vec4(shadeSurface(normal, to_eye, 1.0, lighting), 1.0);
+}
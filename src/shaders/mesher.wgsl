@@ -0,0 +1,34 @@
+// Bindings and helpers shared by the meshers.
+// Each mesher reads the density texture and appends triangles to the vertex buffer,
+// bumping the vertex count of the indirect draw call as it goes.
+
+struct IndirectDrawCommand {
+    vertex_count: atomic<u32>,
+    instance_count: u32,
+    first_vertex: u32,
+    first_instance: u32
+};
+
+@group(0) @binding(0)
+var density: texture_storage_3d<rgba16float, read>;
+
+@group(0) @binding(1)
+var<storage, read_write> draw_command: IndirectDrawCommand;
+
+@group(0) @binding(2)
+var<storage, read_write> vertices: array<Vertex>;
+
+// Corners can be read from -1 through VOXELS_PER_CHUNK_DIM, see densityTexel
+fn loadDensity(corner: vec3<i32>) -> vec4<f32> {
+    return textureLoad(density, densityTexel(chunkCoord(), corner));
+}
+
+fn makeVertex(position: vec3<f32>, normal: vec3<f32>) -> Vertex {
+    var vert = Vertex();
+    vert.position = vec4(position, 1.0);
+
+    let global_xyz = position / f32(VOXELS_PER_CHUNK_DIM) + vec3<f32>(chunkCoord());
+    vert.normal = vec4(normal, (global_xyz.y / 2.0));
+
+    return vert;
+}
@@ -0,0 +1,105 @@
+#include "common.wgsl"
+
+// Turns the HDR frame into the one shown on screen.
+// Bloom follows the mip chain approach from Call of Duty: Advanced Warfare: the bright parts of the frame
+// are downsampled into smaller and smaller mips, then upsampled and added back up the chain.
+// fs_tonemap adds the bloom to the frame, applies the exposure and tone maps it.
+
+struct PostUniform {
+    exposure: f32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    _padding: f32,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+
+@group(0) @binding(1)
+var source_sampler: sampler;
+
+@group(0) @binding(2)
+var<uniform> post: PostUniform;
+
+// Only read by fs_tonemap
+@group(0) @binding(3)
+var bloom: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// A single triangle covering the screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+// The source, offset by a number of its texels
+fn tap(uv: vec2<f32>, texel: vec2<f32>, x: f32, y: f32) -> vec3<f32> {
+    return textureSampleLevel(source, source_sampler, uv + vec2(x, y) * texel, 0.0).rgb;
+}
+
+// 13 bilinear taps over 4x4 texels of the source, weighted to avoid flickering on small bright spots
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    let center = tap(uv, texel, 0.0, 0.0);
+    let inner = tap(uv, texel, -1.0, -1.0) + tap(uv, texel, 1.0, -1.0)
+        + tap(uv, texel, -1.0, 1.0) + tap(uv, texel, 1.0, 1.0);
+    let corners = tap(uv, texel, -2.0, -2.0) + tap(uv, texel, 2.0, -2.0)
+        + tap(uv, texel, -2.0, 2.0) + tap(uv, texel, 2.0, 2.0);
+    let edges = tap(uv, texel, 0.0, -2.0) + tap(uv, texel, -2.0, 0.0)
+        + tap(uv, texel, 2.0, 0.0) + tap(uv, texel, 0.0, 2.0);
+    return center * 0.125 + inner * 0.125 + corners * 0.03125 + edges * 0.0625;
+}
+
+// Keeps only what's brighter than the threshold, with a soft knee so the glow doesn't pop in
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = downsample(in.uv) * post.exposure;
+    let brightness = max(color.r, max(color.g, color.b));
+    let knee = post.bloom_threshold * 0.5;
+    let soft = clamp(brightness - post.bloom_threshold + knee, 0.0, 2.0 * knee);
+    let contribution = max(soft * soft / (4.0 * knee + 0.0001), brightness - post.bloom_threshold);
+    return vec4(color * contribution / max(brightness, 0.0001), 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(downsample(in.uv), 1.0);
+}
+
+// A 3x3 tent filter, added to the larger mip with blending
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    let sum = tap(in.uv, texel, 0.0, 0.0) * 4.0
+        + (tap(in.uv, texel, 0.0, -1.0) + tap(in.uv, texel, -1.0, 0.0)
+        + tap(in.uv, texel, 1.0, 0.0) + tap(in.uv, texel, 0.0, 1.0)) * 2.0
+        + tap(in.uv, texel, -1.0, -1.0) + tap(in.uv, texel, 1.0, -1.0)
+        + tap(in.uv, texel, -1.0, 1.0) + tap(in.uv, texel, 1.0, 1.0);
+    return vec4(sum / 16.0, 1.0);
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), vec3(0.0), vec3(1.0));
+}
+
+// The surface is sRGB, so the output stays linear
+@fragment
+fn fs_tonemap(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSampleLevel(source, source_sampler, in.uv, 0.0).rgb * post.exposure;
+    let glow = textureSampleLevel(bloom, source_sampler, in.uv, 0.0).rgb * post.bloom_intensity;
+    return vec4(aces(color + glow), 1.0);
+}
@@ -0,0 +1,13 @@
+#include "common.wgsl"
+
+// Draws the chunk meshes into one cascade of the sun's shadow map.
+// Only depth is written, so there's no fragment stage.
+
+@group(0) @binding(0)
+var<uniform> cascade_view_proj: mat4x4<f32>;
+
+@vertex
+fn vs_main(@location(0) position: vec4<f32>) -> @builtin(position) vec4<f32> {
+    let chunk_offset = vec3<f32>(chunkCoord() * VOXELS_PER_CHUNK_DIM);
+    return cascade_view_proj * vec4(voxelToWorld(position.xyz + chunk_offset), 1.0);
+}
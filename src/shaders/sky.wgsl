@@ -0,0 +1,43 @@
+#include "common.wgsl"
+#include "atmosphere.wgsl"
+
+// The sky behind the clouds, lit by the same sun.
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(0) @binding(1)
+var<uniform> lighting: LightingUniform;
+
+// Angular radius of the sun's disk, larger than the real one so it reads on screen
+const SUN_DISK_RADIUS: f32 = 0.02;
+const SUN_DISK_INTENSITY: f32 = 40.0;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// A single triangle covering the screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.ndc = uv * 2.0 - 1.0;
+    out.position = vec4(out.ndc, 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let far = camera.inverse_view_proj * vec4(in.ndc, 1.0, 1.0);
+    let direction = normalize(far.xyz / far.w - camera.eye.xyz);
+    let sun_direction = lighting.sun_direction.xyz;
+
+    var color = skyRadiance(direction, sun_direction, lighting.sun_color.rgb);
+    // A soft edged disk, hidden below the horizon
+    let sun_angle = acos(clamp(dot(direction, sun_direction), -1.0, 1.0));
+    let disk = 1.0 - smoothstep(SUN_DISK_RADIUS * 0.8, SUN_DISK_RADIUS, sun_angle);
+    color += lighting.sun_color.rgb * SUN_DISK_INTENSITY * disk * step(0.0, direction.y);
+    return vec4(color, 1.0);
+}
@@ -0,0 +1,124 @@
+#include "common.wgsl"
+
+// Screen space ambient occlusion for the mesh.
+// fs_ssao checks how much of the hemisphere above each pixel's surface is buried under the depth buffer,
+// fs_composite blurs away its noise and darkens the frame with it.
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Bound as a float texture since the GL backend can't load from depth textures
+@group(0) @binding(1)
+var scene_depth: texture_2d<f32>;
+
+// World space normals of the mesh, w is 0 where there is no mesh
+@group(0) @binding(2)
+var scene_normals: texture_2d<f32>;
+
+// Only read by fs_composite
+@group(0) @binding(3)
+var occlusion: texture_2d<f32>;
+
+const SAMPLE_COUNT: u32 = 16u;
+// How far around each point to look for occluders, in world units
+const RADIUS: f32 = 0.6;
+// Keeps the surface from occluding itself
+const BIAS: f32 = 0.05;
+const INTENSITY: f32 = 1.5;
+// The noise repeats every NOISE_SIZE pixels, and the blur averages over exactly that many
+const NOISE_SIZE: i32 = 4;
+const GOLDEN_ANGLE: f32 = 2.39996323;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// A single triangle covering the screen
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.ndc = uv * 2.0 - 1.0;
+    out.position = vec4(out.ndc, 0.0, 1.0);
+    return out;
+}
+
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = camera.inverse_view_proj * vec4(ndc, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// Cheap noise that tiles every NOISE_SIZE pixels, used to rotate the samples
+fn noise(pixel: vec2<i32>) -> f32 {
+    let tile = vec2<f32>(pixel % NOISE_SIZE);
+    return fract(sin(dot(tile, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+@fragment
+fn fs_ssao(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.position.xy);
+    let normal = textureLoad(scene_normals, pixel, 0);
+    if (normal.w == 0.0) {
+        return vec4(1.0);
+    }
+    let depth = textureLoad(scene_depth, pixel, 0).x;
+    let position = unproject(in.ndc, depth);
+    let n = normalize(normal.xyz);
+
+    // A basis around the normal, spun by the noise
+    let angle = noise(pixel) * 2.0 * PI;
+    var helper = vec3(0.0, 1.0, 0.0);
+    if (abs(n.y) > 0.9) {
+        helper = vec3(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(helper, n));
+    let bitangent = cross(n, tangent);
+    let spin = mat2x2<f32>(cos(angle), sin(angle), -sin(angle), cos(angle));
+
+    let dimensions = vec2<f32>(textureDimensions(scene_depth));
+    var occluded = 0.0;
+    for (var i = 0u; i < SAMPLE_COUNT; i++) {
+        // Spread over the hemisphere in a spiral, packed closer to the center
+        let t = (f32(i) + 0.5) / f32(SAMPLE_COUNT);
+        let around = spin * vec2(cos(f32(i) * GOLDEN_ANGLE), sin(f32(i) * GOLDEN_ANGLE));
+        let up = sqrt(1.0 - t);
+        let offset = (tangent * around.x + bitangent * around.y) * sqrt(t) + n * up;
+        let sample_position = position + offset * RADIUS * mix(0.1, 1.0, t * t);
+
+        let clip = camera.view_proj * vec4(sample_position, 1.0);
+        let sample_ndc = clip.xy / clip.w;
+        let sample_pixel = vec2<i32>((sample_ndc * vec2(0.5, -0.5) + 0.5) * dimensions);
+        if (any(sample_pixel < vec2(0)) || any(sample_pixel >= vec2<i32>(dimensions))) {
+            continue;
+        }
+        let occluder_depth = textureLoad(scene_depth, sample_pixel, 0).x;
+        let occluder = unproject(sample_ndc, occluder_depth);
+
+        let sample_distance = distance(sample_position, camera.eye.xyz);
+        let occluder_distance = distance(occluder, camera.eye.xyz);
+        // Occluders far in front of the sample are other clouds, not a crevice
+        let in_range = smoothstep(0.0, 1.0, RADIUS / abs(occluder_distance - sample_distance));
+        if (occluder_distance < sample_distance - BIAS) {
+            occluded += in_range;
+        }
+    }
+
+    let visibility = 1.0 - occluded / f32(SAMPLE_COUNT);
+    return vec4(pow(visibility, INTENSITY));
+}
+
+// Drawn with a multiplying blend over the lit frame
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.position.xy);
+    let dimensions = vec2<i32>(textureDimensions(occlusion));
+    var sum = 0.0;
+    for (var y = 0; y < NOISE_SIZE; y++) {
+        for (var x = 0; x < NOISE_SIZE; x++) {
+            let offset = vec2(x, y) - NOISE_SIZE / 2;
+            sum += textureLoad(occlusion, clamp(pixel + offset, vec2(0), dimensions - 1), 0).x;
+        }
+    }
+    return vec4(vec3(sum / f32(NOISE_SIZE * NOISE_SIZE)), 1.0);
+}
@@ -0,0 +1,149 @@
+#include "common.wgsl"
+#include "mesher.wgsl"
+
+// Naive surface nets: every cell the surface passes through gets a single vertex,
+// and every edge the surface crosses becomes a quad joining the vertices of the four cells around it.
+// Vertices are shared between neighbouring quads, so the mesh has no slivers and about half the
+// triangles of marching cubes.
+//
+// The dual contouring entry point additionally moves each vertex towards the point that best fits
+// the tangent planes given by the stored gradients, which keeps sharp features sharp.
+
+const DUAL_CONTOURING_ITERATIONS: u32 = 8u;
+const EPSILON: f32 = 0.000001;
+
+fn cornerOffset(corner: u32) -> vec3<i32> {
+    return vec3<i32>(i32(corner & 1u), i32((corner >> 1u) & 1u), i32((corner >> 2u) & 1u));
+}
+
+fn axisOffset(axis: u32) -> vec3<i32> {
+    return cornerOffset(1u << axis);
+}
+
+struct CellVertex {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+}
+
+fn cellVertex(cell: vec3<i32>, refine: bool) -> CellVertex {
+    var samples = array<vec4<f32>, 8>();
+    for (var corner = 0u; corner < 8u; corner++) {
+        samples[corner] = loadDensity(cell + cornerOffset(corner));
+    }
+
+    // Where the surface crosses each of the cell's 12 edges, relative to the cell's low corner
+    var crossings = array<vec3<f32>, 12>();
+    var crossing_normals = array<vec3<f32>, 12>();
+    var crossing_count = 0u;
+    var mass_point = vec3(0.0);
+    var normal = vec3(0.0);
+    for (var a = 0u; a < 8u; a++) {
+        for (var axis = 0u; axis < 3u; axis++) {
+            if (((a >> axis) & 1u) == 1u) {
+                continue;
+            }
+            let b = a | (1u << axis);
+            let va = samples[a].x;
+            let vb = samples[b].x;
+            if ((va > ISO_LEVEL) == (vb > ISO_LEVEL)) {
+                continue;
+            }
+            let mu = clamp((ISO_LEVEL - va) / (vb - va), 0.0, 1.0);
+            let point = mix(vec3<f32>(cornerOffset(a)), vec3<f32>(cornerOffset(b)), mu);
+            let point_normal = mix(samples[a].yzw, samples[b].yzw, mu);
+            crossings[crossing_count] = point;
+            crossing_normals[crossing_count] = point_normal;
+            crossing_count++;
+            mass_point += point;
+            normal += point_normal;
+        }
+    }
+
+    var position = mass_point / f32(max(crossing_count, 1u));
+    if (refine) {
+        // Minimize the distance to the crossings' tangent planes with a few steps of gradient descent,
+        // starting from the mass point. Cheaper than solving the QEF exactly and stable on flat regions.
+        for (var i = 0u; i < DUAL_CONTOURING_ITERATIONS; i++) {
+            var force = vec3(0.0);
+            for (var c = 0u; c < crossing_count; c++) {
+                let n = crossing_normals[c] / max(length(crossing_normals[c]), EPSILON);
+                force -= n * dot(n, position - crossings[c]);
+            }
+            position = clamp(position + force / f32(max(crossing_count, 1u)), vec3(0.0), vec3(1.0));
+        }
+    }
+
+    var out: CellVertex;
+    out.position = vec3<f32>(cell) + position;
+    out.normal = normal / f32(max(crossing_count, 1u));
+    return out;
+}
+
+fn mesh(corner: vec3<u32>, refine: bool) {
+    if (any(corner >= vec3(VOXELS_PER_CHUNK_DIM))) {
+        return;
+    }
+    let q = vec3<i32>(corner);
+    let inside = loadDensity(q).x > ISO_LEVEL;
+
+    // Each thread owns the three edges leaving its corner in the positive direction.
+    // The cells around an edge can reach into the padding of the density texture,
+    // so the quads on the low faces of the chunk meet the neighbouring chunk's without a seam.
+    var crossed = array<bool, 3>();
+    var quad_count = 0u;
+    for (var axis = 0u; axis < 3u; axis++) {
+        crossed[axis] = (loadDensity(q + axisOffset(axis)).x > ISO_LEVEL) != inside;
+        quad_count += u32(crossed[axis]);
+    }
+    if (quad_count == 0u) {
+        return;
+    }
+
+    var vertex_idx = atomicAdd(&draw_command.vertex_count, quad_count * 6u);
+    for (var axis = 0u; axis < 3u; axis++) {
+        if (!crossed[axis]) {
+            continue;
+        }
+        let b = axisOffset((axis + 1u) % 3u);
+        let c = axisOffset((axis + 2u) % 3u);
+        // Counter-clockwise around the edge when looking down it
+        let v0 = cellVertex(q - b - c, refine);
+        let v1 = cellVertex(q - c, refine);
+        let v2 = cellVertex(q, refine);
+        let v3 = cellVertex(q - b, refine);
+        var quad = array<Vertex, 4>(
+            makeVertex(v0.position, v0.normal),
+            makeVertex(v1.position, v1.normal),
+            makeVertex(v2.position, v2.normal),
+            makeVertex(v3.position, v3.normal),
+        );
+
+        // Face away from the inside of the cloud
+        if (inside) {
+            vertices[vertex_idx + 0u] = quad[0];
+            vertices[vertex_idx + 1u] = quad[1];
+            vertices[vertex_idx + 2u] = quad[2];
+            vertices[vertex_idx + 3u] = quad[0];
+            vertices[vertex_idx + 4u] = quad[2];
+            vertices[vertex_idx + 5u] = quad[3];
+        } else {
+            vertices[vertex_idx + 0u] = quad[0];
+            vertices[vertex_idx + 1u] = quad[2];
+            vertices[vertex_idx + 2u] = quad[1];
+            vertices[vertex_idx + 3u] = quad[0];
+            vertices[vertex_idx + 4u] = quad[3];
+            vertices[vertex_idx + 5u] = quad[2];
+        }
+        vertex_idx += 6u;
+    }
+}
+
+@compute @workgroup_size(8, 8, 8)
+fn surface_nets(@builtin(global_invocation_id) thread_id: vec3<u32>) {
+    mesh(thread_id, false);
+}
+
+@compute @workgroup_size(8, 8, 8)
+fn dual_contouring(@builtin(global_invocation_id) thread_id: vec3<u32>) {
+    mesh(thread_id, true);
+}
@@ -0,0 +1,37 @@
+// Lighting of the cloud mesh, shared by chunk_render.wgsl and mesh_render.wgsl.
+// Needs LightingUniform from common.wgsl.
+
+// Bends the light seen through the cloud toward the surface normal
+const TRANSLUCENCY_DISTORTION: f32 = 0.2;
+const TRANSLUCENCY_POWER: f32 = 4.0;
+
+// The density gradient points into the cloud.
+// Interpolated gradients can cancel out, which would make the lighting NaN and smear it around with the bloom.
+fn surfaceNormal(gradient: vec3<f32>) -> vec3<f32> {
+    let gradient_length = length(gradient);
+    if (gradient_length > 0.0001) {
+        return -gradient / gradient_length;
+    }
+    return vec3(0.0, 1.0, 0.0);
+}
+
+// Wrapped diffuse, translucency and rim light, before any fog.
+// sun_visibility goes from 0 in full shadow to 1.
+fn shadeSurface(
+    normal: vec3<f32>,
+    to_eye: vec3<f32>,
+    sun_visibility: f32,
+    lighting: LightingUniform
+) -> vec3<f32> {
+    let to_sun = lighting.sun_direction.xyz;
+    let n_dot_l = dot(normal, to_sun);
+
+    let diffuse = max((n_dot_l + lighting.wrap) / (1.0 + lighting.wrap), 0.0);
+    let ambient = mix(lighting.ground_color.rgb, lighting.sky_color.rgb, normal.y * 0.5 + 0.5);
+    let through = -normalize(to_sun + normal * TRANSLUCENCY_DISTORTION);
+    let translucency = pow(max(dot(to_eye, through), 0.0), TRANSLUCENCY_POWER) * lighting.translucency;
+    let rim = pow(1.0 - max(dot(normal, to_eye), 0.0), lighting.rim_power) * lighting.rim_strength;
+
+    let sun = lighting.sun_color.rgb * sun_visibility;
+    return lighting.albedo.rgb * (sun * (diffuse + translucency) + ambient) + sun * rim;
+}
@@ -0,0 +1,435 @@
+use glm::{Mat4, Vec3, Vec4};
+use wgpu::{
+    BindGroup, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
+    PipelineLayout, PushConstantRange, RenderPipeline, ShaderModule, ShaderStages,
+};
+
+use crate::{
+    chunks::{Chunks, CLOUD_VERTEX_BUFFER_LAYOUT, PUSH_CONSTANTS_SIZE},
+    profiler::{GpuPass, Profiler},
+    shader::{self, Shader},
+    texture,
+};
+
+/// Each cascade covers a slice of the view frustum, the first ones are smaller and sharper.
+pub const CASCADE_COUNT: usize = 3;
+const SHADOW_MAP_SIZE: u32 = 2048;
+/// Nothing further than this from the camera is shadowed.
+const SHADOW_DISTANCE: f32 = 40.0;
+/// Blends between evenly spaced (0) and logarithmic (1) cascade splits.
+const SPLIT_LAMBDA: f32 = 0.6;
+/// How far toward the sun each cascade reaches past its slice, to catch the clouds casting shadows into it.
+const CASTER_MARGIN: f32 = 32.0;
+/// Dynamic uniform offsets need to be aligned to this, 256 is the largest alignment wgpu allows.
+const CASCADE_STRIDE: u64 = 256;
+
+/// A directional shadow map for the sun, split into cascades along the camera's view.
+pub struct ShadowMap {
+    // Kept alive for its views
+    _texture: wgpu::Texture,
+    /// Every cascade, for sampling them in the lit pass.
+    view: wgpu::TextureView,
+    /// One per cascade, for rendering into them.
+    cascade_views: Vec<wgpu::TextureView>,
+    sampler: wgpu::Sampler,
+    /// All the cascades' matrices, for the lit pass.
+    uniform_buffer: wgpu::Buffer,
+    /// One cascade's matrix every CASCADE_STRIDE bytes, picked with a dynamic offset.
+    cascade_buffer: wgpu::Buffer,
+    pipeline_layout: PipelineLayout,
+    pipeline: RenderPipeline,
+    cascade_bind_group: BindGroup,
+}
+
+impl ShadowMap {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow_map_texture"),
+            size: wgpu::Extent3d {
+                width: SHADOW_MAP_SIZE,
+                height: SHADOW_MAP_SIZE,
+                depth_or_array_layers: CASCADE_COUNT as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: texture::Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("shadow_map_view"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let cascade_views = (0..CASCADE_COUNT as u32)
+            .map(|cascade| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("shadow_map_cascade_view"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: cascade,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow_map_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("shadow_uniform_buffer"),
+            size: std::mem::size_of::<ShadowUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let cascade_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("shadow_cascade_buffer"),
+            size: CASCADE_STRIDE * CASCADE_COUNT as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("shadow_bind_group_layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    // Picks the cascade
+                    has_dynamic_offset: true,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("shadow_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[PushConstantRange {
+                stages: ShaderStages::VERTEX,
+                range: 0..PUSH_CONSTANTS_SIZE,
+            }],
+        });
+        let pipeline = create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader::create_shader_module(device, Shader::ShadowMap),
+        );
+        let cascade_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &cascade_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<[[f32; 4]; 4]>() as u64),
+                }),
+            }],
+        });
+
+        Self {
+            _texture: texture,
+            view,
+            cascade_views,
+            sampler,
+            uniform_buffer,
+            cascade_buffer,
+            pipeline_layout,
+            pipeline,
+            cascade_bind_group,
+        }
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device, source: &str) {
+        if let Some(pipeline) =
+            shader::try_create_pipeline(device, Shader::ShadowMap, source, |module| {
+                create_pipeline(device, &self.pipeline_layout, module)
+            })
+        {
+            self.pipeline = pipeline;
+        }
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.sampler
+    }
+
+    pub fn uniform_binding_resource(&self) -> BindingResource<'_> {
+        self.uniform_buffer.as_entire_binding()
+    }
+
+    fn cascade_offset(cascade: usize) -> u32 {
+        (cascade as u64 * CASCADE_STRIDE) as u32
+    }
+
+    /// Fits the cascades around what the camera sees.
+    pub fn write_data_buffers(
+        &self,
+        queue: &wgpu::Queue,
+        inverse_view_proj: &Mat4,
+        znear: f32,
+        zfar: f32,
+        sun_direction: &Vec3,
+    ) {
+        let view_proj = fit_cascades(
+            inverse_view_proj,
+            znear,
+            zfar,
+            SHADOW_DISTANCE.min(zfar),
+            sun_direction,
+        );
+        for (cascade, view_proj) in view_proj.iter().enumerate() {
+            queue.write_buffer(
+                &self.cascade_buffer,
+                Self::cascade_offset(cascade) as u64,
+                bytemuck::cast_slice(view_proj.as_slice()),
+            );
+        }
+        let data = ShadowUniform {
+            view_proj: view_proj.map(Into::into),
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[data]));
+    }
+
+    /// Draws the chunks' depth from the sun into every cascade.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        profiler: &Profiler,
+        chunks: &Chunks,
+        world_time: f32,
+    ) {
+        for cascade in 0..CASCADE_COUNT {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("shadow_pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.cascade_views[cascade],
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: profiler.render_pass(GpuPass::Shadows),
+                ..Default::default()
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(
+                0,
+                &self.cascade_bind_group,
+                &[Self::cascade_offset(cascade)],
+            );
+            chunks.draw(&mut render_pass, world_time);
+        }
+    }
+}
+
+/// Depth only, drawn from the sun.
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &PipelineLayout,
+    module: &ShaderModule,
+) -> RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("shadow_pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vs_main",
+            buffers: &[CLOUD_VERTEX_BUFFER_LAYOUT],
+        },
+        fragment: None,
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: Default::default(),
+            // Pushes the depth back a little, most of all on surfaces at a grazing angle to the sun,
+            // so they don't shadow themselves
+            bias: wgpu::DepthBiasState {
+                constant: 2,
+                slope_scale: 2.0,
+                clamp: 0.0,
+            },
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+/// View distances splitting the frustum between the cascades, from `near` to `far`.
+fn split_distances(near: f32, far: f32) -> [f32; CASCADE_COUNT + 1] {
+    std::array::from_fn(|i| {
+        let t = i as f32 / CASCADE_COUNT as f32;
+        let logarithmic = near * (far / near).powf(t);
+        let even = near + (far - near) * t;
+        SPLIT_LAMBDA * logarithmic + (1.0 - SPLIT_LAMBDA) * even
+    })
+}
+
+/// The corners of the part of the view frustum between the view distances `near` and `far`.
+/// The camera uses an OpenGL style projection, so its near and far planes sit at -1 and 1.
+fn frustum_slice_corners(
+    inverse_view_proj: &Mat4,
+    znear: f32,
+    zfar: f32,
+    near: f32,
+    far: f32,
+) -> [Vec3; 8] {
+    let unproject = |x: f32, y: f32, z: f32| {
+        let world = inverse_view_proj * Vec4::new(x, y, z, 1.0);
+        world.xyz() / world.w
+    };
+    std::array::from_fn(|i| {
+        let x = if i & 1 == 0 { -1.0 } else { 1.0 };
+        let y = if i & 2 == 0 { -1.0 } else { 1.0 };
+        let distance = if i & 4 == 0 { near } else { far };
+        // Points along a ray from the eye move linearly with their view distance
+        let on_near_plane = unproject(x, y, -1.0);
+        let on_far_plane = unproject(x, y, 1.0);
+        let t = (distance - znear) / (zfar - znear);
+        on_near_plane + (on_far_plane - on_near_plane) * t
+    })
+}
+
+/// A view projection matrix from the sun for each cascade, covering the view up to `shadow_distance`.
+fn fit_cascades(
+    inverse_view_proj: &Mat4,
+    znear: f32,
+    zfar: f32,
+    shadow_distance: f32,
+    sun_direction: &Vec3,
+) -> [Mat4; CASCADE_COUNT] {
+    let sun_direction = sun_direction.normalize();
+    let up = if sun_direction.y.abs() > 0.99 {
+        Vec3::x()
+    } else {
+        Vec3::y()
+    };
+    let splits = split_distances(znear, shadow_distance);
+
+    std::array::from_fn(|cascade| {
+        let corners = frustum_slice_corners(
+            inverse_view_proj,
+            znear,
+            zfar,
+            splits[cascade],
+            splits[cascade + 1],
+        );
+        // A bounding sphere keeps the cascade the same size as the camera turns, so it doesn't shimmer
+        let center = corners.iter().sum::<Vec3>() / 8.0;
+        let radius = corners
+            .iter()
+            .map(|corner| (corner - center).norm())
+            .fold(0.0, f32::max);
+        let radius = (radius * 16.0).ceil() / 16.0;
+
+        let view = glm::look_at_rh(
+            &(center + sun_direction * (radius + CASTER_MARGIN)),
+            &center,
+            &up,
+        );
+        let proj = glm::ortho_rh_zo(
+            -radius,
+            radius,
+            -radius,
+            radius,
+            0.0,
+            2.0 * radius + CASTER_MARGIN,
+        );
+        let mut view_proj = proj * view;
+
+        // Only move the cascade by whole texels, so the shadow edges don't crawl as the camera moves
+        let texels_per_unit = SHADOW_MAP_SIZE as f32 / 2.0;
+        let origin = view_proj * Vec4::new(0.0, 0.0, 0.0, 1.0);
+        let snapped = (origin.xy() * texels_per_unit).map(f32::round) / texels_per_unit;
+        view_proj[(0, 3)] += snapped.x - origin.x;
+        view_proj[(1, 3)] += snapped.y - origin.y;
+        view_proj
+    })
+}
+
+// Mirrors ShadowUniform in shaders/chunk_render.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct ShadowUniform {
+    pub view_proj: [[[f32; 4]; 4]; CASCADE_COUNT],
+}
+
+shader::assert_wgsl_layout!(ShadowUniform, 64 * CASCADE_COUNT, { view_proj: 0 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_cover_the_whole_range() {
+        let splits = split_distances(0.1, 40.0);
+        assert!((splits[0] - 0.1).abs() < 1e-5);
+        assert!((splits[CASCADE_COUNT] - 40.0).abs() < 1e-3);
+        assert!(splits.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn cascades_contain_their_frustum_slices() {
+        let view = glm::look_at_rh(&Vec3::new(14.0, 4.0, 6.0), &Vec3::zeros(), &Vec3::y());
+        let proj = glm::perspective(1.0, 1.0, 0.1, 100.0);
+        let inverse_view_proj = (proj * view).try_inverse().unwrap();
+        let sun_direction = Vec3::new(0.4, 0.8, 0.6);
+
+        let cascades = fit_cascades(&inverse_view_proj, 0.1, 100.0, 40.0, &sun_direction);
+        let splits = split_distances(0.1, 40.0);
+        for (cascade, view_proj) in cascades.iter().enumerate() {
+            let corners = frustum_slice_corners(
+                &inverse_view_proj,
+                0.1,
+                100.0,
+                splits[cascade],
+                splits[cascade + 1],
+            );
+            for corner in corners {
+                let clip = view_proj * Vec4::new(corner.x, corner.y, corner.z, 1.0);
+                let ndc = clip.xyz() / clip.w;
+                assert!(
+                    ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0 && (0.0..=1.0).contains(&ndc.z),
+                    "cascade {} misses {:?}",
+                    cascade,
+                    corner
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cascades_reach_toward_the_sun() {
+        let view = glm::look_at_rh(&Vec3::new(0.0, 0.0, 10.0), &Vec3::zeros(), &Vec3::y());
+        let proj = glm::perspective(1.0, 1.0, 0.1, 100.0);
+        let inverse_view_proj = (proj * view).try_inverse().unwrap();
+        let sun_direction = Vec3::new(0.0, 1.0, 0.0);
+
+        let cascades = fit_cascades(&inverse_view_proj, 0.1, 100.0, 40.0, &sun_direction);
+        // A caster high above the last cascade's slice still lands in its depth range
+        let caster = Vec3::new(0.0, 30.0, -10.0);
+        let clip = cascades[CASCADE_COUNT - 1] * Vec4::new(caster.x, caster.y, caster.z, 1.0);
+        assert!((0.0..=1.0).contains(&clip.z));
+    }
+}
@@ -0,0 +1,92 @@
+use wgpu::{BindGroup, BindGroupLayout, LoadOp, PipelineLayout, RenderPipeline, ShaderModule};
+
+use crate::{
+    fullscreen,
+    post::HDR_FORMAT,
+    profiler::{GpuPass, Profiler},
+    shader::{self, Shader},
+};
+
+// Draws behind what's already drawn, showing through where it's transparent
+const UNDER_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::OneMinusDstAlpha,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::OneMinusDstAlpha,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+/// The sky, drawn last behind everything else, so it doesn't need to be multisampled.
+pub(crate) struct Sky {
+    pipeline_layout: PipelineLayout,
+    pipeline: RenderPipeline,
+}
+
+impl Sky {
+    /// Bound with the lit mesh's bind group, since the sky only reads the camera and lighting.
+    pub fn new(device: &wgpu::Device, lit_mesh_layout: &BindGroupLayout) -> Self {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("sky_pipeline_layout"),
+            bind_group_layouts: &[lit_mesh_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader::create_shader_module(device, Shader::Sky),
+        );
+
+        Self {
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device, source: &str) {
+        if let Some(pipeline) = shader::try_create_pipeline(device, Shader::Sky, source, |module| {
+            create_pipeline(device, &self.pipeline_layout, module)
+        }) {
+            self.pipeline = pipeline;
+        }
+    }
+
+    /// Draws behind everything already in `hdr`.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        profiler: &Profiler,
+        hdr: &wgpu::TextureView,
+        lit_mesh_bind_group: &BindGroup,
+    ) {
+        fullscreen::draw(
+            encoder,
+            "sky_pass",
+            hdr,
+            LoadOp::Load,
+            &self.pipeline,
+            lit_mesh_bind_group,
+            profiler.render_pass(GpuPass::Sky),
+        );
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &PipelineLayout,
+    module: &ShaderModule,
+) -> RenderPipeline {
+    fullscreen::create_pipeline(
+        device,
+        "sky_pipeline",
+        layout,
+        module,
+        "fs_main",
+        HDR_FORMAT,
+        Some(UNDER_BLEND),
+    )
+}
@@ -0,0 +1,256 @@
+use wgpu::{
+    BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    Color, LoadOp, PipelineLayout, RenderPipeline, ShaderModule, ShaderStages, TextureFormat,
+    TextureViewDimension,
+};
+
+use crate::{
+    camera::Camera,
+    fullscreen,
+    graphics::Graphics,
+    post::HDR_FORMAT,
+    profiler::{GpuPass, Profiler},
+    shader::{self, Shader},
+    texture,
+};
+
+const OCCLUSION_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+// Darkens what's already drawn by the color being drawn, keeping its alpha
+const MULTIPLY_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::Src,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+/// Screen space ambient occlusion, from the lit mesh's depth and normals.
+pub(crate) struct Ssao {
+    bind_group_layout: BindGroupLayout,
+    // Split from the rest since the occlusion is drawn to in the first pass
+    composite_bind_group_layout: BindGroupLayout,
+    pipeline_layout: PipelineLayout,
+    composite_pipeline_layout: PipelineLayout,
+    pipeline: RenderPipeline,
+    composite_pipeline: RenderPipeline,
+    targets: SsaoTargets,
+}
+
+/// Drawn at the surface's size, so recreated when it's resized.
+struct SsaoTargets {
+    occlusion: texture::Texture,
+    bind_group: BindGroup,
+    composite_bind_group: BindGroup,
+}
+
+impl Ssao {
+    /// Reads the camera's depth and `normal`, so both must be resized first.
+    pub fn new(gfx: &Graphics, camera: &Camera, normal: &texture::Texture) -> Self {
+        let device = gfx.device();
+        let unfilterable_texture = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("ssao_bind_group_layout"),
+            entries: &[
+                // Camera
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Depth, bound as a float texture like for the hybrid mode
+                unfilterable_texture(1),
+                // Normals
+                unfilterable_texture(2),
+            ],
+        });
+        let composite_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("ssao_composite_bind_group_layout"),
+                entries: &[unfilterable_texture(3)],
+            });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ssao_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let composite_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("ssao_composite_pipeline_layout"),
+                bind_group_layouts: &[&composite_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let (pipeline, composite_pipeline) = create_pipelines(
+            device,
+            &pipeline_layout,
+            &composite_pipeline_layout,
+            &shader::create_shader_module(device, Shader::Ssao),
+        );
+        let targets = SsaoTargets::new(
+            gfx,
+            &bind_group_layout,
+            &composite_bind_group_layout,
+            camera,
+            normal,
+        );
+
+        Self {
+            bind_group_layout,
+            composite_bind_group_layout,
+            pipeline_layout,
+            composite_pipeline_layout,
+            pipeline,
+            composite_pipeline,
+            targets,
+        }
+    }
+
+    pub fn resize(&mut self, gfx: &Graphics, camera: &Camera, normal: &texture::Texture) {
+        self.targets = SsaoTargets::new(
+            gfx,
+            &self.bind_group_layout,
+            &self.composite_bind_group_layout,
+            camera,
+            normal,
+        );
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device, source: &str) {
+        if let Some((pipeline, composite)) =
+            shader::try_create_pipeline(device, Shader::Ssao, source, |module| {
+                create_pipelines(
+                    device,
+                    &self.pipeline_layout,
+                    &self.composite_pipeline_layout,
+                    module,
+                )
+            })
+        {
+            self.pipeline = pipeline;
+            self.composite_pipeline = composite;
+        }
+    }
+
+    /// Draws the occlusion, then darkens `hdr` by it.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        profiler: &Profiler,
+        hdr: &wgpu::TextureView,
+    ) {
+        fullscreen::draw(
+            encoder,
+            "ssao_pass",
+            &self.targets.occlusion.view,
+            LoadOp::Clear(Color::WHITE),
+            &self.pipeline,
+            &self.targets.bind_group,
+            profiler.render_pass(GpuPass::AmbientOcclusion),
+        );
+        fullscreen::draw(
+            encoder,
+            "ssao_composite_pass",
+            hdr,
+            LoadOp::Load,
+            &self.composite_pipeline,
+            &self.targets.composite_bind_group,
+            profiler.render_pass(GpuPass::AmbientOcclusion),
+        );
+    }
+}
+
+impl SsaoTargets {
+    fn new(
+        gfx: &Graphics,
+        layout: &BindGroupLayout,
+        composite_layout: &BindGroupLayout,
+        camera: &Camera,
+        normal: &texture::Texture,
+    ) -> Self {
+        let occlusion = texture::Texture::create_render_target(
+            gfx.device(),
+            gfx.config(),
+            OCCLUSION_FORMAT,
+            "occlusion_target",
+        );
+        let bind_group = gfx.device().create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ssao_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera.buffer_binding_resource(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&camera.depth_texture().view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal.view),
+                },
+            ],
+        });
+        let composite_bind_group = gfx.device().create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ssao_composite_bind_group"),
+            layout: composite_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&occlusion.view),
+            }],
+        });
+
+        Self {
+            occlusion,
+            bind_group,
+            composite_bind_group,
+        }
+    }
+}
+
+/// The occlusion pipeline, then the one compositing it over the HDR target.
+fn create_pipelines(
+    device: &wgpu::Device,
+    layout: &PipelineLayout,
+    composite_layout: &PipelineLayout,
+    module: &ShaderModule,
+) -> (RenderPipeline, RenderPipeline) {
+    (
+        fullscreen::create_pipeline(
+            device,
+            "ssao_pipeline",
+            layout,
+            module,
+            "fs_ssao",
+            OCCLUSION_FORMAT,
+            None,
+        ),
+        fullscreen::create_pipeline(
+            device,
+            "ssao_composite_pipeline",
+            composite_layout,
+            module,
+            "fs_composite",
+            HDR_FORMAT,
+            Some(MULTIPLY_BLEND),
+        ),
+    )
+}
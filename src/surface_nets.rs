@@ -0,0 +1,169 @@
+//! A CPU port of `surface_nets.wgsl`, both the naive surface nets and the dual contouring entry
+//! points, to check that the surfaces they build are closed and that the shader writes the same
+//! triangles.
+
+use std::array;
+
+const DUAL_CONTOURING_ITERATIONS: u32 = 8;
+const EPSILON: f32 = 0.000001;
+
+fn corner_offset(corner: u32) -> [i32; 3] {
+    [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1].map(|c| c as i32)
+}
+
+fn axis_offset(axis: u32) -> [i32; 3] {
+    corner_offset(1 << axis)
+}
+
+fn add(a: [i32; 3], b: [i32; 3]) -> [i32; 3] {
+    array::from_fn(|axis| a[axis] + b[axis])
+}
+
+fn sub(a: [i32; 3], b: [i32; 3]) -> [i32; 3] {
+    array::from_fn(|axis| a[axis] - b[axis])
+}
+
+fn mix(a: [f32; 3], b: [f32; 3], mu: f32) -> [f32; 3] {
+    array::from_fn(|axis| a[axis] + (b[axis] - a[axis]) * mu)
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// The cell's vertex, from where the surface crosses its 12 edges.
+fn cell_vertex(
+    cell: [i32; 3],
+    iso_level: f32,
+    refine: bool,
+    density: &impl Fn([i32; 3]) -> [f32; 4],
+) -> [f32; 3] {
+    let samples: [[f32; 4]; 8] =
+        array::from_fn(|corner| density(add(cell, corner_offset(corner as u32))));
+
+    let mut crossings = Vec::new();
+    let mut mass_point = [0.0; 3];
+    for a in 0..8 {
+        for axis in 0..3 {
+            if (a >> axis) & 1 == 1 {
+                continue;
+            }
+            let b = a | (1 << axis);
+            let (va, vb) = (samples[a as usize][0], samples[b as usize][0]);
+            if (va > iso_level) == (vb > iso_level) {
+                continue;
+            }
+            let mu = ((iso_level - va) / (vb - va)).clamp(0.0, 1.0);
+            let point = mix(
+                corner_offset(a).map(|c| c as f32),
+                corner_offset(b).map(|c| c as f32),
+                mu,
+            );
+            let [_, na @ ..] = samples[a as usize];
+            let [_, nb @ ..] = samples[b as usize];
+            crossings.push((point, mix(na, nb, mu)));
+            mass_point = array::from_fn(|axis| mass_point[axis] + point[axis]);
+        }
+    }
+
+    let count = crossings.len().max(1) as f32;
+    let mut position = mass_point.map(|c| c / count);
+    if refine {
+        // A few steps of gradient descent toward the crossings' tangent planes
+        for _ in 0..DUAL_CONTOURING_ITERATIONS {
+            let mut force = [0.0; 3];
+            for &(point, normal) in &crossings {
+                let length = dot(normal, normal).sqrt().max(EPSILON);
+                let n = normal.map(|c| c / length);
+                let distance = dot(n, array::from_fn(|axis| position[axis] - point[axis]));
+                force = array::from_fn(|axis| force[axis] - n[axis] * distance);
+            }
+            position =
+                array::from_fn(|axis| (position[axis] + force[axis] / count).clamp(0.0, 1.0));
+        }
+    }
+    array::from_fn(|axis| cell[axis] as f32 + position[axis])
+}
+
+/// Meshes `voxels_per_dim^3` corners the way the shader does, with dual contouring when `refine`
+/// is set. `density` holds the density and its gradient, like the density texture, and is
+/// sampled from -1 through `voxels_per_dim` on each axis.
+/// Returns a triangle list of positions in voxel units.
+pub fn mesh(
+    voxels_per_dim: u32,
+    iso_level: f32,
+    refine: bool,
+    density: impl Fn([i32; 3]) -> [f32; 4],
+) -> Vec<[f32; 3]> {
+    let mut vertices = Vec::new();
+    let dim = voxels_per_dim as i32;
+    for z in 0..dim {
+        for y in 0..dim {
+            for x in 0..dim {
+                let q = [x, y, z];
+                let inside = density(q)[0] > iso_level;
+                // Each corner owns the three edges leaving it in the positive direction
+                for axis in 0..3 {
+                    if (density(add(q, axis_offset(axis)))[0] > iso_level) == inside {
+                        continue;
+                    }
+                    let b = axis_offset((axis + 1) % 3);
+                    let c = axis_offset((axis + 2) % 3);
+                    let [v0, v1, v2, v3] = [sub(sub(q, b), c), sub(q, c), q, sub(q, b)]
+                        .map(|cell| cell_vertex(cell, iso_level, refine, &density));
+                    // Face away from the inside of the cloud
+                    if inside {
+                        vertices.extend([v0, v1, v2, v0, v2, v3]);
+                    } else {
+                        vertices.extend([v0, v2, v1, v0, v3, v2]);
+                    }
+                }
+            }
+        }
+    }
+    vertices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marching_cubes::tests::{
+        assert_closed, assert_on_sphere, sphere_density, SPHERE_DIM,
+    };
+
+    /// The sphere's density, with its gradient like the density pass stores it.
+    fn sphere(corner: [i32; 3]) -> [f32; 4] {
+        let point = corner.map(|c| c as f32);
+        let gradient: [f32; 3] = array::from_fn(|axis| {
+            let mut step = point;
+            step[axis] += 0.5;
+            let mut back = point;
+            back[axis] -= 0.5;
+            sphere_density(step) - sphere_density(back)
+        });
+        [sphere_density(point), gradient[0], gradient[1], gradient[2]]
+    }
+
+    #[test]
+    fn empty_and_full_grids_have_no_triangles() {
+        for refine in [false, true] {
+            assert!(mesh(4, 0.5, refine, |_| [0.0; 4]).is_empty());
+            assert!(mesh(4, 0.5, refine, |_| [1.0, 0.0, 0.0, 0.0]).is_empty());
+        }
+    }
+
+    #[test]
+    fn surface_nets_sphere_is_closed_and_near_the_surface() {
+        let vertices = mesh(SPHERE_DIM, 0.5, false, sphere);
+        assert_closed(&vertices);
+        // The mass point of the crossings sits a little inside of a curved surface
+        assert_on_sphere(&vertices, 0.25);
+    }
+
+    #[test]
+    fn dual_contouring_sphere_is_closed_and_near_the_surface() {
+        let vertices = mesh(SPHERE_DIM, 0.5, true, sphere);
+        assert_closed(&vertices);
+        assert_on_sphere(&vertices, 0.1);
+    }
+}
@@ -0,0 +1,240 @@
+use wgpu::{
+    BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    Color, LoadOp, PipelineLayout, RenderPipeline, ShaderModule, ShaderStages,
+    TextureViewDimension,
+};
+
+use crate::{
+    camera::Camera,
+    fullscreen,
+    post::HDR_FORMAT,
+    profiler::{GpuPass, Profiler},
+    shader::{self, Shader},
+};
+
+/// Raymarches the density texture, through the whole world or just in front of the mesh.
+pub(crate) struct Volume {
+    bind_group_layout: BindGroupLayout,
+    density_sampler: wgpu::Sampler,
+    pipeline_layout: PipelineLayout,
+    volume_pipeline: RenderPipeline,
+    hybrid_pipeline: RenderPipeline,
+    // Reads the camera's depth, so recreated when it's resized
+    bind_group: BindGroup,
+}
+
+impl Volume {
+    pub fn new(
+        device: &wgpu::Device,
+        camera: &Camera,
+        density: &wgpu::TextureView,
+        lighting_buffer: &wgpu::Buffer,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("volume_bind_group_layout"),
+            entries: &[
+                // Camera
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Density data
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                // Mesh depth, for the hybrid mode.
+                // Bound as a float texture since the GL backend can't load from depth textures.
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // Lighting
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("volume_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let (volume_pipeline, hybrid_pipeline) = create_pipelines(
+            device,
+            &pipeline_layout,
+            &shader::create_shader_module(device, Shader::CloudVolume),
+        );
+        let density_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("density_sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = create_bind_group(
+            device,
+            &bind_group_layout,
+            camera,
+            density,
+            &density_sampler,
+            lighting_buffer,
+        );
+
+        Self {
+            bind_group_layout,
+            density_sampler,
+            pipeline_layout,
+            volume_pipeline,
+            hybrid_pipeline,
+            bind_group,
+        }
+    }
+
+    /// Binds the resized camera's depth.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        camera: &Camera,
+        density: &wgpu::TextureView,
+        lighting_buffer: &wgpu::Buffer,
+    ) {
+        self.bind_group = create_bind_group(
+            device,
+            &self.bind_group_layout,
+            camera,
+            density,
+            &self.density_sampler,
+            lighting_buffer,
+        );
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device, source: &str) {
+        if let Some((volume, hybrid)) =
+            shader::try_create_pipeline(device, Shader::CloudVolume, source, |module| {
+                create_pipelines(device, &self.pipeline_layout, module)
+            })
+        {
+            self.volume_pipeline = volume;
+            self.hybrid_pipeline = hybrid;
+        }
+    }
+
+    /// Draws the clouds into `hdr`, only in front of the mesh's depth when `hybrid` is set.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        profiler: &Profiler,
+        hdr: &wgpu::TextureView,
+        hybrid: bool,
+    ) {
+        fullscreen::draw(
+            encoder,
+            "cloud_volume_pass",
+            hdr,
+            // Left transparent for the sky to show through
+            LoadOp::Clear(Color::TRANSPARENT),
+            if hybrid {
+                &self.hybrid_pipeline
+            } else {
+                &self.volume_pipeline
+            },
+            &self.bind_group,
+            profiler.render_pass(GpuPass::Volume),
+        );
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &BindGroupLayout,
+    camera: &Camera,
+    density: &wgpu::TextureView,
+    density_sampler: &wgpu::Sampler,
+    lighting_buffer: &wgpu::Buffer,
+) -> BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("volume_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera.buffer_binding_resource(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(density),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(density_sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&camera.depth_texture().view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: lighting_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+/// The volume pipeline, then the hybrid one.
+fn create_pipelines(
+    device: &wgpu::Device,
+    layout: &PipelineLayout,
+    module: &ShaderModule,
+) -> (RenderPipeline, RenderPipeline) {
+    (
+        fullscreen::create_pipeline(
+            device,
+            "cloud_volume_pipeline",
+            layout,
+            module,
+            "fs_main",
+            HDR_FORMAT,
+            Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+        ),
+        fullscreen::create_pipeline(
+            device,
+            "cloud_hybrid_pipeline",
+            layout,
+            module,
+            "fs_hybrid",
+            HDR_FORMAT,
+            Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+        ),
+    )
+}
@@ -7,9 +7,12 @@ use winit::{
     window::WindowBuilder,
 };
 
-use crate::{cloud_world::CloudWorld, graphics::Graphics};
+use crate::{
+    capture::Screenshots, cloud_world::CloudWorld, graphics::Graphics, lighting::Lighting,
+    overlay::Overlay, post::PostProcessing,
+};
 
-pub async fn run() -> Result<()> {
+pub async fn run(lighting: Lighting, post_processing: PostProcessing) -> Result<()> {
     let event_loop = EventLoop::new()?;
     let window = WindowBuilder::new()
         .with_title("silky clouds")
@@ -17,7 +20,9 @@ pub async fn run() -> Result<()> {
     let _ = window.request_inner_size(PhysicalSize::new(1200, 1200));
 
     let mut gfx = Graphics::new(window).await;
-    let mut cloud_world = CloudWorld::new(&gfx);
+    let mut cloud_world = CloudWorld::new(&gfx, lighting, post_processing);
+    let mut overlay = Overlay::new(&gfx);
+    let mut screenshots = Screenshots::default();
 
     event_loop.run(move |event, window_target| match event {
         Event::AboutToWait => {
@@ -26,36 +31,59 @@ pub async fn run() -> Result<()> {
         Event::WindowEvent {
             ref event,
             window_id,
-        } if window_id == gfx.window().id() => match event {
-            WindowEvent::CloseRequested
-            | WindowEvent::KeyboardInput {
-                event:
-                    KeyEvent {
-                        physical_key: PhysicalKey::Code(KeyCode::Escape),
-                        ..
-                    },
-                ..
-            } => window_target.exit(),
-            WindowEvent::KeyboardInput { .. } | WindowEvent::MouseInput { .. } => {
-                // TODO: Input?
-            }
-            WindowEvent::Resized(physical_size) => {
-                gfx.resize(*physical_size);
-            }
-            WindowEvent::RedrawRequested => {
-                cloud_world.update();
-                match cloud_world.render(&gfx) {
-                    Ok(_) => {}
-                    // Reconfigure the surface if lost
-                    Err(wgpu::SurfaceError::Lost) => gfx.resize(*gfx.size()),
-                    // The system is out of memory, we should probably quit
-                    Err(wgpu::SurfaceError::OutOfMemory) => window_target.exit(),
-                    // All other errors (Outdated, Timeout) should be resolved by the next frame
-                    Err(e) => eprintln!("{:?}", e),
+        } if window_id == gfx.window().id() && !overlay.handle_event(gfx.window(), event) => {
+            match event {
+                WindowEvent::CloseRequested
+                | WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            physical_key: PhysicalKey::Code(KeyCode::Escape),
+                            ..
+                        },
+                    ..
+                } => window_target.exit(),
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            physical_key: PhysicalKey::Code(key_code),
+                            state: ElementState::Pressed,
+                            repeat: false,
+                            ..
+                        },
+                    ..
+                } => match key_code {
+                    KeyCode::F1 => overlay.toggle(),
+                    KeyCode::KeyP => screenshots.request(),
+                    _ => cloud_world.handle_key(*key_code),
+                },
+                WindowEvent::Resized(physical_size) => {
+                    gfx.resize(*physical_size);
+                    cloud_world.resize(&gfx);
                 }
+                WindowEvent::RedrawRequested => {
+                    cloud_world.reload_shaders(&gfx);
+                    screenshots.poll(&gfx);
+                    cloud_world.update();
+                    overlay.run(gfx.window(), |ctx| cloud_world.tuning_window(ctx));
+                    let rendered = cloud_world.render(&gfx, |encoder, frame, view| {
+                        // Taken before the overlay is drawn over the clouds
+                        screenshots.copy(&gfx, encoder, frame);
+                        overlay.paint(&gfx, encoder, view);
+                    });
+                    screenshots.end_frame();
+                    match rendered {
+                        Ok(_) => {}
+                        // Reconfigure the surface if lost
+                        Err(wgpu::SurfaceError::Lost) => gfx.resize(*gfx.size()),
+                        // The system is out of memory, we should probably quit
+                        Err(wgpu::SurfaceError::OutOfMemory) => window_target.exit(),
+                        // All other errors (Outdated, Timeout) should be resolved by the next frame
+                        Err(e) => eprintln!("{:?}", e),
+                    }
+                }
+                _ => {}
             }
-            _ => {}
-        },
+        }
         _ => {}
     })?;
 